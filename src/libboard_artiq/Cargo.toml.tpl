@@ -25,6 +25,7 @@ nb = "1.0"
 void = { version = "1", default-features = false }
 
 io = { path = "../libio", features = ["byteorder"] }
+clock_solver = { path = "../libclock_solver" }
 libboard_zynq = { path = "@@ZYNQ_RS@@/libboard_zynq" }
 libsupport_zynq = { path = "@@ZYNQ_RS@@/libsupport_zynq", default-features = false, features = ["alloc_core"] }
 libregister = { path = "@@ZYNQ_RS@@/libregister" }
@@ -2,10 +2,14 @@ use embedded_hal::prelude::_embedded_hal_blocking_delay_DelayUs;
 use libboard_zynq::timer::GlobalTimer;
 use libconfig::Config;
 use libsupport_zynq::alloc::format;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 
 use crate::pl;
 
+// A lane is considered aligned when the phase detector low rate at its
+// assigned tap is within this distance from the 50% crossover.
+const ALIGN_TOLERANCE: f32 = 0.3;
+
 struct SerdesConfig {
     pub delay: [u8; 4],
 }
@@ -19,6 +23,29 @@ impl SerdesConfig {
             )
         }
     }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<SerdesConfig> {
+        // IDELAYE2 has 32 taps
+        if bytes.len() != core::mem::size_of::<SerdesConfig>() || bytes.iter().any(|&tap| tap >= 32) {
+            return None;
+        }
+        let mut delay = [0; 4];
+        delay.copy_from_slice(bytes);
+        Some(SerdesConfig { delay: delay })
+    }
+}
+
+/// Per-lane delay taps of a transceiver, and the phase detector low rate
+/// measured at each of them (0.5 being ideal).
+pub struct LaneStatus {
+    pub delay: [u8; 4],
+    pub score: [f32; 4],
+}
+
+fn select_transceiver(trx_no: u8) {
+    unsafe {
+        pl::csr::eem_transceiver::transceiver_sel_write(trx_no);
+    }
 }
 
 fn select_lane(lane_no: u8) {
@@ -36,6 +63,26 @@ fn apply_delay(tap: u8, timer: &mut GlobalTimer) {
     }
 }
 
+// Applies the configuration lane by lane, measuring the low rate at each tap.
+fn score_config(config: &SerdesConfig, timer: &mut GlobalTimer) -> [f32; 4] {
+    let mut score = [0.0; 4];
+    for lane_no in 0..4 {
+        select_lane(lane_no as u8);
+        score[lane_no] = read_align(config.delay[lane_no], timer);
+    }
+    score
+}
+
+// Reads back the delay taps currently applied to each lane.
+fn current_config() -> SerdesConfig {
+    let mut delay = [0; 4];
+    for lane_no in 0..4 {
+        select_lane(lane_no as u8);
+        delay[lane_no] = unsafe { pl::csr::eem_transceiver::dly_cnt_out_read() };
+    }
+    SerdesConfig { delay: delay }
+}
+
 fn apply_config(config: &SerdesConfig, timer: &mut GlobalTimer) {
     for lane_no in 0..4 {
         select_lane(lane_no as u8);
@@ -43,56 +90,56 @@ fn apply_config(config: &SerdesConfig, timer: &mut GlobalTimer) {
     }
 }
 
-unsafe fn assign_delay(timer: &mut GlobalTimer) -> SerdesConfig {
+fn deviation(low_rate: f32) -> f32 {
+    // abs() from f32 is not available in core library
+    if low_rate < 0.5 {
+        0.5 - low_rate
+    } else {
+        low_rate - 0.5
+    }
+}
+
+fn is_aligned(score: &[f32; 4]) -> bool {
+    score.iter().all(|&low_rate| deviation(low_rate) <= ALIGN_TOLERANCE)
+}
+
+unsafe fn assign_delay(timer: &mut GlobalTimer) -> Result<SerdesConfig, &'static str> {
     // Select an appropriate delay for lane 0
     select_lane(0);
 
-    //
-
     let mut best_dly = None;
-
-    loop {
-        let mut prev = None;
-        for curr_dly in 0..32 {
-            //let read_align = read_align_fn(curr_dly, timer);
-            let curr_low_rate = read_align(curr_dly, timer);
-
-            if let Some(prev_low_rate) = prev {
-                // This is potentially a crossover position
-                if prev_low_rate <= curr_low_rate && curr_low_rate >= 0.5 {
-                    let prev_dev = 0.5 - prev_low_rate;
-                    let curr_dev = curr_low_rate - 0.5;
-                    let selected_idx = if prev_dev < curr_dev { curr_dly - 1 } else { curr_dly };
-
-                    // The setup setup/hold calibration timing (even with
-                    // tolerance) might be invalid in other lanes due to skew.
-                    // 5 taps is very conservative, generally it is 1 or 2
-                    if selected_idx < 5 {
-                        prev = None;
-                        continue;
-                    } else {
-                        best_dly = Some(selected_idx);
-                        break;
-                    }
+    let mut prev = None;
+    for curr_dly in 0..32 {
+        let curr_low_rate = read_align(curr_dly, timer);
+
+        if let Some(prev_low_rate) = prev {
+            // This is potentially a crossover position
+            if prev_low_rate <= curr_low_rate && curr_low_rate >= 0.5 {
+                let prev_dev = 0.5 - prev_low_rate;
+                let curr_dev = curr_low_rate - 0.5;
+                let selected_idx = if prev_dev < curr_dev { curr_dly - 1 } else { curr_dly };
+
+                // The setup setup/hold calibration timing (even with
+                // tolerance) might be invalid in other lanes due to skew.
+                // 5 taps is very conservative, generally it is 1 or 2
+                if selected_idx < 5 {
+                    prev = None;
+                    continue;
+                } else {
+                    best_dly = Some(selected_idx);
+                    break;
                 }
             }
-
-            // Only rising slope from <= 0.5 can result in a rising low rate
-            // crossover at 50%.
-            if curr_low_rate <= 0.5 {
-                prev = Some(curr_low_rate);
-            }
         }
 
-        if best_dly.is_none() {
-            error!("setup/hold timing calibration failed, retry in 1s...");
-            timer.delay_us(1_000_000);
-        } else {
-            break;
+        // Only rising slope from <= 0.5 can result in a rising low rate
+        // crossover at 50%.
+        if curr_low_rate <= 0.5 {
+            prev = Some(curr_low_rate);
         }
     }
 
-    let best_dly = best_dly.unwrap();
+    let best_dly = best_dly.ok_or("setup/hold timing calibration failed")?;
 
     apply_delay(best_dly, timer);
     let mut delay_list = [best_dly; 4];
@@ -105,9 +152,7 @@ unsafe fn assign_delay(timer: &mut GlobalTimer) -> SerdesConfig {
         let mut min_idx = 0;
         for dly_delta in -3..=3 {
             let index = (best_dly as isize + dly_delta) as u8;
-            let low_rate = read_align(index, timer);
-            // abs() from f32 is not available in core library
-            let deviation = if low_rate < 0.5 { 0.5 - low_rate } else { low_rate - 0.5 };
+            let deviation = deviation(read_align(index, timer));
 
             if deviation < min_deviation {
                 min_deviation = deviation;
@@ -121,7 +166,7 @@ unsafe fn assign_delay(timer: &mut GlobalTimer) -> SerdesConfig {
 
     debug!("setup/hold timing calibration: {:?}", delay_list);
 
-    SerdesConfig { delay: delay_list }
+    Ok(SerdesConfig { delay: delay_list })
 }
 
 fn read_align(dly: u8, timer: &mut GlobalTimer) -> f32 {
@@ -145,84 +190,151 @@ fn read_align(dly: u8, timer: &mut GlobalTimer) -> f32 {
     }
 }
 
-unsafe fn align_comma(timer: &mut GlobalTimer) {
-    loop {
-        for slip in 1..=10 {
-            // The soft transceiver has 2 8b10b decoders, which receives lane
-            // 0/1 and lane 2/3 respectively. The decoder are time-multiplexed
-            // to decode exactly 1 lane each sysclk cycle.
-            //
-            // The decoder decodes lane 0/2 data on odd sysclk cycles, buffer
-            // on even cycles, and vice versa for lane 1/3. Data/Clock latency
-            // could change timing. The extend bit flips the decoding timing,
-            // so lane 0/2 data are decoded on even cycles, and lane 1/3 data
-            // are decoded on odd cycles.
-            //
-            // This is needed because transmitting/receiving a 8b10b character
-            // takes 2 sysclk cycles. Adjusting bitslip only via ISERDES
-            // limits the range to 1 cycle. The wordslip bit extends the range
-            // to 2 sysclk cycles.
-            pl::csr::eem_transceiver::wordslip_write((slip > 5) as u8);
-
-            // Apply a double bitslip since the ISERDES is 2x oversampled.
-            // Bitslip is used for comma alignment purposes once setup/hold
-            // timing is met.
-            pl::csr::eem_transceiver::bitslip_write(1);
-            pl::csr::eem_transceiver::bitslip_write(1);
-            timer.delay_us(1);
-
-            pl::csr::eem_transceiver::comma_align_reset_write(1);
-            timer.delay_us(100);
-
-            if pl::csr::eem_transceiver::comma_read() == 1 {
-                debug!("comma alignment completed after {} bitslips", slip);
-                return;
-            }
-        }
+unsafe fn align_comma(timer: &mut GlobalTimer) -> Result<(), &'static str> {
+    for slip in 1..=10 {
+        // The soft transceiver has 2 8b10b decoders, which receives lane
+        // 0/1 and lane 2/3 respectively. The decoder are time-multiplexed
+        // to decode exactly 1 lane each sysclk cycle.
+        //
+        // The decoder decodes lane 0/2 data on odd sysclk cycles, buffer
+        // on even cycles, and vice versa for lane 1/3. Data/Clock latency
+        // could change timing. The extend bit flips the decoding timing,
+        // so lane 0/2 data are decoded on even cycles, and lane 1/3 data
+        // are decoded on odd cycles.
+        //
+        // This is needed because transmitting/receiving a 8b10b character
+        // takes 2 sysclk cycles. Adjusting bitslip only via ISERDES
+        // limits the range to 1 cycle. The wordslip bit extends the range
+        // to 2 sysclk cycles.
+        pl::csr::eem_transceiver::wordslip_write((slip > 5) as u8);
+
+        // Apply a double bitslip since the ISERDES is 2x oversampled.
+        // Bitslip is used for comma alignment purposes once setup/hold
+        // timing is met.
+        pl::csr::eem_transceiver::bitslip_write(1);
+        pl::csr::eem_transceiver::bitslip_write(1);
+        timer.delay_us(1);
 
-        error!("comma alignment failed, retrying in 1s...");
-        timer.delay_us(1_000_000);
+        pl::csr::eem_transceiver::comma_align_reset_write(1);
+        timer.delay_us(100);
+
+        if pl::csr::eem_transceiver::comma_read() == 1 {
+            debug!("comma alignment completed after {} bitslips", slip);
+            return Ok(());
+        }
     }
+    Err("comma alignment failed")
+}
+
+fn store_config(cfg: &Config, key: &str, config: &SerdesConfig) {
+    match cfg.write(key, config.as_bytes().to_vec()) {
+        Ok(()) => {
+            info!("storing calibration timing values into sd card");
+        }
+        Err(e) => {
+            error!(
+                "calibration successful but calibration timing values cannot be stored into sd card. \
+                 Error:{}",
+                e
+            );
+        }
+    };
+}
+
+fn calibrate(timer: &mut GlobalTimer, cfg: &Config, key: &str) {
+    info!("calibrating...");
+    let config = loop {
+        match unsafe { assign_delay(timer) } {
+            Ok(config) => break config,
+            Err(e) => {
+                error!("{}, retry in 1s...", e);
+                timer.delay_us(1_000_000);
+            }
+        }
+    };
+    store_config(cfg, key, &config);
 }
 
 pub fn init(timer: &mut GlobalTimer, cfg: &Config) {
     for trx_no in 0..pl::csr::CONFIG_EEM_DRTIO_COUNT {
-        unsafe {
-            pl::csr::eem_transceiver::transceiver_sel_write(trx_no as u8);
-        }
+        select_transceiver(trx_no as u8);
 
         let key = format!("eem_drtio_delay{}", trx_no);
 
-        let cfg_read = cfg.read(&key);
-        match cfg_read {
+        let stored = match cfg.read(&key) {
             Ok(record) => {
-                info!("loading calibrated timing values from sd card");
-                unsafe {
-                    apply_config(&*(record.as_ptr() as *const SerdesConfig), timer);
+                let config = SerdesConfig::from_bytes(&record);
+                if config.is_none() {
+                    warn!("ignoring malformed {}", key);
                 }
+                config
             }
-            Err(_) => {
-                info!("calibrating...");
-                let config = unsafe { assign_delay(timer) };
-
-                match cfg.write(&key, config.as_bytes().to_vec()) {
-                    Ok(()) => {
-                        info!("storing calibration timing values into sd card");
-                    }
-                    Err(e) => {
-                        error!(
-                            "calibration successful but calibration timing values cannot be stored into sd card. \
-                             Error:{}",
-                            e
-                        );
-                    }
-                };
+            Err(_) => None,
+        };
+        match stored {
+            Some(config) => {
+                info!("loading calibrated timing values from sd card");
+                let score = score_config(&config, timer);
+                if is_aligned(&score) {
+                    debug!("stored timing values are aligned: {:?}", score);
+                } else {
+                    warn!("stored timing values are no longer aligned: {:?}", score);
+                    calibrate(timer, cfg, &key);
+                }
             }
+            None => calibrate(timer, cfg, &key),
         }
 
         unsafe {
-            align_comma(timer);
+            while let Err(e) = align_comma(timer) {
+                error!("{}, retrying in 1s...", e);
+                timer.delay_us(1_000_000);
+            }
             pl::csr::eem_transceiver::rx_ready_write(1);
         }
     }
 }
+
+/// Forces the setup/hold timing calibration of a transceiver, replacing the
+/// stored timing values. The link goes down until comma alignment completes.
+/// On failure, the previous timing values are restored and nothing is stored.
+pub fn recalibrate(trx_no: u8, timer: &mut GlobalTimer, cfg: &Config) -> Result<LaneStatus, &'static str> {
+    if trx_no as usize >= pl::csr::CONFIG_EEM_DRTIO_COUNT as usize {
+        return Err("no such EEM transceiver");
+    }
+    select_transceiver(trx_no);
+    unsafe {
+        pl::csr::eem_transceiver::rx_ready_write(0);
+    }
+
+    info!("recalibrating EEM transceiver {}", trx_no);
+    let previous = current_config();
+    let result = unsafe { assign_delay(timer) }.and_then(|config| {
+        let score = score_config(&config, timer);
+        unsafe { align_comma(timer) }?;
+        Ok((config, score))
+    });
+    let (config, score) = match result {
+        Ok(calibrated) => calibrated,
+        Err(e) => {
+            warn!("recalibration failed, restoring previous timing values");
+            apply_config(&previous, timer);
+            if let Err(e) = unsafe { align_comma(timer) } {
+                error!("{} with the previous timing values", e);
+            }
+            unsafe {
+                pl::csr::eem_transceiver::rx_ready_write(1);
+            }
+            return Err(e);
+        }
+    };
+    store_config(cfg, &format!("eem_drtio_delay{}", trx_no), &config);
+
+    unsafe {
+        pl::csr::eem_transceiver::rx_ready_write(1);
+    }
+    Ok(LaneStatus {
+        delay: config.delay,
+        score: score,
+    })
+}
@@ -1,4 +1,4 @@
-use core::fmt;
+use core::{cmp, fmt, str};
 
 use libconfig::Config;
 use log::{info, warn};
@@ -75,6 +75,66 @@ pub fn config_routing_table(default_n_links: usize, cfg: &Config) -> RoutingTabl
     ret
 }
 
+/// Link bring-up retry policy, shared by master links and satellite repeaters
+#[derive(Clone, Copy)]
+pub struct RetryPolicy {
+    // echo requests sent per bring-up attempt
+    pub ping_count: u16,
+    // delay before retrying after the first failure, doubled on each subsequent one
+    pub backoff_min: u64,
+    pub backoff_max: u64,
+    // consecutive failed bring-ups before giving up on the link, 0 for no limit
+    pub max_retries: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            ping_count: 100,
+            backoff_min: 1000,
+            backoff_max: 60000,
+            max_retries: 0,
+        }
+    }
+}
+
+impl RetryPolicy {
+    // delay in ms before the next attempt, after `failures` consecutive failures
+    pub fn backoff(&self, failures: u32) -> u64 {
+        let shift = cmp::min(failures.saturating_sub(1), 16);
+        cmp::min(self.backoff_min << shift, self.backoff_max)
+    }
+
+    pub fn exhausted(&self, failures: u32) -> bool {
+        self.max_retries != 0 && failures >= self.max_retries
+    }
+}
+
+fn config_value<T: str::FromStr>(cfg: &Config, key: &str, default: T) -> T {
+    match cfg.read_str(key) {
+        Ok(value) => value.parse().unwrap_or_else(|_| {
+            warn!("{} value is invalid, using default", key);
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+pub fn config_retry_policy(cfg: &Config, default_ping_count: u16) -> RetryPolicy {
+    let default = RetryPolicy::default();
+    let policy = RetryPolicy {
+        ping_count: config_value(cfg, "drtio_ping_count", default_ping_count),
+        backoff_min: config_value(cfg, "drtio_backoff_min", default.backoff_min),
+        backoff_max: config_value(cfg, "drtio_backoff_max", default.backoff_max),
+        max_retries: config_value(cfg, "drtio_max_retries", default.max_retries),
+    };
+    info!(
+        "DRTIO link retry policy: {} pings, backoff {}..{} ms, {} retries max",
+        policy.ping_count, policy.backoff_min, policy.backoff_max, policy.max_retries
+    );
+    policy
+}
+
 #[cfg(has_drtio_routing)]
 pub fn interconnect_enable(routing_table: &RoutingTable, rank: u8, destination: u8) {
     let hop = routing_table.0[destination as usize][rank as usize];
@@ -92,6 +92,53 @@ pub enum Packet {
         rank: u8,
     },
     RoutingAck,
+    RepeaterStatsRequest {
+        destination: u8,
+        repno: u8,
+    },
+    RepeaterStatsReply {
+        succeeded: bool,
+        up: bool,
+        forwarded_packets: u32,
+        forward_timeouts: u32,
+        rtio_errors: u32,
+        tsc_sync_attempts: u32,
+    },
+    RepeaterSetEnabledRequest {
+        destination: u8,
+        repno: u8,
+        enabled: bool,
+    },
+    RepeaterSetEnabledReply {
+        succeeded: bool,
+    },
+    WrpllCalibrateRequest {
+        destination: u8,
+    },
+    WrpllCalibrateReply {
+        succeeded: bool,
+    },
+    GrabberStatusRequest {
+        destination: u8,
+        grabber: u8,
+    },
+    GrabberStatusReply {
+        count: u8,
+        state: u8,
+        video_clock: u32,
+        frame_width: u16,
+        frame_height: u16,
+        lock_loss_count: u32,
+    },
+    DestinationEchoRequest {
+        source: u8,
+        destination: u8,
+        seqno: u16,
+    },
+    DestinationEchoReply {
+        destination: u8,
+        seqno: u16,
+    },
 
     MonitorRequest {
         destination: u8,
@@ -239,6 +286,28 @@ pub enum Packet {
         channel: u32,
         timestamp: u64,
     },
+    DmaPatchRequest {
+        source: u8,
+        destination: u8,
+        id: u32,
+        target: u32,
+        timestamp: u64,
+        word: u8,
+        value: u32,
+    },
+    DmaPatchReply {
+        destination: u8,
+        succeeded: bool,
+    },
+    DmaPatchRevertRequest {
+        source: u8,
+        destination: u8,
+        id: u32,
+    },
+    DmaPatchRevertReply {
+        destination: u8,
+        succeeded: bool,
+    },
 
     SubkernelAddDataRequest {
         destination: u8,
@@ -250,6 +319,16 @@ pub enum Packet {
     SubkernelAddDataReply {
         succeeded: bool,
     },
+    SubkernelCacheQuery {
+        destination: u8,
+        id: u32,
+        hash: u32,
+        length: u32,
+        bind: bool,
+    },
+    SubkernelCacheReply {
+        present: bool,
+    },
     SubkernelLoadRunRequest {
         source: u8,
         destination: u8,
@@ -286,8 +365,40 @@ pub enum Packet {
         data: [u8; MASTER_PAYLOAD_MAX_SIZE],
     },
     SubkernelMessageAck {
+        source: u8,
+        destination: u8,
+    },
+    SubkernelStopRequest {
+        source: u8,
+        destination: u8,
+        id: u32,
+    },
+    SubkernelStopReply {
+        destination: u8,
+        succeeded: bool,
+    },
+
+    FirmwareAddDataRequest {
+        destination: u8,
+        status: PayloadStatus,
+        length: u16,
+        data: [u8; MASTER_PAYLOAD_MAX_SIZE],
+    },
+    FirmwareAddDataReply {
+        succeeded: bool,
+    },
+    FirmwareCommitRequest {
         destination: u8,
+        length: u32,
+        checksum: u32,
     },
+    FirmwareCommitReply {
+        succeeded: bool,
+    },
+    RebootRequest {
+        destination: u8,
+    },
+    RebootAck,
 }
 
 impl Packet {
@@ -328,6 +439,53 @@ impl Packet {
                 rank: reader.read_u8()?,
             },
             0x32 => Packet::RoutingAck,
+            0x33 => Packet::RepeaterStatsRequest {
+                destination: reader.read_u8()?,
+                repno: reader.read_u8()?,
+            },
+            0x34 => Packet::RepeaterStatsReply {
+                succeeded: reader.read_bool()?,
+                up: reader.read_bool()?,
+                forwarded_packets: reader.read_u32()?,
+                forward_timeouts: reader.read_u32()?,
+                rtio_errors: reader.read_u32()?,
+                tsc_sync_attempts: reader.read_u32()?,
+            },
+            0x35 => Packet::RepeaterSetEnabledRequest {
+                destination: reader.read_u8()?,
+                repno: reader.read_u8()?,
+                enabled: reader.read_bool()?,
+            },
+            0x36 => Packet::RepeaterSetEnabledReply {
+                succeeded: reader.read_bool()?,
+            },
+            0x37 => Packet::WrpllCalibrateRequest {
+                destination: reader.read_u8()?,
+            },
+            0x38 => Packet::WrpllCalibrateReply {
+                succeeded: reader.read_bool()?,
+            },
+            0x39 => Packet::GrabberStatusRequest {
+                destination: reader.read_u8()?,
+                grabber: reader.read_u8()?,
+            },
+            0x3a => Packet::GrabberStatusReply {
+                count: reader.read_u8()?,
+                state: reader.read_u8()?,
+                video_clock: reader.read_u32()?,
+                frame_width: reader.read_u16()?,
+                frame_height: reader.read_u16()?,
+                lock_loss_count: reader.read_u32()?,
+            },
+            0x3b => Packet::DestinationEchoRequest {
+                source: reader.read_u8()?,
+                destination: reader.read_u8()?,
+                seqno: reader.read_u16()?,
+            },
+            0x3c => Packet::DestinationEchoReply {
+                destination: reader.read_u8()?,
+                seqno: reader.read_u16()?,
+            },
 
             0x40 => Packet::MonitorRequest {
                 destination: reader.read_u8()?,
@@ -491,6 +649,28 @@ impl Packet {
                 channel: reader.read_u32()?,
                 timestamp: reader.read_u64()?,
             },
+            0xb7 => Packet::DmaPatchRequest {
+                source: reader.read_u8()?,
+                destination: reader.read_u8()?,
+                id: reader.read_u32()?,
+                target: reader.read_u32()?,
+                timestamp: reader.read_u64()?,
+                word: reader.read_u8()?,
+                value: reader.read_u32()?,
+            },
+            0xb8 => Packet::DmaPatchReply {
+                destination: reader.read_u8()?,
+                succeeded: reader.read_bool()?,
+            },
+            0xb9 => Packet::DmaPatchRevertRequest {
+                source: reader.read_u8()?,
+                destination: reader.read_u8()?,
+                id: reader.read_u32()?,
+            },
+            0xba => Packet::DmaPatchRevertReply {
+                destination: reader.read_u8()?,
+                succeeded: reader.read_bool()?,
+            },
 
             0xc0 => {
                 let destination = reader.read_u8()?;
@@ -510,6 +690,16 @@ impl Packet {
             0xc1 => Packet::SubkernelAddDataReply {
                 succeeded: reader.read_bool()?,
             },
+            0xc2 => Packet::SubkernelCacheQuery {
+                destination: reader.read_u8()?,
+                id: reader.read_u32()?,
+                hash: reader.read_u32()?,
+                length: reader.read_u32()?,
+                bind: reader.read_bool()?,
+            },
+            0xc3 => Packet::SubkernelCacheReply {
+                present: reader.read_bool()?,
+            },
             0xc4 => Packet::SubkernelLoadRunRequest {
                 source: reader.read_u8()?,
                 destination: reader.read_u8()?,
@@ -562,9 +752,48 @@ impl Packet {
                 }
             }
             0xcc => Packet::SubkernelMessageAck {
+                source: reader.read_u8()?,
+                destination: reader.read_u8()?,
+            },
+            0xcd => Packet::SubkernelStopRequest {
+                source: reader.read_u8()?,
+                destination: reader.read_u8()?,
+                id: reader.read_u32()?,
+            },
+            0xce => Packet::SubkernelStopReply {
                 destination: reader.read_u8()?,
+                succeeded: reader.read_bool()?,
             },
 
+            0xd0 => {
+                let destination = reader.read_u8()?;
+                let status = reader.read_u8()?;
+                let length = reader.read_u16()?;
+                let mut data: [u8; MASTER_PAYLOAD_MAX_SIZE] = [0; MASTER_PAYLOAD_MAX_SIZE];
+                reader.read_exact(&mut data[0..length as usize])?;
+                Packet::FirmwareAddDataRequest {
+                    destination: destination,
+                    status: PayloadStatus::from(status),
+                    length: length,
+                    data: data,
+                }
+            }
+            0xd1 => Packet::FirmwareAddDataReply {
+                succeeded: reader.read_bool()?,
+            },
+            0xd2 => Packet::FirmwareCommitRequest {
+                destination: reader.read_u8()?,
+                length: reader.read_u32()?,
+                checksum: reader.read_u32()?,
+            },
+            0xd3 => Packet::FirmwareCommitReply {
+                succeeded: reader.read_bool()?,
+            },
+            0xd4 => Packet::RebootRequest {
+                destination: reader.read_u8()?,
+            },
+            0xd5 => Packet::RebootAck,
+
             ty => return Err(Error::UnknownPacket(ty)),
         })
     }
@@ -607,6 +836,85 @@ impl Packet {
                 writer.write_u8(rank)?;
             }
             Packet::RoutingAck => writer.write_u8(0x32)?,
+            Packet::RepeaterStatsRequest { destination, repno } => {
+                writer.write_u8(0x33)?;
+                writer.write_u8(destination)?;
+                writer.write_u8(repno)?;
+            }
+            Packet::RepeaterStatsReply {
+                succeeded,
+                up,
+                forwarded_packets,
+                forward_timeouts,
+                rtio_errors,
+                tsc_sync_attempts,
+            } => {
+                writer.write_u8(0x34)?;
+                writer.write_bool(succeeded)?;
+                writer.write_bool(up)?;
+                writer.write_u32(forwarded_packets)?;
+                writer.write_u32(forward_timeouts)?;
+                writer.write_u32(rtio_errors)?;
+                writer.write_u32(tsc_sync_attempts)?;
+            }
+            Packet::RepeaterSetEnabledRequest {
+                destination,
+                repno,
+                enabled,
+            } => {
+                writer.write_u8(0x35)?;
+                writer.write_u8(destination)?;
+                writer.write_u8(repno)?;
+                writer.write_bool(enabled)?;
+            }
+            Packet::RepeaterSetEnabledReply { succeeded } => {
+                writer.write_u8(0x36)?;
+                writer.write_bool(succeeded)?;
+            }
+            Packet::WrpllCalibrateRequest { destination } => {
+                writer.write_u8(0x37)?;
+                writer.write_u8(destination)?;
+            }
+            Packet::WrpllCalibrateReply { succeeded } => {
+                writer.write_u8(0x38)?;
+                writer.write_bool(succeeded)?;
+            }
+            Packet::GrabberStatusRequest { destination, grabber } => {
+                writer.write_u8(0x39)?;
+                writer.write_u8(destination)?;
+                writer.write_u8(grabber)?;
+            }
+            Packet::GrabberStatusReply {
+                count,
+                state,
+                video_clock,
+                frame_width,
+                frame_height,
+                lock_loss_count,
+            } => {
+                writer.write_u8(0x3a)?;
+                writer.write_u8(count)?;
+                writer.write_u8(state)?;
+                writer.write_u32(video_clock)?;
+                writer.write_u16(frame_width)?;
+                writer.write_u16(frame_height)?;
+                writer.write_u32(lock_loss_count)?;
+            }
+            Packet::DestinationEchoRequest {
+                source,
+                destination,
+                seqno,
+            } => {
+                writer.write_u8(0x3b)?;
+                writer.write_u8(source)?;
+                writer.write_u8(destination)?;
+                writer.write_u16(seqno)?;
+            }
+            Packet::DestinationEchoReply { destination, seqno } => {
+                writer.write_u8(0x3c)?;
+                writer.write_u8(destination)?;
+                writer.write_u16(seqno)?;
+            }
 
             Packet::MonitorRequest {
                 destination,
@@ -855,6 +1163,44 @@ impl Packet {
                 writer.write_u32(channel)?;
                 writer.write_u64(timestamp)?;
             }
+            Packet::DmaPatchRequest {
+                source,
+                destination,
+                id,
+                target,
+                timestamp,
+                word,
+                value,
+            } => {
+                writer.write_u8(0xb7)?;
+                writer.write_u8(source)?;
+                writer.write_u8(destination)?;
+                writer.write_u32(id)?;
+                writer.write_u32(target)?;
+                writer.write_u64(timestamp)?;
+                writer.write_u8(word)?;
+                writer.write_u32(value)?;
+            }
+            Packet::DmaPatchReply { destination, succeeded } => {
+                writer.write_u8(0xb8)?;
+                writer.write_u8(destination)?;
+                writer.write_bool(succeeded)?;
+            }
+            Packet::DmaPatchRevertRequest {
+                source,
+                destination,
+                id,
+            } => {
+                writer.write_u8(0xb9)?;
+                writer.write_u8(source)?;
+                writer.write_u8(destination)?;
+                writer.write_u32(id)?;
+            }
+            Packet::DmaPatchRevertReply { destination, succeeded } => {
+                writer.write_u8(0xba)?;
+                writer.write_u8(destination)?;
+                writer.write_bool(succeeded)?;
+            }
 
             Packet::SubkernelAddDataRequest {
                 destination,
@@ -874,6 +1220,24 @@ impl Packet {
                 writer.write_u8(0xc1)?;
                 writer.write_bool(succeeded)?;
             }
+            Packet::SubkernelCacheQuery {
+                destination,
+                id,
+                hash,
+                length,
+                bind,
+            } => {
+                writer.write_u8(0xc2)?;
+                writer.write_u8(destination)?;
+                writer.write_u32(id)?;
+                writer.write_u32(hash)?;
+                writer.write_u32(length)?;
+                writer.write_bool(bind)?;
+            }
+            Packet::SubkernelCacheReply { present } => {
+                writer.write_u8(0xc3)?;
+                writer.write_bool(present)?;
+            }
             Packet::SubkernelLoadRunRequest {
                 source,
                 destination,
@@ -938,10 +1302,62 @@ impl Packet {
                 writer.write_u16(length)?;
                 writer.write_all(&data[0..length as usize])?;
             }
-            Packet::SubkernelMessageAck { destination } => {
+            Packet::SubkernelMessageAck { source, destination } => {
                 writer.write_u8(0xcc)?;
+                writer.write_u8(source)?;
+                writer.write_u8(destination)?;
+            }
+            Packet::SubkernelStopRequest {
+                source,
+                destination,
+                id,
+            } => {
+                writer.write_u8(0xcd)?;
+                writer.write_u8(source)?;
+                writer.write_u8(destination)?;
+                writer.write_u32(id)?;
+            }
+            Packet::SubkernelStopReply { destination, succeeded } => {
+                writer.write_u8(0xce)?;
+                writer.write_u8(destination)?;
+                writer.write_bool(succeeded)?;
+            }
+
+            Packet::FirmwareAddDataRequest {
+                destination,
+                status,
+                length,
+                data,
+            } => {
+                writer.write_u8(0xd0)?;
+                writer.write_u8(destination)?;
+                writer.write_u8(status as u8)?;
+                writer.write_u16(length)?;
+                writer.write_all(&data[0..length as usize])?;
+            }
+            Packet::FirmwareAddDataReply { succeeded } => {
+                writer.write_u8(0xd1)?;
+                writer.write_bool(succeeded)?;
+            }
+            Packet::FirmwareCommitRequest {
+                destination,
+                length,
+                checksum,
+            } => {
+                writer.write_u8(0xd2)?;
+                writer.write_u8(destination)?;
+                writer.write_u32(length)?;
+                writer.write_u32(checksum)?;
+            }
+            Packet::FirmwareCommitReply { succeeded } => {
+                writer.write_u8(0xd3)?;
+                writer.write_bool(succeeded)?;
+            }
+            Packet::RebootRequest { destination } => {
+                writer.write_u8(0xd4)?;
                 writer.write_u8(destination)?;
             }
+            Packet::RebootAck => writer.write_u8(0xd5)?,
         }
         Ok(())
     }
@@ -958,15 +1374,34 @@ impl Packet {
             Packet::SubkernelLoadRunRequest { destination, .. } => Some(*destination),
             Packet::SubkernelLoadRunReply { destination, .. } => Some(*destination),
             Packet::SubkernelMessage { destination, .. } => Some(*destination),
-            Packet::SubkernelMessageAck { destination } => Some(*destination),
+            Packet::SubkernelMessageAck { destination, .. } => Some(*destination),
             Packet::SubkernelExceptionRequest { destination, .. } => Some(*destination),
             Packet::SubkernelException { destination, .. } => Some(*destination),
             Packet::DmaPlaybackStatus { destination, .. } => Some(*destination),
+            Packet::DmaPatchRequest { destination, .. } => Some(*destination),
+            Packet::DmaPatchReply { destination, .. } => Some(*destination),
+            Packet::DmaPatchRevertRequest { destination, .. } => Some(*destination),
+            Packet::DmaPatchRevertReply { destination, .. } => Some(*destination),
             Packet::SubkernelFinished { destination, .. } => Some(*destination),
+            Packet::SubkernelStopRequest { destination, .. } => Some(*destination),
+            Packet::SubkernelStopReply { destination, .. } => Some(*destination),
+            Packet::DestinationEchoRequest { destination, .. } => Some(*destination),
+            Packet::DestinationEchoReply { destination, .. } => Some(*destination),
             _ => None,
         }
     }
 
+    pub fn reply_timeout(&self) -> u32 {
+        // in ms, how long to wait for the reply to a request;
+        // skew calibration only replies once it is done, which takes minutes at worst,
+        // and a firmware commit once the whole image is checked and written to the SD card
+        match self {
+            Packet::WrpllCalibrateRequest { .. } => 240_000,
+            Packet::FirmwareCommitRequest { .. } => 60_000,
+            _ => 200,
+        }
+    }
+
     pub fn expects_response(&self) -> bool {
         // returns true if the routable packet should elicit a response
         // e.g. reply, ACK packets end a conversation,
@@ -978,7 +1413,11 @@ impl Packet {
             | Packet::SubkernelLoadRunReply { .. }
             | Packet::SubkernelMessageAck { .. }
             | Packet::DmaPlaybackStatus { .. }
+            | Packet::DmaPatchReply { .. }
+            | Packet::DmaPatchRevertReply { .. }
             | Packet::SubkernelFinished { .. }
+            | Packet::SubkernelStopReply { .. }
+            | Packet::DestinationEchoReply { .. }
             | Packet::InjectionRequest { .. } => false,
             _ => true,
         }
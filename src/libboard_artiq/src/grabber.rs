@@ -1,9 +1,13 @@
+use libcortex_a9::mutex::Mutex;
 use log::info;
 
 use crate::pl::csr;
 
-#[derive(PartialEq, Clone, Copy)]
-enum State {
+pub const GRABBER_COUNT: usize = csr::GRABBER_LEN;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[repr(u8)]
+pub enum State {
     Reset,
     ExitReset,
     Lock,
@@ -15,12 +19,57 @@ enum State {
 struct Info {
     state: State,
     frame_size: (u16, u16),
+    video_clock: u32,
+    lock_loss_count: u32,
+}
+
+// Updated by tick() on core0, read by kernels on core1
+static INFO: Mutex<[Info; csr::GRABBER_LEN]> = Mutex::new(
+    [Info {
+        state: State::Reset,
+        frame_size: (0, 0),
+        video_clock: 0,
+        lock_loss_count: 0,
+    }; csr::GRABBER_LEN],
+);
+
+impl Default for State {
+    fn default() -> State {
+        State::Reset
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Status {
+    pub state: State,
+    /// Video clock in MHz, as measured when the PLL locked
+    pub video_clock: u32,
+    /// Width and height of the last frame, (0, 0) until a frame is seen
+    pub frame_size: (u16, u16),
+    /// Times the grabber dropped out of lock or alignment after locking
+    pub lock_loss_count: u32,
 }
 
-static mut INFO: [Info; csr::GRABBER_LEN] = [Info {
-    state: State::Reset,
-    frame_size: (0, 0),
-}; csr::GRABBER_LEN];
+pub fn status(g: usize) -> Option<Status> {
+    if g >= GRABBER_COUNT {
+        return None;
+    }
+    let info = INFO.lock()[g];
+    Some(Status {
+        state: info.state,
+        video_clock: info.video_clock,
+        frame_size: match info.frame_size {
+            (0, 0) => (0, 0),
+            // see the frame size capture in tick()
+            (x, y) => (x, y + 1),
+        },
+        lock_loss_count: info.lock_loss_count,
+    })
+}
+
+fn lock_lost(info: &mut Info) {
+    info.lock_loss_count += 1;
+}
 
 fn get_pll_reset(g: usize) -> bool {
     unsafe { (csr::GRABBER[g].pll_reset_read)() != 0 }
@@ -95,12 +144,13 @@ fn get_video_clock(g: usize) -> u32 {
 
 pub fn tick() {
     for g in 0..csr::GRABBER.len() {
-        let next = match unsafe { INFO[g].state } {
+        // alignment takes a while, so the lock is only held to copy the info
+        let mut info = INFO.lock()[g];
+        let next = match info.state {
             State::Reset => {
                 set_pll_reset(g, true);
-                unsafe {
-                    INFO[g].frame_size = (0, 0);
-                }
+                info.frame_size = (0, 0);
+                info.video_clock = 0;
                 State::ExitReset
             }
             State::ExitReset => {
@@ -113,7 +163,9 @@ pub fn tick() {
             }
             State::Lock => {
                 if pll_locked(g) {
-                    info!("grabber{} locked: {}MHz", g, get_video_clock(g));
+                    let video_clock = get_video_clock(g);
+                    info!("grabber{} locked: {}MHz", g, video_clock);
+                    info.video_clock = video_clock;
                     State::Align
                 } else {
                     State::Lock
@@ -130,6 +182,7 @@ pub fn tick() {
                     }
                 } else {
                     info!("grabber{} lock lost", g);
+                    lock_lost(&mut info);
                     State::Reset
                 }
             }
@@ -137,27 +190,28 @@ pub fn tick() {
                 if pll_locked(g) {
                     if clock_pattern_ok(g) {
                         let last_xy = get_last_pixels(g);
-                        if last_xy != unsafe { INFO[g].frame_size } {
+                        if last_xy != info.frame_size {
                             // x capture is on ~LVAL which is after
                             // the last increment on DVAL
                             // y capture is on ~FVAL which coincides with the
                             // last increment on ~LVAL
                             info!("grabber{} frame size: {}x{}", g, last_xy.0, last_xy.1 + 1);
-                            unsafe { INFO[g].frame_size = last_xy }
+                            info.frame_size = last_xy;
                         }
                         State::Watch
                     } else {
                         info!("grabber{} alignment lost", g);
+                        lock_lost(&mut info);
                         State::Reset
                     }
                 } else {
                     info!("grabber{} lock lost", g);
+                    lock_lost(&mut info);
                     State::Reset
                 }
             }
         };
-        unsafe {
-            INFO[g].state = next;
-        }
+        info.state = next;
+        INFO.lock()[g] = info;
     }
 }
@@ -1,5 +1,10 @@
-use libboard_zynq::i2c;
-use log::info;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use libboard_zynq::{i2c, time::Milliseconds, timer::GlobalTimer};
+use libconfig::Config;
+use libcortex_a9::mutex::Mutex;
+use libsupport_zynq::alloc::{format, string::String, vec::Vec};
+use log::{info, warn};
 
 #[cfg(has_virtual_leds)]
 use crate::pl::csr;
@@ -11,6 +16,8 @@ struct Registers {
     iodirb: u8, // Configuration Port 1
     gpioa: u8,  // Output Port 0
     gpiob: u8,  // Output Port 1
+    inputa: u8, // Input Port 0
+    inputb: u8, // Input Port 1
 }
 
 //IO expanders pins
@@ -44,13 +51,171 @@ const IODIR1: [u8; 2] = [
     0xFF & !IODIR_OUT_SFP_TX_DISABLE & !IODIR_OUT_SFP_LED,
 ];
 
+const INPUT_POLL_INTERVAL: Milliseconds = Milliseconds(200);
+
+#[derive(Clone, Copy, PartialEq)]
+struct Pin {
+    expander: u8,
+    port: u8,
+    bit: u8,
+}
+
+impl Pin {
+    const fn new(expander: u8, port: u8, bit: u8) -> Pin {
+        Pin {
+            expander: expander,
+            port: port,
+            bit: bit,
+        }
+    }
+}
+
+// Named outputs and their levels at boot, overridable with `io_expander.<name>` config keys
+const OUTPUT_PINS: &[(&str, Pin, bool)] = &[
+    ("sfp0_tx_disable", Pin::new(0, 0, 1), false),
+    ("sfp1_tx_disable", Pin::new(1, 0, 1), false),
+    ("sfp2_tx_disable", Pin::new(0, 1, 1), false),
+    ("sfp3_tx_disable", Pin::new(1, 1, 1), false),
+    #[cfg(has_si549)]
+    ("clk_sel", Pin::new(0, 1, 7), true),
+    #[cfg(hw_rev = "v1.2")]
+    ("eem_pwr_en", Pin::new(1, 0, 7), true),
+];
+
+// Named inputs, declared in the `io_expander_inputs` config key and read from both cores
+static INPUT_PINS: Mutex<Vec<(String, Pin)>> = Mutex::new(Vec::new());
+
+// Output levels requested by any core, written out by IoExpander::service()
+static OUT_TARGET: [[AtomicU8; 2]; 2] = [
+    [AtomicU8::new(0), AtomicU8::new(0)],
+    [AtomicU8::new(0), AtomicU8::new(0)],
+];
+// Input levels as of the last IoExpander::poll_inputs()
+static IN_CURRENT: [[AtomicU8; 2]; 2] = [
+    [AtomicU8::new(0), AtomicU8::new(0)],
+    [AtomicU8::new(0), AtomicU8::new(0)],
+];
+
+fn write_pin(pin: Pin, high: bool) {
+    let target = &OUT_TARGET[pin.expander as usize][pin.port as usize];
+    if high {
+        target.fetch_or(1 << pin.bit, Ordering::SeqCst);
+    } else {
+        target.fetch_and(!(1 << pin.bit), Ordering::SeqCst);
+    }
+}
+
+fn find_output(name: &str) -> Option<Pin> {
+    OUTPUT_PINS
+        .iter()
+        .find(|(pin_name, _, _)| *pin_name == name)
+        .map(|(_, pin, _)| *pin)
+}
+
+fn find_input(name: &str) -> Option<Pin> {
+    INPUT_PINS
+        .lock()
+        .iter()
+        .find(|(pin_name, _)| pin_name == name)
+        .map(|(_, pin)| *pin)
+}
+
+// "<expander>.<port>.<bit>", e.g. "0.1.3"
+fn parse_pin(s: &str) -> Result<Pin, &'static str> {
+    let mut fields = s.split('.').map(|field| field.trim().parse::<u8>());
+    let mut next = || match fields.next() {
+        Some(Ok(value)) => Ok(value),
+        _ => Err("expected <expander>.<port>.<bit>"),
+    };
+    let pin = Pin {
+        expander: next()?,
+        port: next()?,
+        bit: next()?,
+    };
+    if pin.expander > 1 || pin.port > 1 || pin.bit > 7 {
+        return Err("pin out of range");
+    }
+    let iodir = [IODIR0, IODIR1][pin.expander as usize][pin.port as usize];
+    if iodir & (1 << pin.bit) == 0 {
+        return Err("pin is an output");
+    }
+    Ok(pin)
+}
+
+/// Declares the named inputs and applies the output levels stored in config.
+pub fn config(cfg: &Config) {
+    if let Ok(inputs) = cfg.read_str("io_expander_inputs") {
+        // e.g. "sfp0_los=0.0.0,sfp1_los=1.0.0"
+        for entry in inputs.split(',').filter(|entry| !entry.is_empty()) {
+            let mut fields = entry.splitn(2, '=');
+            let name = fields.next().unwrap().trim();
+            match fields.next().ok_or("expected <name>=<pin>").and_then(parse_pin) {
+                Ok(pin) => INPUT_PINS.lock().push((String::from(name), pin)),
+                Err(e) => warn!("ignoring io_expander_inputs entry {} ({})", entry, e),
+            }
+        }
+    }
+    for (name, pin, _) in OUTPUT_PINS.iter() {
+        let key = format!("io_expander.{}", name);
+        if let Ok(level) = cfg.read_str(&key) {
+            match level.as_ref() {
+                "0" => write_pin(*pin, false),
+                "1" => write_pin(*pin, true),
+                _ => warn!("{} must be 0 or 1, ignoring", key),
+            }
+        }
+    }
+}
+
+pub fn set_pin(name: &str, high: bool) -> Result<(), &'static str> {
+    match find_output(name) {
+        Some(pin) => {
+            write_pin(pin, high);
+            Ok(())
+        }
+        None if find_input(name).is_some() => Err("I/O expander pin is an input"),
+        None => Err("no such I/O expander pin"),
+    }
+}
+
+pub fn get_pin(name: &str) -> Result<bool, &'static str> {
+    let (levels, pin) = match (find_output(name), find_input(name)) {
+        (Some(pin), _) => (&OUT_TARGET, pin),
+        (None, Some(pin)) => (&IN_CURRENT, pin),
+        (None, None) => return Err("no such I/O expander pin"),
+    };
+    Ok(levels[pin.expander as usize][pin.port as usize].load(Ordering::SeqCst) & (1 << pin.bit) != 0)
+}
+
+pub struct PinStatus {
+    pub name: String,
+    pub output: bool,
+    pub level: bool,
+}
+
+pub fn pins() -> Vec<PinStatus> {
+    let outputs = OUTPUT_PINS.iter().map(|(name, _, _)| (String::from(*name), true));
+    // collect the names first, get_pin() locks INPUT_PINS again
+    let inputs: Vec<(String, bool)> = INPUT_PINS.lock().iter().map(|(name, _)| (name.clone(), false)).collect();
+    outputs
+        .chain(inputs)
+        .map(|(name, output)| PinStatus {
+            level: get_pin(&name).unwrap(),
+            name: name,
+            output: output,
+        })
+        .collect()
+}
+
 pub struct IoExpander {
+    index: u8,
     address: u8,
     #[cfg(has_virtual_leds)]
     virtual_led_mapping: &'static [(u8, u8, u8)],
     iodir: [u8; 2],
     out_current: [u8; 2],
-    out_target: [u8; 2],
+    in_current: Option<[u8; 2]>,
+    next_poll: Milliseconds,
     registers: Registers,
 }
 
@@ -66,31 +231,39 @@ impl IoExpander {
         // Both expanders on SHARED I2C bus
         let mut io_expander = match index {
             0 => IoExpander {
+                index: 0,
                 address: 0x40,
                 #[cfg(has_virtual_leds)]
                 virtual_led_mapping: &VIRTUAL_LED_MAPPING0,
                 iodir: IODIR0,
                 out_current: [0; 2],
-                out_target: [0; 2],
+                in_current: None,
+                next_poll: Milliseconds(0),
                 registers: Registers {
                     iodira: 0x00,
                     iodirb: 0x01,
                     gpioa: 0x12,
                     gpiob: 0x13,
+                    inputa: 0x12,
+                    inputb: 0x13,
                 },
             },
             1 => IoExpander {
+                index: 1,
                 address: 0x42,
                 #[cfg(has_virtual_leds)]
                 virtual_led_mapping: &VIRTUAL_LED_MAPPING1,
                 iodir: IODIR1,
                 out_current: [0; 2],
-                out_target: [0; 2],
+                in_current: None,
+                next_poll: Milliseconds(0),
                 registers: Registers {
                     iodira: 0x00,
                     iodirb: 0x01,
                     gpioa: 0x12,
                     gpiob: 0x13,
+                    inputa: 0x12,
+                    inputb: 0x13,
                 },
             },
             _ => return Err("incorrect I/O expander index"),
@@ -103,6 +276,8 @@ impl IoExpander {
                 iodirb: 0x07,
                 gpioa: 0x02,
                 gpiob: 0x03,
+                inputa: 0x00,
+                inputb: 0x01,
             };
             if !io_expander.check_ack(i2c)? {
                 return Err("Neither MCP23017 nor PCA9539 io expander found.");
@@ -126,6 +301,17 @@ impl IoExpander {
         Ok(())
     }
 
+    fn read(&self, i2c: &mut i2c::I2c, addr: u8) -> Result<u8, &'static str> {
+        i2c.start()?;
+        i2c.write(self.address)?;
+        i2c.write(addr)?;
+        i2c.restart()?;
+        i2c.write(self.address | 1)?;
+        let value = i2c.read(false)?;
+        i2c.stop()?;
+        Ok(value)
+    }
+
     fn check_ack(&self, i2c: &mut i2c::I2c) -> Result<bool, &'static str> {
         // Check for ack from io expander
         self.select(i2c)?;
@@ -150,6 +336,10 @@ impl IoExpander {
         self.write(i2c, self.registers.gpioa, 0x00)?;
         self.out_current[1] = 0x00;
         self.write(i2c, self.registers.gpiob, 0x00)?;
+
+        for (_, pin, level) in OUTPUT_PINS.iter().filter(|(_, pin, _)| pin.expander == self.index) {
+            write_pin(*pin, *level);
+        }
         Ok(())
     }
 
@@ -160,11 +350,7 @@ impl IoExpander {
     }
 
     pub fn set(&mut self, port: u8, bit: u8, high: bool) {
-        if high {
-            self.out_target[port as usize] |= 1 << bit;
-        } else {
-            self.out_target[port as usize] &= !(1 << bit);
-        }
+        write_pin(Pin::new(self.index, port, bit), high);
     }
 
     pub fn service(&mut self, i2c: &mut i2c::I2c) -> Result<(), &'static str> {
@@ -174,18 +360,62 @@ impl IoExpander {
             self.set(*port, *bit, level != 0);
         }
 
-        if self.out_target != self.out_current {
+        let out_target = [
+            OUT_TARGET[self.index as usize][0].load(Ordering::SeqCst),
+            OUT_TARGET[self.index as usize][1].load(Ordering::SeqCst),
+        ];
+        if out_target != self.out_current {
             self.select(i2c)?;
-            if self.out_target[0] != self.out_current[0] {
-                self.write(i2c, self.registers.gpioa, self.out_target[0])?;
-                self.out_current[0] = self.out_target[0];
+            if out_target[0] != self.out_current[0] {
+                self.write(i2c, self.registers.gpioa, out_target[0])?;
+                self.out_current[0] = out_target[0];
             }
-            if self.out_target[1] != self.out_current[1] {
-                self.write(i2c, self.registers.gpiob, self.out_target[1])?;
-                self.out_current[1] = self.out_target[1];
+            if out_target[1] != self.out_current[1] {
+                self.write(i2c, self.registers.gpiob, out_target[1])?;
+                self.out_current[1] = out_target[1];
             }
         }
 
         Ok(())
     }
+
+    /// Samples the input ports if due, logging level changes of the named inputs.
+    pub fn poll_inputs(&mut self, i2c: &mut i2c::I2c, timer: &GlobalTimer) -> Result<(), &'static str> {
+        let now = timer.get_time();
+        if now < self.next_poll {
+            return Ok(());
+        }
+        self.next_poll = now + INPUT_POLL_INTERVAL;
+
+        // keep the shared bus free unless some input is named
+        let inputs: Vec<(String, Pin)> = INPUT_PINS
+            .lock()
+            .iter()
+            .filter(|(_, pin)| pin.expander == self.index)
+            .cloned()
+            .collect();
+        if inputs.is_empty() {
+            return Ok(());
+        }
+
+        self.select(i2c)?;
+        let in_current = [
+            self.read(i2c, self.registers.inputa)?,
+            self.read(i2c, self.registers.inputb)?,
+        ];
+        for (port, level) in in_current.iter().enumerate() {
+            IN_CURRENT[self.index as usize][port].store(*level, Ordering::SeqCst);
+        }
+        if let Some(in_previous) = self.in_current {
+            for (name, pin) in inputs.iter() {
+                let changed = (in_current[pin.port as usize] ^ in_previous[pin.port as usize]) & (1 << pin.bit);
+                if changed != 0 {
+                    let level = in_current[pin.port as usize] & (1 << pin.bit) != 0;
+                    info!("I/O expander input {} changed to {}", name, level as u8);
+                }
+            }
+        }
+        self.in_current = Some(in_current);
+        Ok(())
+    }
 }
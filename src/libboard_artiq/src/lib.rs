@@ -40,6 +40,7 @@ pub mod grabber;
 pub mod si5324;
 #[cfg(has_si549)]
 pub mod si549;
+pub mod spi;
 use core::{cmp, str};
 
 pub fn identifier_read(buf: &mut [u8]) -> &str {
@@ -1,5 +1,7 @@
 use core::result;
 
+use clock_solver::si5324::map_frequency_settings;
+pub use clock_solver::si5324::FrequencySettings;
 use embedded_hal::blocking::delay::DelayUs;
 use libboard_zynq::{i2c::I2c, time::Milliseconds, timer::GlobalTimer};
 use log::info;
@@ -23,78 +25,11 @@ fn hard_reset(timer: &mut GlobalTimer) {
     timer.delay_us(10_000);
 }
 
-// NOTE: the logical parameters DO NOT MAP to physical values written
-// into registers. They have to be mapped; see the datasheet.
-// DSPLLsim reports the logical parameters in the design summary, not
-// the physical register values.
-pub struct FrequencySettings {
-    pub n1_hs: u8,
-    pub nc1_ls: u32,
-    pub n2_hs: u8,
-    pub n2_ls: u32,
-    pub n31: u32,
-    pub n32: u32,
-    pub bwsel: u8,
-    pub crystal_as_ckin2: bool,
-}
-
 pub enum Input {
     Ckin1,
     Ckin2,
 }
 
-fn map_frequency_settings(settings: &FrequencySettings) -> Result<FrequencySettings> {
-    if settings.nc1_ls != 0 && (settings.nc1_ls % 2) == 1 {
-        return Err("NC1_LS must be 0 or even");
-    }
-    if settings.nc1_ls > (1 << 20) {
-        return Err("NC1_LS is too high");
-    }
-    if (settings.n2_ls % 2) == 1 {
-        return Err("N2_LS must be even");
-    }
-    if settings.n2_ls > (1 << 20) {
-        return Err("N2_LS is too high");
-    }
-    if settings.n31 > (1 << 19) {
-        return Err("N31 is too high");
-    }
-    if settings.n32 > (1 << 19) {
-        return Err("N32 is too high");
-    }
-    let r = FrequencySettings {
-        n1_hs: match settings.n1_hs {
-            4 => 0b000,
-            5 => 0b001,
-            6 => 0b010,
-            7 => 0b011,
-            8 => 0b100,
-            9 => 0b101,
-            10 => 0b110,
-            11 => 0b111,
-            _ => return Err("N1_HS has an invalid value"),
-        },
-        nc1_ls: settings.nc1_ls - 1,
-        n2_hs: match settings.n2_hs {
-            4 => 0b000,
-            5 => 0b001,
-            6 => 0b010,
-            7 => 0b011,
-            8 => 0b100,
-            9 => 0b101,
-            10 => 0b110,
-            11 => 0b111,
-            _ => return Err("N2_HS has an invalid value"),
-        },
-        n2_ls: settings.n2_ls - 1,
-        n31: settings.n31 - 1,
-        n32: settings.n32 - 1,
-        bwsel: settings.bwsel,
-        crystal_as_ckin2: settings.crystal_as_ckin2,
-    };
-    Ok(r)
-}
-
 fn write(i2c: &mut I2c, reg: u8, val: u8) -> Result<()> {
     i2c.start().unwrap();
     if !i2c.write(ADDRESS << 1).unwrap() {
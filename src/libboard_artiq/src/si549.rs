@@ -1,25 +1,17 @@
+use core::cmp;
+
 use embedded_hal::prelude::_embedded_hal_blocking_delay_DelayUs;
 use libboard_zynq::timer::GlobalTimer;
 use log::info;
 
 use crate::pl::csr;
+pub use clock_solver::si549::{DividerConfig, FrequencySetting};
 
 #[cfg(feature = "target_kasli_soc")]
 const ADDRESS: u8 = 0x67;
 
 const ADPLL_MAX: i32 = (950.0 / 0.0001164) as i32;
 
-pub struct DividerConfig {
-    pub hsdiv: u16,
-    pub lsdiv: u8,
-    pub fbdiv: u64,
-}
-
-pub struct FrequencySetting {
-    pub main: DividerConfig,
-    pub helper: DividerConfig,
-}
-
 mod i2c {
     use super::*;
 
@@ -328,6 +320,12 @@ fn set_adpll(dcxo: i2c::DCXO, adpll: i32) -> Result<(), &'static str> {
 #[cfg(has_wrpll)]
 pub mod wrpll {
 
+    use core::{str::FromStr,
+               sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering}};
+
+    use libconfig::Config;
+    use log::warn;
+
     use super::*;
 
     const BEATING_PERIOD: i32 = 0x8000;
@@ -336,7 +334,8 @@ pub mod wrpll {
     const DIV_WIDTH: u32 = 2;
 
     // y[n] = b0*x[n] + b1*x[n-1] + b2*x[n-2] - a1*y[n-1] - a2*y[n-2]
-    struct FilterParameters {
+    #[derive(Debug, Clone, Copy)]
+    pub struct FilterParameters {
         pub b0: f64,
         pub b1: f64,
         pub b2: f64,
@@ -344,8 +343,36 @@ pub mod wrpll {
         pub a2: f64,
     }
 
+    impl FromStr for FilterParameters {
+        type Err = &'static str;
+
+        // "b0,b1,b2,a1,a2"
+        fn from_str(s: &str) -> Result<FilterParameters, &'static str> {
+            let mut coefficients = [0.0; 5];
+            let mut values = s.split(',');
+            for coefficient in coefficients.iter_mut() {
+                *coefficient = values
+                    .next()
+                    .ok_or("too few filter coefficients")?
+                    .trim()
+                    .parse()
+                    .map_err(|_| "invalid filter coefficient")?;
+            }
+            if values.next().is_some() {
+                return Err("too many filter coefficients");
+            }
+            Ok(FilterParameters {
+                b0: coefficients[0],
+                b1: coefficients[1],
+                b2: coefficients[2],
+                a1: coefficients[3],
+                a2: coefficients[4],
+            })
+        }
+    }
+
     #[cfg(rtio_frequency = "100.0")]
-    const LPF: FilterParameters = FilterParameters {
+    pub const DEFAULT_LPF: FilterParameters = FilterParameters {
         b0: 0.03967479060647884,
         b1: 0.07934958121295768,
         b2: 0.03967479060647884,
@@ -354,7 +381,7 @@ pub mod wrpll {
     };
 
     #[cfg(rtio_frequency = "125.0")]
-    const LPF: FilterParameters = FilterParameters {
+    pub const DEFAULT_LPF: FilterParameters = FilterParameters {
         b0: 0.07209205036273991,
         b1: 0.14418410072547982,
         b2: 0.07209205036273991,
@@ -362,6 +389,85 @@ pub mod wrpll {
         a2: -0.10022394739274834,
     };
 
+    // Double-buffered so that the FIQ handler never sees a half-written filter;
+    // set_filter writes the inactive slot, then flips LPF_ACTIVE.
+    static mut LPF: [FilterParameters; 2] = [DEFAULT_LPF, DEFAULT_LPF];
+    static LPF_ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+    pub fn filter() -> FilterParameters {
+        unsafe { LPF[LPF_ACTIVE.load(Ordering::Acquire)] }
+    }
+
+    pub fn set_filter(params: FilterParameters) {
+        let inactive = 1 - LPF_ACTIVE.load(Ordering::Acquire);
+        unsafe {
+            LPF[inactive] = params;
+        }
+        LPF_ACTIVE.store(inactive, Ordering::Release);
+        info!("WRPLL loop filter set to {:?}", params);
+    }
+
+    /// Applies the loop filter from the `wrpll_filter` config key, if present.
+    pub fn config_filter(cfg: &Config) {
+        if let Ok(s) = cfg.read_str("wrpll_filter") {
+            match s.parse() {
+                Ok(params) => set_filter(params),
+                Err(e) => warn!("ignoring wrpll_filter ({}), using the default loop filter", e),
+            }
+        }
+    }
+
+    /// One loop iteration: the latest errors and the ADPLL offsets (from the base) applied.
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct Sample {
+        pub period_err: i32,
+        pub phase_err: i32,
+        pub helper_adpll: i32,
+        pub main_adpll: i32,
+    }
+
+    pub const TELEMETRY_LEN: usize = 256;
+
+    // Ring buffer filled by the FIQ handler. TELEMETRY_SEQ counts the samples
+    // ever written and is bumped after each write, so readers can detect one
+    // happening under them.
+    static mut TELEMETRY: [Sample; TELEMETRY_LEN] = [Sample {
+        period_err: 0,
+        phase_err: 0,
+        helper_adpll: 0,
+        main_adpll: 0,
+    }; TELEMETRY_LEN];
+    static TELEMETRY_SEQ: AtomicU32 = AtomicU32::new(0);
+
+    fn record_sample() {
+        let seq = TELEMETRY_SEQ.load(Ordering::Relaxed);
+        unsafe {
+            TELEMETRY[seq as usize % TELEMETRY_LEN] = Sample {
+                period_err: PERIOD_ERR1,
+                phase_err: PHASE_ERR1,
+                helper_adpll: H_ADPLL1,
+                main_adpll: M_ADPLL1,
+            };
+        }
+        TELEMETRY_SEQ.store(seq.wrapping_add(1), Ordering::Release);
+    }
+
+    /// Copies the most recent samples, oldest first, into `buffer`. Returns the
+    /// total number of samples recorded since reset and the number copied.
+    pub fn telemetry(buffer: &mut [Sample]) -> (u32, usize) {
+        loop {
+            let seq = TELEMETRY_SEQ.load(Ordering::Acquire);
+            let count = cmp::min(cmp::min(seq as usize, TELEMETRY_LEN), buffer.len());
+            for i in 0..count {
+                let index = (seq as usize - count + i) % TELEMETRY_LEN;
+                buffer[i] = unsafe { TELEMETRY[index] };
+            }
+            if TELEMETRY_SEQ.load(Ordering::Acquire) == seq {
+                return (seq, count);
+            }
+        }
+    }
+
     static mut H_ADPLL1: i32 = 0;
     static mut H_ADPLL2: i32 = 0;
     static mut PERIOD_ERR1: i32 = 0;
@@ -424,14 +530,13 @@ pub mod wrpll {
             unsafe { REF_TAG_READY && MAIN_TAG_READY }
         }
 
-        #[cfg(feature = "calibrate_wrpll_skew")]
         pub fn set_tag_offset(offset: u32) {
             unsafe {
                 TAG_OFFSET = offset;
             }
         }
 
-        #[cfg(feature = "calibrate_wrpll_skew")]
+        #[allow(dead_code)]
         pub fn get_tag_offset() -> u32 {
             unsafe { TAG_OFFSET }
         }
@@ -463,6 +568,164 @@ pub mod wrpll {
         }
     }
 
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Event {
+        ReferenceLost,
+        ReferenceRecovered,
+    }
+
+    static REFERENCE_LOST: AtomicBool = AtomicBool::new(false);
+    static REFERENCE_RECOVERED: AtomicBool = AtomicBool::new(false);
+    static IN_HOLDOVER: AtomicBool = AtomicBool::new(false);
+    static HOLDOVER_COUNT: AtomicU32 = AtomicU32::new(0);
+
+    /// Takes a pending reference loss or recovery event, losses first.
+    pub fn take_event() -> Option<Event> {
+        if REFERENCE_LOST.swap(false, Ordering::AcqRel) {
+            Some(Event::ReferenceLost)
+        } else if REFERENCE_RECOVERED.swap(false, Ordering::AcqRel) {
+            Some(Event::ReferenceRecovered)
+        } else {
+            None
+        }
+    }
+
+    /// Whether the DCXOs are held at their last good average, and how many
+    /// times the reference has been lost.
+    pub fn holdover_status() -> (bool, u32) {
+        (IN_HOLDOVER.load(Ordering::Acquire), HOLDOVER_COUNT.load(Ordering::Acquire))
+    }
+
+    mod holdover {
+        use super::*;
+
+        // Main tags keep coming when the reference is gone, so a run of them
+        // without a reference tag, or of period errors out of range, means the
+        // reference was lost. Lock is (re)acquired after a run of good samples.
+        const MISSING_REF_TAGS: u32 = 8;
+        const PERIOD_ERR_LIMIT: i32 = BEATING_PERIOD / 16;
+        const LOSS_SAMPLES: u32 = 8;
+        const LOCK_SAMPLES: u32 = 64;
+        // ADPLL values are averaged over about 2^AVG_SHIFT samples
+        const AVG_SHIFT: u32 = 10;
+
+        #[derive(Clone, Copy, PartialEq)]
+        enum State {
+            Acquiring,
+            Locked,
+            Holdover,
+        }
+
+        static mut STATE: State = State::Acquiring;
+        static mut GOOD_SAMPLES: u32 = 0;
+        static mut BAD_SAMPLES: u32 = 0;
+        static mut MAIN_TAGS_WITHOUT_REF: u32 = 0;
+        // scaled by 2^AVG_SHIFT
+        static mut MAIN_ADPLL_AVG: i64 = 0;
+        static mut HELPER_ADPLL_AVG: i64 = 0;
+
+        pub fn reset() {
+            unsafe {
+                STATE = State::Acquiring;
+                GOOD_SAMPLES = 0;
+                BAD_SAMPLES = 0;
+                MAIN_TAGS_WITHOUT_REF = 0;
+            }
+            IN_HOLDOVER.store(false, Ordering::Release);
+        }
+
+        pub fn active() -> bool {
+            unsafe { STATE == State::Holdover }
+        }
+
+        fn in_range(period_err: i32) -> bool {
+            period_err.abs() <= PERIOD_ERR_LIMIT
+        }
+
+        /// Tracks a reference tag, returns whether the helper PLL should run.
+        pub fn ref_tag(period_err: i32) -> bool {
+            unsafe {
+                MAIN_TAGS_WITHOUT_REF = 0;
+                match STATE {
+                    State::Acquiring => {
+                        GOOD_SAMPLES = if in_range(period_err) { GOOD_SAMPLES + 1 } else { 0 };
+                        if GOOD_SAMPLES >= LOCK_SAMPLES {
+                            STATE = State::Locked;
+                            BAD_SAMPLES = 0;
+                            MAIN_ADPLL_AVG = ((BASE_ADPLL + M_ADPLL1) as i64) << AVG_SHIFT;
+                            HELPER_ADPLL_AVG = ((BASE_ADPLL + H_ADPLL1) as i64) << AVG_SHIFT;
+                        }
+                        true
+                    }
+                    State::Locked => {
+                        BAD_SAMPLES = if in_range(period_err) { 0 } else { BAD_SAMPLES + 1 };
+                        if BAD_SAMPLES >= LOSS_SAMPLES {
+                            enter();
+                            return false;
+                        }
+                        true
+                    }
+                    State::Holdover => {
+                        GOOD_SAMPLES = if in_range(period_err) { GOOD_SAMPLES + 1 } else { 0 };
+                        if GOOD_SAMPLES >= LOCK_SAMPLES {
+                            recover();
+                        }
+                        false
+                    }
+                }
+            }
+        }
+
+        pub fn main_tag() {
+            unsafe {
+                MAIN_TAGS_WITHOUT_REF += 1;
+                if STATE == State::Locked && MAIN_TAGS_WITHOUT_REF >= MISSING_REF_TAGS {
+                    enter();
+                }
+            }
+        }
+
+        pub fn update_averages() {
+            unsafe {
+                if STATE == State::Locked {
+                    MAIN_ADPLL_AVG += (BASE_ADPLL + M_ADPLL1) as i64 - (MAIN_ADPLL_AVG >> AVG_SHIFT);
+                    HELPER_ADPLL_AVG += (BASE_ADPLL + H_ADPLL1) as i64 - (HELPER_ADPLL_AVG >> AVG_SHIFT);
+                }
+            }
+        }
+
+        fn enter() {
+            unsafe {
+                STATE = State::Holdover;
+                GOOD_SAMPLES = 0;
+                set_adpll(i2c::DCXO::Main, (MAIN_ADPLL_AVG >> AVG_SHIFT) as i32).expect("failed to hold main DCXO");
+                set_adpll(i2c::DCXO::Helper, (HELPER_ADPLL_AVG >> AVG_SHIFT) as i32)
+                    .expect("failed to hold helper DCXO");
+            }
+            IN_HOLDOVER.store(true, Ordering::Release);
+            HOLDOVER_COUNT.fetch_add(1, Ordering::AcqRel);
+            REFERENCE_LOST.store(true, Ordering::Release);
+        }
+
+        fn recover() {
+            unsafe {
+                // restart the loop filters from the holdover values
+                M_ADPLL1 = (MAIN_ADPLL_AVG >> AVG_SHIFT) as i32 - BASE_ADPLL;
+                M_ADPLL2 = M_ADPLL1;
+                H_ADPLL1 = (HELPER_ADPLL_AVG >> AVG_SHIFT) as i32 - BASE_ADPLL;
+                H_ADPLL2 = H_ADPLL1;
+                PHASE_ERR1 = 0;
+                PHASE_ERR2 = 0;
+                PERIOD_ERR1 = 0;
+                PERIOD_ERR2 = 0;
+                STATE = State::Acquiring;
+                GOOD_SAMPLES = 0;
+            }
+            IN_HOLDOVER.store(false, Ordering::Release);
+            REFERENCE_RECOVERED.store(true, Ordering::Release);
+        }
+    }
+
     fn set_isr(en: bool) {
         let val = if en { 1 } else { 0 };
         unsafe {
@@ -509,6 +772,7 @@ pub mod wrpll {
             PHASE_ERR1 = 0;
             PHASE_ERR2 = 0;
         }
+        TELEMETRY_SEQ.store(0, Ordering::Release);
         set_adpll(i2c::DCXO::Main, 0)?;
         set_adpll(i2c::DCXO::Helper, 0)?;
         // wait for adpll to transfer and DCXO to settle
@@ -534,26 +798,34 @@ pub mod wrpll {
         if is_pending(ISR::RefTag) {
             tag_collector::collect_tags(ISR::RefTag);
             clear_pending(ISR::RefTag);
-            helper_pll().expect("failed to run helper DCXO PLL");
+            let period_err = tag_collector::get_period_error();
+            if holdover::ref_tag(period_err) {
+                helper_pll(period_err).expect("failed to run helper DCXO PLL");
+            }
         }
 
         if is_pending(ISR::MainTag) {
             tag_collector::collect_tags(ISR::MainTag);
             clear_pending(ISR::MainTag);
+            holdover::main_tag();
         }
 
         if tag_collector::phase_diff_ready() {
-            main_pll().expect("failed to run main DCXO PLL");
+            if !holdover::active() {
+                main_pll().expect("failed to run main DCXO PLL");
+                holdover::update_averages();
+                record_sample();
+            }
             tag_collector::clear_phase_diff_ready();
         }
     }
 
-    fn helper_pll() -> Result<(), &'static str> {
-        let period_err = tag_collector::get_period_error();
+    fn helper_pll(period_err: i32) -> Result<(), &'static str> {
+        let lpf = filter();
         unsafe {
-            let adpll = ((LPF.b0 * period_err as f64) + (LPF.b1 * PERIOD_ERR1 as f64) + (LPF.b2 * PERIOD_ERR2 as f64)
-                - (LPF.a1 * H_ADPLL1 as f64)
-                - (LPF.a2 * H_ADPLL2 as f64)) as i32;
+            let adpll = ((lpf.b0 * period_err as f64) + (lpf.b1 * PERIOD_ERR1 as f64) + (lpf.b2 * PERIOD_ERR2 as f64)
+                - (lpf.a1 * H_ADPLL1 as f64)
+                - (lpf.a2 * H_ADPLL2 as f64)) as i32;
             set_adpll(i2c::DCXO::Helper, BASE_ADPLL + adpll)?;
             H_ADPLL2 = H_ADPLL1;
             PERIOD_ERR2 = PERIOD_ERR1;
@@ -565,10 +837,11 @@ pub mod wrpll {
 
     fn main_pll() -> Result<(), &'static str> {
         let phase_err = tag_collector::get_phase_error();
+        let lpf = filter();
         unsafe {
-            let adpll = ((LPF.b0 * phase_err as f64) + (LPF.b1 * PHASE_ERR1 as f64) + (LPF.b2 * PHASE_ERR2 as f64)
-                - (LPF.a1 * M_ADPLL1 as f64)
-                - (LPF.a2 * M_ADPLL2 as f64)) as i32;
+            let adpll = ((lpf.b0 * phase_err as f64) + (lpf.b1 * PHASE_ERR1 as f64) + (lpf.b2 * PHASE_ERR2 as f64)
+                - (lpf.a1 * M_ADPLL1 as f64)
+                - (lpf.a2 * M_ADPLL2 as f64)) as i32;
             set_adpll(i2c::DCXO::Main, BASE_ADPLL + adpll)?;
             M_ADPLL2 = M_ADPLL1;
             PHASE_ERR2 = PHASE_ERR1;
@@ -664,10 +937,17 @@ pub mod wrpll {
     fn calibrate_skew(timer: &mut GlobalTimer) -> Result<(), &'static str> {
         info!("calibrating skew to meet timing constraint...");
 
-        // clear calibrated value
+        // clear calibrated value, keeping it in case calibration fails
+        let previous = tag_collector::get_tag_offset();
         tag_collector::set_tag_offset(0);
-        let rising = find_edge(true, timer)? as i32;
-        let falling = find_edge(false, timer)? as i32;
+        let edges = find_edge(true, timer).and_then(|rising| Ok((rising as i32, find_edge(false, timer)? as i32)));
+        let (rising, falling) = match edges {
+            Ok(edges) => edges,
+            Err(e) => {
+                tag_collector::set_tag_offset(previous);
+                return Err(e);
+            }
+        };
 
         let width = BEATING_PERIOD - (falling - rising);
         let result = falling + width / 2;
@@ -685,11 +965,67 @@ pub mod wrpll {
         Ok(())
     }
 
+    const TAG_OFFSET_KEY: &str = "wrpll_tag_offset";
+    // whether TAG_OFFSET matches the one stored in config
+    static mut TAG_OFFSET_STORED: bool = false;
+
+    /// Applies the tag offset calibrated on an earlier boot, if stored in config.
+    pub fn load_tag_offset(cfg: &Config) {
+        match cfg.read(TAG_OFFSET_KEY) {
+            Ok(record) if record.len() == 4 => {
+                let offset = u32::from_le_bytes([record[0], record[1], record[2], record[3]]);
+                info!("loading calibrated tag offset {} from sd card", offset);
+                tag_collector::set_tag_offset(offset);
+                unsafe {
+                    TAG_OFFSET_STORED = true;
+                }
+            }
+            Ok(_) => warn!("ignoring malformed {}", TAG_OFFSET_KEY),
+            Err(_) => {}
+        }
+    }
+
+    /// Stores a freshly calibrated tag offset into config, so that the next
+    /// boot can skip calibration.
+    #[cfg(feature = "calibrate_wrpll_skew")]
+    pub fn save_tag_offset(cfg: &Config) -> Result<(), &'static str> {
+        if unsafe { TAG_OFFSET_STORED } {
+            return Ok(());
+        }
+        let offset = tag_collector::get_tag_offset();
+        cfg.write(TAG_OFFSET_KEY, offset.to_le_bytes().to_vec())
+            .map_err(|_| "failed to store tag offset into sd card")?;
+        info!("storing calibrated tag offset {} into sd card", offset);
+        unsafe {
+            TAG_OFFSET_STORED = true;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "calibrate_wrpll_skew")]
+    /// Re-runs the skew calibration on the recovered clock and stores the result.
+    /// The previous offset is kept if the new one cannot be found or fails the skew test.
+    pub fn recalibrate_skew(timer: &mut GlobalTimer, cfg: &Config) -> Result<u32, &'static str> {
+        let previous = tag_collector::get_tag_offset();
+        calibrate_skew(timer)?;
+        #[cfg(wrpll_ref_clk = "GT_CDR")]
+        if let Err(e) = test_skew(timer) {
+            tag_collector::set_tag_offset(previous);
+            return Err(e);
+        }
+        unsafe {
+            TAG_OFFSET_STORED = false;
+        }
+        save_tag_offset(cfg)?;
+        Ok(tag_collector::get_tag_offset())
+    }
+
     pub fn select_recovered_clock(rc: bool, timer: &mut GlobalTimer) {
         set_isr(false);
 
         if rc {
             tag_collector::reset();
+            holdover::reset();
             reset_plls(timer).expect("failed to reset main and helper PLL");
 
             // get within capture range
@@ -704,7 +1040,19 @@ pub mod wrpll {
             info!("WRPLL interrupt enabled");
 
             #[cfg(feature = "calibrate_wrpll_skew")]
-            calibrate_skew(timer).expect("failed to set the correct skew");
+            {
+                // the stored offset may not fit anymore, e.g. after a gateware change
+                #[cfg(wrpll_ref_clk = "GT_CDR")]
+                if unsafe { TAG_OFFSET_STORED } && test_skew(timer).is_err() {
+                    warn!("stored tag offset failed the skew test, recalibrating");
+                    unsafe {
+                        TAG_OFFSET_STORED = false;
+                    }
+                }
+                if unsafe { !TAG_OFFSET_STORED } {
+                    calibrate_skew(timer).expect("failed to set the correct skew");
+                }
+            }
 
             #[cfg(wrpll_ref_clk = "GT_CDR")]
             test_skew(timer).expect("skew test failed");
@@ -716,19 +1064,7 @@ pub mod wrpll {
 pub mod wrpll_refclk {
     use super::*;
 
-    pub struct MmcmSetting {
-        pub clkout0_reg1: u16,  //0x08
-        pub clkout0_reg2: u16,  //0x09
-        pub clkfbout_reg1: u16, //0x14
-        pub clkfbout_reg2: u16, //0x15
-        pub div_reg: u16,       //0x16
-        pub lock_reg1: u16,     //0x18
-        pub lock_reg2: u16,     //0x19
-        pub lock_reg3: u16,     //0x1A
-        pub power_reg: u16,     //0x28
-        pub filt_reg1: u16,     //0x4E
-        pub filt_reg2: u16,     //0x4F
-    }
+    pub use clock_solver::mmcm::MmcmSetting;
 
     fn one_clock_cycle() {
         unsafe {
@@ -841,7 +1177,7 @@ pub mod wrpll_refclk {
 
             let locked = unsafe { csr::wrpll_refclk::mmcm_locked_read() == 1 };
             if !locked {
-                return Err("mmcm failed to generate 125MHz ref clock from SMA CLKIN");
+                return Err("mmcm failed to generate ref clock from SMA CLKIN");
             }
         }
 
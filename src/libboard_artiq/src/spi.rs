@@ -0,0 +1,17 @@
+//! SPI buses for the DRTIO SPI requests. The Zynq gateware exposes no SPI master
+//! core to the CPU, so every request fails until one is added; kernels are not
+//! given the `spi_*` functions meanwhile, and use the RTIO SPI masters instead.
+
+const UNSUPPORTED: &str = "SPI is not supported by this gateware";
+
+pub fn set_config(_busno: u8, _flags: u8, _length: u8, _div: u8, _cs: u8) -> Result<(), &'static str> {
+    Err(UNSUPPORTED)
+}
+
+pub fn write(_busno: u8, _data: u32) -> Result<(), &'static str> {
+    Err(UNSUPPORTED)
+}
+
+pub fn read(_busno: u8) -> Result<u32, &'static str> {
+    Err(UNSUPPORTED)
+}
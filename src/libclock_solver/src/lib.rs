@@ -0,0 +1,19 @@
+//! Divider solvers for the clock chips, kept free of hardware dependencies
+//! so that they can be tested on the host.
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod mmcm;
+pub mod si5324;
+pub mod si549;
+
+// `div_ceil` and `is_multiple_of` are not available on the firmware toolchain
+#[allow(clippy::manual_div_ceil)]
+fn div_ceil(n: u64, d: u64) -> u64 {
+    (n + d - 1) / d
+}
+
+#[allow(clippy::manual_is_multiple_of)]
+fn divides(d: u64, n: u64) -> bool {
+    n % d == 0
+}
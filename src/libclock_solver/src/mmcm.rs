@@ -0,0 +1,336 @@
+use core::cmp;
+
+use crate::divides;
+
+// VCO and phase detector ranges searched for the reference MMCM, in Hz.
+// The VCO is kept at or below the 1250MHz of the preconfigured settings.
+const VCO_MIN: u64 = 600_000_000;
+const VCO_MAX: u64 = 1_250_000_000;
+const PFD_MIN: u64 = 10_000_000;
+const PFD_MAX: u64 = 450_000_000;
+
+// Lock and loop filter tables from XAPP888, indexed by CLKFBOUT_MULT - 1
+const LOCK_CNT: [u16; 64] = [
+    1000, 1000, 1000, 1000, 1000, 1000, 1000, 1000, 1000, 1000, 900, 825, 750, 700, 650, 625, 575, 550, 525, 500,
+    475, 450, 425, 400, 400, 375, 350, 350, 325, 325, 300, 300, 300, 275, 275, 275, 250, 250, 250, 250, 250, 250,
+    250, 250, 250, 250, 250, 250, 250, 250, 250, 250, 250, 250, 250, 250, 250, 250, 250, 250, 250, 250, 250, 250,
+];
+const LOCK_DLY: [u16; 10] = [6, 6, 8, 11, 14, 17, 19, 22, 25, 28];
+const LOCK_SAT_HIGH: u16 = 1001;
+const UNLOCK_CNT: u16 = 1;
+// CP[3:0] RES[3:0] LFHF[1:0]
+const fn filter(cp: u16, res: u16, lfhf: u16) -> u16 {
+    (cp << 6) | (res << 2) | lfhf
+}
+
+// Optimized bandwidth
+const FILTER: [u16; 64] = [
+    filter(0b0010, 0b1111, 0b00),
+    filter(0b0100, 0b1111, 0b00),
+    filter(0b0101, 0b1011, 0b00),
+    filter(0b0111, 0b0111, 0b00),
+    filter(0b1101, 0b0111, 0b00),
+    filter(0b1110, 0b1011, 0b00),
+    filter(0b1110, 0b1101, 0b00),
+    filter(0b1111, 0b0011, 0b00),
+    filter(0b1110, 0b0101, 0b00),
+    filter(0b1111, 0b0101, 0b00),
+    filter(0b1111, 0b1001, 0b00),
+    filter(0b1101, 0b0001, 0b00),
+    filter(0b1111, 0b1001, 0b00),
+    filter(0b1111, 0b1001, 0b00),
+    filter(0b1111, 0b1001, 0b00),
+    filter(0b1111, 0b1001, 0b00),
+    filter(0b1111, 0b0101, 0b00),
+    filter(0b1111, 0b0101, 0b00),
+    filter(0b1100, 0b0001, 0b00),
+    filter(0b1100, 0b0001, 0b00),
+    filter(0b1100, 0b0001, 0b00),
+    filter(0b0101, 0b1100, 0b00),
+    filter(0b0101, 0b1100, 0b00),
+    filter(0b0101, 0b1100, 0b00),
+    filter(0b0101, 0b1100, 0b00),
+    filter(0b0011, 0b0100, 0b00),
+    filter(0b0011, 0b0100, 0b00),
+    filter(0b0011, 0b0100, 0b00),
+    filter(0b0011, 0b0100, 0b00),
+    filter(0b0011, 0b0100, 0b00),
+    filter(0b0011, 0b0100, 0b00),
+    filter(0b0011, 0b0100, 0b00),
+    filter(0b0011, 0b0100, 0b00),
+    filter(0b0011, 0b0100, 0b00),
+    filter(0b0011, 0b0100, 0b00),
+    filter(0b0011, 0b0100, 0b00),
+    filter(0b0011, 0b0100, 0b00),
+    filter(0b0011, 0b0100, 0b00),
+    filter(0b0011, 0b0100, 0b00),
+    filter(0b0011, 0b0100, 0b00),
+    filter(0b0011, 0b0100, 0b00),
+    filter(0b0010, 0b1000, 0b00),
+    filter(0b0010, 0b1000, 0b00),
+    filter(0b0010, 0b1000, 0b00),
+    filter(0b0010, 0b1000, 0b00),
+    filter(0b0010, 0b1000, 0b00),
+    filter(0b0111, 0b0001, 0b00),
+    filter(0b0111, 0b0001, 0b00),
+    filter(0b0100, 0b1100, 0b00),
+    filter(0b0100, 0b1100, 0b00),
+    filter(0b0100, 0b1100, 0b00),
+    filter(0b0100, 0b1100, 0b00),
+    filter(0b0110, 0b0001, 0b00),
+    filter(0b0110, 0b0001, 0b00),
+    filter(0b0101, 0b0110, 0b00),
+    filter(0b0101, 0b0110, 0b00),
+    filter(0b0010, 0b0100, 0b00),
+    filter(0b0010, 0b0100, 0b00),
+    filter(0b0100, 0b1010, 0b00),
+    filter(0b0100, 0b1010, 0b00),
+    filter(0b0100, 0b1010, 0b00),
+    filter(0b0100, 0b1010, 0b00),
+    filter(0b0011, 0b1100, 0b00),
+    filter(0b0011, 0b1100, 0b00),
+];
+
+// Bit 12 of the counter registers is reserved and reads back as 1.
+const COUNT_RESERVED: u16 = 0x1000;
+
+// Counter registers for an integer divider with 50% duty cycle and no phase shift
+fn count_regs(divide: u32) -> (u16, u16) {
+    if divide == 1 {
+        // NO_COUNT
+        return (COUNT_RESERVED | (1 << 6) | 1, 1 << 6);
+    }
+    let high = divide / 2;
+    let low = divide - high;
+    let edge = divide % 2;
+    (COUNT_RESERVED | ((high as u16) << 6) | low as u16, (edge as u16) << 7)
+}
+
+// Counter registers for a divider in 1/8 steps, as computed by mmcm_frac_count_calc
+fn frac_count_regs(divide_8: u32) -> (u16, u16) {
+    let (divide, frac) = (divide_8 / 8, divide_8 % 8);
+    let even_part = divide >> 1;
+    let odd = divide - 2 * even_part;
+    let odd_and_frac = 8 * odd + frac;
+    let lt_frac = even_part - (odd_and_frac <= 9) as u32;
+    let ht_frac = even_part - (odd_and_frac <= 8) as u32;
+    let wf_rise_frac = (1..=8).contains(&odd_and_frac) as u16;
+    let reg1 = COUNT_RESERVED | ((ht_frac as u16) << 6) | lt_frac as u16;
+    // EDGE is left set for odd integer parts, as in the preconfigured settings
+    let reg2 = ((frac as u16) << 12) | (1 << 11) | (wf_rise_frac << 10) | ((odd as u16) << 7);
+    (reg1, reg2)
+}
+
+#[derive(Debug, PartialEq)]
+pub struct MmcmSetting {
+    pub clkout0_reg1: u16,  //0x08
+    pub clkout0_reg2: u16,  //0x09
+    pub clkfbout_reg1: u16, //0x14
+    pub clkfbout_reg2: u16, //0x15
+    pub div_reg: u16,       //0x16
+    pub lock_reg1: u16,     //0x18
+    pub lock_reg2: u16,     //0x19
+    pub lock_reg3: u16,     //0x1A
+    pub power_reg: u16,     //0x28
+    pub filt_reg1: u16,     //0x4E
+    pub filt_reg2: u16,     //0x4F
+}
+
+impl MmcmSetting {
+    fn from_dividers(mult_8: u32, divclk: u32, clkout0: u32) -> MmcmSetting {
+        let (clkout0_reg1, clkout0_reg2) = count_regs(clkout0);
+        let fractional = !divides(8, mult_8 as u64);
+        let (clkfbout_reg1, clkfbout_reg2) = if fractional {
+            frac_count_regs(mult_8)
+        } else {
+            count_regs(mult_8 / 8)
+        };
+        let div_reg = if divclk == 1 {
+            (1 << 12) | (1 << 6) | 1
+        } else {
+            let high = divclk / 2;
+            ((divclk as u16 % 2) << 13) | ((high as u16) << 6) | (divclk - high) as u16
+        };
+
+        let index = (mult_8 / 8 - 1) as usize;
+        let lock_dly = if index < LOCK_DLY.len() { LOCK_DLY[index] } else { 31 };
+        let filter = FILTER[index];
+        let filter_bit = |bit: u16| (filter >> bit) & 1;
+
+        MmcmSetting {
+            clkout0_reg1,
+            clkout0_reg2,
+            clkfbout_reg1,
+            clkfbout_reg2,
+            div_reg,
+            lock_reg1: LOCK_CNT[index],
+            lock_reg2: (lock_dly << 10) | UNLOCK_CNT,
+            lock_reg3: 0x8000 | (lock_dly << 10) | LOCK_SAT_HIGH,
+            power_reg: if fractional { 0x9900 } else { 0x0100 },
+            filt_reg1: (filter_bit(9) << 15)
+                | (filter_bit(8) << 12)
+                | (filter_bit(7) << 11)
+                | (filter_bit(6) << 8)
+                | 0x08,
+            filt_reg2: (filter_bit(5) << 15)
+                | (filter_bit(4) << 12)
+                | (filter_bit(3) << 11)
+                | (filter_bit(2) << 8)
+                | (filter_bit(1) << 7)
+                | (filter_bit(0) << 4),
+        }
+    }
+
+    /// Computes the settings generating `out_freq` from `ref_freq` (in Hz),
+    /// preferring the highest VCO frequency and the lowest DIVCLK_DIVIDE.
+    pub fn new(ref_freq: u64, out_freq: u64) -> Result<MmcmSetting, &'static str> {
+        if ref_freq == 0 || out_freq == 0 {
+            return Err("mmcm frequencies must be non-zero");
+        }
+        for clkout0 in (1..=cmp::min(VCO_MAX / out_freq, 128)).rev() {
+            let vco = out_freq * clkout0;
+            if vco < VCO_MIN {
+                break;
+            }
+            for divclk in 1..=106 {
+                let pfd = ref_freq / divclk;
+                if pfd > PFD_MAX {
+                    continue;
+                }
+                if pfd < PFD_MIN {
+                    break;
+                }
+                // CLKFBOUT_MULT ranges from 2 to 64 in steps of 1/8
+                if !divides(ref_freq, 8 * vco * divclk) {
+                    continue;
+                }
+                let mult_8 = 8 * vco * divclk / ref_freq;
+                if (16..=512).contains(&mult_8) {
+                    return Ok(MmcmSetting::from_dividers(mult_8 as u32, divclk as u32, clkout0 as u32));
+                }
+            }
+        }
+        Err("mmcm cannot generate the requested frequency")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ext0_synth0_10to125() {
+        // CLKFBOUT_MULT = 62.5, DIVCLK_DIVIDE = 1 , CLKOUT0_DIVIDE = 5
+        assert_eq!(
+            MmcmSetting::new(10_000_000, 125_000_000),
+            Ok(MmcmSetting {
+                clkout0_reg1: 0x1083,
+                clkout0_reg2: 0x0080,
+                clkfbout_reg1: 0x179e,
+                clkfbout_reg2: 0x4c00,
+                div_reg: 0x1041,
+                lock_reg1: 0x00fa,
+                lock_reg2: 0x7c01,
+                lock_reg3: 0xffe9,
+                power_reg: 0x9900,
+                filt_reg1: 0x1008,
+                filt_reg2: 0x8800,
+            })
+        );
+    }
+
+    #[test]
+    fn ext0_synth0_80to125() {
+        // CLKFBOUT_MULT = 15.625, DIVCLK_DIVIDE = 1 , CLKOUT0_DIVIDE = 10
+        assert_eq!(
+            MmcmSetting::new(80_000_000, 125_000_000),
+            Ok(MmcmSetting {
+                clkout0_reg1: 0x1145,
+                clkout0_reg2: 0x0000,
+                clkfbout_reg1: 0x11c7,
+                clkfbout_reg2: 0x5880,
+                div_reg: 0x1041,
+                lock_reg1: 0x028a,
+                lock_reg2: 0x7c01,
+                lock_reg3: 0xffe9,
+                power_reg: 0x9900,
+                filt_reg1: 0x9908,
+                filt_reg2: 0x8100,
+            })
+        );
+    }
+
+    #[test]
+    fn ext0_synth0_100to125() {
+        // CLKFBOUT_MULT = 12.5, DIVCLK_DIVIDE = 1 , CLKOUT0_DIVIDE = 10
+        assert_eq!(
+            MmcmSetting::new(100_000_000, 125_000_000),
+            Ok(MmcmSetting {
+                clkout0_reg1: 0x1145,
+                clkout0_reg2: 0x0000,
+                clkfbout_reg1: 0x1145,
+                clkfbout_reg2: 0x4c00,
+                div_reg: 0x1041,
+                lock_reg1: 0x0339,
+                lock_reg2: 0x7c01,
+                lock_reg3: 0xffe9,
+                power_reg: 0x9900,
+                filt_reg1: 0x9108,
+                filt_reg2: 0x0100,
+            })
+        );
+    }
+
+    #[test]
+    fn ext0_synth0_125to125() {
+        // CLKFBOUT_MULT = 10, DIVCLK_DIVIDE = 1 , CLKOUT0_DIVIDE = 10
+        assert_eq!(
+            MmcmSetting::new(125_000_000, 125_000_000),
+            Ok(MmcmSetting {
+                clkout0_reg1: 0x1145,
+                clkout0_reg2: 0x0000,
+                clkfbout_reg1: 0x1145,
+                clkfbout_reg2: 0x0000,
+                div_reg: 0x1041,
+                lock_reg1: 0x03e8,
+                lock_reg2: 0x7001,
+                lock_reg3: 0xf3e9,
+                power_reg: 0x0100,
+                filt_reg1: 0x9908,
+                filt_reg2: 0x1100,
+            })
+        );
+    }
+
+    #[test]
+    fn ext0_synth0_50to125() {
+        // CLKFBOUT_MULT = 25, DIVCLK_DIVIDE = 1 , CLKOUT0_DIVIDE = 10
+        assert_eq!(
+            MmcmSetting::new(50_000_000, 125_000_000),
+            Ok(MmcmSetting {
+                clkout0_reg1: 0x1145,
+                clkout0_reg2: 0x0000,
+                clkfbout_reg1: 0x130d,
+                clkfbout_reg2: 0x0080,
+                div_reg: 0x1041,
+                lock_reg1: 0x0190,
+                lock_reg2: 0x7c01,
+                lock_reg3: 0xffe9,
+                power_reg: 0x0100,
+                filt_reg1: 0x1108,
+                filt_reg2: 0x9000,
+            })
+        );
+    }
+
+    #[test]
+    fn unreachable_frequency() {
+        assert!(MmcmSetting::new(1_000_000, 125_000_000).is_err());
+    }
+
+    #[test]
+    fn zero_frequency_is_rejected() {
+        assert!(MmcmSetting::new(0, 125_000_000).is_err());
+        assert!(MmcmSetting::new(10_000_000, 0).is_err());
+    }
+}
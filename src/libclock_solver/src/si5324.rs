@@ -0,0 +1,280 @@
+use core::{cmp, result};
+
+use crate::{div_ceil, divides};
+
+type Result<T> = result::Result<T, &'static str>;
+
+// NOTE: the logical parameters DO NOT MAP to physical values written
+// into registers. They have to be mapped; see the datasheet.
+// DSPLLsim reports the logical parameters in the design summary, not
+// the physical register values.
+#[derive(Debug, PartialEq)]
+pub struct FrequencySettings {
+    pub n1_hs: u8,
+    pub nc1_ls: u32,
+    pub n2_hs: u8,
+    pub n2_ls: u32,
+    pub n31: u32,
+    pub n32: u32,
+    pub bwsel: u8,
+    pub crystal_as_ckin2: bool,
+}
+
+/// Frequency of the XA/XB crystal when used as CKIN2, in Hz
+pub const CRYSTAL_FREQ: u64 = 114_285_000;
+
+// Phase detector and VCO limits from the datasheet, in Hz
+const F3_MIN: u64 = 2_000;
+const F3_MAX: u64 = 2_000_000;
+const FOSC_MIN: u64 = 4_850_000_000;
+const FOSC_MAX: u64 = 5_670_000_000;
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        let t = a % b;
+        a = b;
+        b = t;
+    }
+    a
+}
+
+impl FrequencySettings {
+    /// Checks the dividers against the ranges the registers accept.
+    pub fn check_dividers(&self) -> Result<()> {
+        if self.n1_hs < 4 || self.n1_hs > 11 || self.n2_hs < 4 || self.n2_hs > 11 {
+            return Err("N1_HS and N2_HS must be between 4 and 11");
+        }
+        if self.nc1_ls == 0 || (self.nc1_ls != 1 && self.nc1_ls % 2 == 1) || self.nc1_ls > (1 << 20) {
+            return Err("NC1_LS must be 1 or even, up to 2^20");
+        }
+        if self.n2_ls == 0 || self.n2_ls % 2 == 1 || self.n2_ls > (1 << 20) {
+            return Err("N2_LS must be even, up to 2^20");
+        }
+        if self.n31 == 0 || self.n31 > (1 << 19) || self.n32 == 0 || self.n32 > (1 << 19) {
+            return Err("N31 and N32 must be between 1 and 2^19");
+        }
+        Ok(())
+    }
+
+    /// Checks the dividers against the datasheet limits for an input of `fin` Hz.
+    pub fn check(&self, fin: u64) -> Result<()> {
+        self.check_dividers()?;
+        let n3 = self.n31 as u64;
+        if fin < F3_MIN * n3 || fin > F3_MAX * n3 {
+            return Err("phase detector frequency out of range");
+        }
+        let n2 = self.n2_hs as u64 * self.n2_ls as u64;
+        if fin * n2 < FOSC_MIN * n3 || fin * n2 > FOSC_MAX * n3 {
+            return Err("VCO frequency out of range");
+        }
+        Ok(())
+    }
+
+    /// Finds dividers generating exactly `fout` from `fin` (in Hz), with the
+    /// lowest VCO frequency and the highest phase detector frequency possible.
+    pub fn solve(fin: u64, fout: u64, bwsel: u8, crystal_as_ckin2: bool) -> Result<FrequencySettings> {
+        if fin == 0 || fout == 0 {
+            return Err("Si5324 frequencies must be non-zero");
+        }
+        for n1 in div_ceil(FOSC_MIN, fout)..=FOSC_MAX / fout {
+            // prefer the highest N1_HS
+            let n1_hs = (4..=11)
+                .rev()
+                .find(|&hs| divides(hs, n1) && (n1 / hs == 1 || divides(2, n1 / hs)));
+            let n1_hs = match n1_hs {
+                Some(n1_hs) => n1_hs,
+                None => continue,
+            };
+            // N2/N3 = fosc/fin = a/b
+            let fosc = fout * n1;
+            let g = gcd(fosc, fin);
+            let (a, b) = (fosc / g, fin / g);
+            let k_min = cmp::max(div_ceil(fin, F3_MAX * b), 1);
+            let mut k = k_min;
+            while b * k <= (1 << 19) && fin >= F3_MIN * b * k {
+                let n2 = a * k;
+                if let Some(n2_hs) = (4..=11).rev().find(|&hs| divides(hs, n2) && divides(2, n2 / hs)) {
+                    let settings = FrequencySettings {
+                        n1_hs: n1_hs as u8,
+                        nc1_ls: (n1 / n1_hs) as u32,
+                        n2_hs: n2_hs as u8,
+                        n2_ls: (n2 / n2_hs) as u32,
+                        n31: (b * k) as u32,
+                        n32: (b * k) as u32,
+                        bwsel,
+                        crystal_as_ckin2,
+                    };
+                    if settings.check(fin).is_ok() {
+                        return Ok(settings);
+                    }
+                }
+                k += 1;
+            }
+        }
+        Err("Si5324 cannot generate the requested frequency")
+    }
+}
+
+/// Maps the logical dividers to the values written into the registers.
+pub fn map_frequency_settings(settings: &FrequencySettings) -> Result<FrequencySettings> {
+    settings.check_dividers()?;
+    let r = FrequencySettings {
+        n1_hs: settings.n1_hs - 4,
+        nc1_ls: settings.nc1_ls - 1,
+        n2_hs: settings.n2_hs - 4,
+        n2_ls: settings.n2_ls - 1,
+        n31: settings.n31 - 1,
+        n32: settings.n32 - 1,
+        bwsel: settings.bwsel,
+        crystal_as_ckin2: settings.crystal_as_ckin2,
+    };
+    Ok(r)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MHZ: u64 = 1_000_000;
+
+    fn settings(n1_hs: u8, nc1_ls: u32, n2_hs: u8, n2_ls: u32, n3: u32, bwsel: u8) -> FrequencySettings {
+        FrequencySettings {
+            n1_hs,
+            nc1_ls,
+            n2_hs,
+            n2_ls,
+            n31: n3,
+            n32: n3,
+            bwsel,
+            crystal_as_ckin2: false,
+        }
+    }
+
+    // (input, output, settings) from the runtime and satman tables
+    fn existing_settings() -> [(u64, u64, FrequencySettings); 8] {
+        [
+            (10 * MHZ, 125 * MHZ, settings(10, 4, 10, 300, 6, 4)),
+            (80 * MHZ, 125 * MHZ, settings(4, 10, 10, 250, 40, 4)),
+            (100 * MHZ, 125 * MHZ, settings(10, 4, 10, 260, 52, 4)),
+            (125 * MHZ, 125 * MHZ, settings(5, 8, 7, 360, 63, 4)),
+            (CRYSTAL_FREQ, 150 * MHZ, settings(9, 4, 10, 33732, 7139, 3)),
+            (CRYSTAL_FREQ, 100 * MHZ, settings(9, 6, 10, 33732, 7139, 3)),
+            (CRYSTAL_FREQ, 125 * MHZ, settings(10, 4, 10, 19972, 4565, 4)),
+            (100 * MHZ, 100 * MHZ, settings(5, 10, 10, 250, 50, 4)),
+        ]
+    }
+
+    // Solved dividers for each table entry. They run the VCO at the same
+    // frequency as the tables, except from the crystal to 100MHz where
+    // the solver picks 5GHz over 5.4GHz.
+    fn solved_settings() -> [FrequencySettings; 8] {
+        [
+            settings(10, 4, 10, 250, 5, 4),
+            settings(10, 4, 10, 250, 40, 4),
+            settings(10, 4, 10, 250, 50, 4),
+            settings(10, 4, 10, 252, 63, 4),
+            settings(9, 4, 10, 36000, 7619, 3),
+            settings(5, 10, 10, 100000, 22857, 3),
+            settings(10, 4, 10, 100000, 22857, 4),
+            settings(5, 10, 10, 250, 50, 4),
+        ]
+    }
+
+    fn output_frequency(s: &FrequencySettings, fin: u64) -> f64 {
+        let n1 = s.n1_hs as f64 * s.nc1_ls as f64;
+        let n2 = s.n2_hs as f64 * s.n2_ls as f64;
+        fin as f64 * n2 / (s.n31 as f64 * n1)
+    }
+
+    fn vco_frequency(s: &FrequencySettings, fin: u64) -> f64 {
+        fin as f64 * s.n2_hs as f64 * s.n2_ls as f64 / s.n31 as f64
+    }
+
+    #[test]
+    fn existing_settings_are_valid() {
+        for (fin, fout, s) in existing_settings().iter() {
+            assert_eq!(s.check(*fin), Ok(()));
+            assert!(map_frequency_settings(s).is_ok());
+            let error = (output_frequency(s, *fin) - *fout as f64).abs() / *fout as f64;
+            // the crystal settings are only accurate to within a fraction of a ppm
+            assert!(error < 1e-6, "{} -> {}: relative error {}", fin, fout, error);
+        }
+    }
+
+    #[test]
+    fn solver_covers_existing_settings() {
+        for ((fin, fout, s), expected) in existing_settings().iter().zip(solved_settings().iter()) {
+            let solved = FrequencySettings::solve(*fin, *fout, s.bwsel, s.crystal_as_ckin2).unwrap();
+            assert_eq!(&solved, expected, "{} -> {}", fin, fout);
+            assert_eq!(solved.check(*fin), Ok(()));
+            let n1 = solved.n1_hs as u64 * solved.nc1_ls as u64;
+            let n2 = solved.n2_hs as u64 * solved.n2_ls as u64;
+            assert_eq!(*fin * n2, *fout * n1 * solved.n31 as u64);
+        }
+    }
+
+    #[test]
+    fn solver_matches_existing_settings() {
+        for ((fin, fout, s), expected) in existing_settings().iter().zip(solved_settings().iter()) {
+            let computed = FrequencySettings::solve(*fin, *fout, s.bwsel, s.crystal_as_ckin2).unwrap();
+            assert_eq!(&computed, expected, "{} -> {}", fin, fout);
+            let error = (vco_frequency(&computed, *fin) - vco_frequency(s, *fin)).abs() / vco_frequency(s, *fin);
+            if *fin != CRYSTAL_FREQ || *fout != 100 * MHZ {
+                assert!(error < 1e-6, "{} -> {}: VCO relative error {}", fin, fout, error);
+            }
+            let error = (output_frequency(&computed, *fin) - output_frequency(s, *fin)).abs() / *fout as f64;
+            assert!(error < 1e-6, "{} -> {}: relative error {}", fin, fout, error);
+        }
+    }
+
+    #[test]
+    fn solved_settings_can_be_mapped() {
+        let inputs = [10 * MHZ, 25 * MHZ, 80 * MHZ, 100 * MHZ, 125 * MHZ, CRYSTAL_FREQ];
+        let outputs = [
+            50 * MHZ,
+            100 * MHZ,
+            125 * MHZ,
+            150 * MHZ,
+            200 * MHZ,
+            250 * MHZ,
+            500 * MHZ,
+            1_000 * MHZ,
+        ];
+        for fin in inputs.iter() {
+            for fout in outputs.iter() {
+                if let Ok(s) = FrequencySettings::solve(*fin, *fout, 4, false) {
+                    assert_eq!(map_frequency_settings(&s).err(), None, "{} -> {}", fin, fout);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn nc1_ls_of_one_is_mapped() {
+        let s = FrequencySettings::solve(125 * MHZ, 500 * MHZ, 4, false).unwrap();
+        assert_eq!(s.nc1_ls, 1);
+        assert_eq!(map_frequency_settings(&s).unwrap().nc1_ls, 0);
+    }
+
+    #[test]
+    fn odd_nc1_ls_is_rejected() {
+        let s = settings(10, 3, 10, 300, 6, 4);
+        assert!(s.check_dividers().is_err());
+        assert!(map_frequency_settings(&s).is_err());
+    }
+
+    #[test]
+    fn solver_picks_lowest_vco() {
+        let s = FrequencySettings::solve(10 * MHZ, 125 * MHZ, 4, false).unwrap();
+        assert_eq!((s.n1_hs, s.nc1_ls), (10, 4));
+        assert_eq!((s.n2_hs, s.n2_ls, s.n31), (10, 250, 5));
+    }
+
+    #[test]
+    fn solver_rejects_out_of_range() {
+        // phase detector cannot go below 2kHz
+        assert!(FrequencySettings::solve(1_000, 125 * MHZ, 4, false).is_err());
+        // no divider reaches the VCO range
+        assert!(FrequencySettings::solve(10 * MHZ, 6_000 * MHZ, 4, false).is_err());
+    }
+}
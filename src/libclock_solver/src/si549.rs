@@ -0,0 +1,201 @@
+use core::cmp;
+
+use crate::div_ceil;
+
+// Crystal frequency and DCO range from the Si549 datasheet, in Hz
+const XTAL_FREQ: u64 = 152_600_000;
+const DCO_MIN: u64 = 10_800_000_000;
+const DCO_MAX: u64 = 12_109_000_000;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct DividerConfig {
+    pub hsdiv: u16,
+    pub lsdiv: u8,
+    pub fbdiv: u64,
+}
+
+impl DividerConfig {
+    // Picks the lowest DCO frequency reachable by the output dividers.
+    fn output_dividers(freq: u64) -> Result<(u16, u8), &'static str> {
+        if freq == 0 {
+            return Err("Si549 frequency must be non-zero");
+        }
+        for lsdiv in 0..=5 {
+            let out = freq << lsdiv;
+            let mut hsdiv = cmp::max(div_ceil(DCO_MIN, out), 5);
+            // HSDIV values above 33 must be even
+            if hsdiv > 33 && hsdiv % 2 == 1 {
+                hsdiv += 1;
+            }
+            if hsdiv <= 2046 && out * hsdiv <= DCO_MAX {
+                return Ok((hsdiv as u16, lsdiv));
+            }
+        }
+        Err("Si549 cannot generate the requested frequency")
+    }
+
+    // Feedback divider for an output of freq * num / den Hz, as 11.32 fixed point.
+    fn feedback_divider(freq: u64, hsdiv: u16, lsdiv: u8, num: u64, den: u64) -> u64 {
+        let dco = ((freq as u128 * hsdiv as u128) << lsdiv) * num as u128;
+        let div = den as u128 * XTAL_FREQ as u128;
+        (((dco << 32) + div / 2) / div) as u64
+    }
+
+    pub fn new(freq: u64) -> Result<DividerConfig, &'static str> {
+        let (hsdiv, lsdiv) = DividerConfig::output_dividers(freq)?;
+        Ok(DividerConfig {
+            hsdiv,
+            lsdiv,
+            fbdiv: DividerConfig::feedback_divider(freq, hsdiv, lsdiv, 1, 1),
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct FrequencySetting {
+    pub main: DividerConfig,
+    pub helper: DividerConfig,
+}
+
+// Settings shipped for the usual RTIO frequencies, as (frequency, setting).
+// Their feedback dividers were rounded differently and are up to 16 LSB
+// (a few parts in 10^12) away from the solved ones. They are kept as they are so that
+// the output frequencies do not change for existing systems.
+const PRECONFIGURED: [(u64, FrequencySetting); 2] = [
+    (
+        125_000_000,
+        FrequencySetting {
+            main: DividerConfig {
+                hsdiv: 0x058,
+                lsdiv: 0,
+                fbdiv: 0x04815791F25,
+            },
+            helper: DividerConfig {
+                hsdiv: 0x058,
+                lsdiv: 0,
+                fbdiv: 0x04814E8F442,
+            },
+        },
+    ),
+    (
+        100_000_000,
+        FrequencySetting {
+            main: DividerConfig {
+                hsdiv: 0x06C,
+                lsdiv: 0,
+                fbdiv: 0x046C5F49797,
+            },
+            helper: DividerConfig {
+                hsdiv: 0x06C,
+                lsdiv: 0,
+                fbdiv: 0x046C5670BBD,
+            },
+        },
+    ),
+];
+
+impl FrequencySetting {
+    /// Returns the DCXO settings for an RTIO frequency in Hz, using the
+    /// preconfigured settings when there are some and solving otherwise.
+    pub fn new(freq: u64) -> Result<FrequencySetting, &'static str> {
+        match PRECONFIGURED.iter().find(|(f, _)| *f == freq) {
+            Some((_, setting)) => Ok(*setting),
+            None => FrequencySetting::solve(freq),
+        }
+    }
+
+    /// Computes the DCXO settings for an RTIO frequency in Hz. The helper runs
+    /// at freq*32767/32768, sharing the output dividers of the main DCXO.
+    pub fn solve(freq: u64) -> Result<FrequencySetting, &'static str> {
+        let main = DividerConfig::new(freq)?;
+        let helper = DividerConfig {
+            hsdiv: main.hsdiv,
+            lsdiv: main.lsdiv,
+            fbdiv: DividerConfig::feedback_divider(freq, main.hsdiv, main.lsdiv, 32767, 32768),
+        };
+        Ok(FrequencySetting { main, helper })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Largest distance between the solved and the preconfigured feedback dividers.
+    const FBDIV_TOLERANCE: u64 = 16;
+
+    fn check(computed: &DividerConfig, expected: &DividerConfig) {
+        assert_eq!(computed.hsdiv, expected.hsdiv);
+        assert_eq!(computed.lsdiv, expected.lsdiv);
+        let error = cmp::max(computed.fbdiv, expected.fbdiv) - cmp::min(computed.fbdiv, expected.fbdiv);
+        assert!(
+            error <= FBDIV_TOLERANCE,
+            "fbdiv {:#x} != {:#x}",
+            computed.fbdiv,
+            expected.fbdiv
+        );
+    }
+
+    #[test]
+    fn solver_matches_preconfigured() {
+        for (freq, expected) in PRECONFIGURED.iter() {
+            let solved = FrequencySetting::solve(*freq).unwrap();
+            check(&solved.main, &expected.main);
+            check(&solved.helper, &expected.helper);
+        }
+    }
+
+    #[test]
+    fn solved_dividers() {
+        let setting = |hsdiv, main_fbdiv, helper_fbdiv| FrequencySetting {
+            main: DividerConfig {
+                hsdiv,
+                lsdiv: 0,
+                fbdiv: main_fbdiv,
+            },
+            helper: DividerConfig {
+                hsdiv,
+                lsdiv: 0,
+                fbdiv: helper_fbdiv,
+            },
+        };
+        assert_eq!(
+            FrequencySetting::solve(125_000_000),
+            Ok(setting(0x058, 0x04815791F34, 0x04814E8F442))
+        );
+        assert_eq!(
+            FrequencySetting::solve(100_000_000),
+            Ok(setting(0x06C, 0x046C5F497A7, 0x046C5670BBE))
+        );
+        assert_eq!(
+            FrequencySetting::solve(150_000_000),
+            Ok(setting(0x048, 0x046C5F497A7, 0x046C5670BBE))
+        );
+    }
+
+    #[test]
+    fn preconfigured_frequencies_are_kept() {
+        for (freq, expected) in PRECONFIGURED.iter() {
+            assert_eq!(FrequencySetting::new(*freq), Ok(*expected));
+        }
+    }
+
+    #[test]
+    fn other_frequencies_are_solved() {
+        assert_eq!(FrequencySetting::new(150_000_000), FrequencySetting::solve(150_000_000));
+    }
+
+    #[test]
+    fn low_frequency_uses_lsdiv() {
+        let config = DividerConfig::new(5_000_000).unwrap();
+        assert_eq!(config.lsdiv, 1);
+        let dco = (5_000_000u64 * config.hsdiv as u64) << config.lsdiv;
+        assert!((DCO_MIN..=DCO_MAX).contains(&dco));
+    }
+
+    #[test]
+    fn zero_frequency_is_rejected() {
+        assert!(DividerConfig::new(0).is_err());
+        assert!(FrequencySetting::new(0).is_err());
+    }
+}
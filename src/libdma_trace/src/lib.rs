@@ -0,0 +1,191 @@
+//! Helpers for the RTIO DMA trace format, kept free of hardware dependencies
+//! so that they can be tested on the host.
+
+#![cfg_attr(not(test), no_std)]
+
+// See gateware/rtio/dma.py.
+pub const HEADER_LENGTH: usize = /*length*/ 1 + /*channel*/ 3 + /*timestamp*/ 8 + /*address*/ 1;
+
+/// Largest number of data words in an event, as accepted by the gateware
+pub const MAX_WORDS: usize = 16;
+
+/// Checks that a trace built outside of a kernel consists of whole events,
+/// each carrying between one and `MAX_WORDS` data words.
+pub fn validate_trace(buffer: &[u8]) -> Result<(), &'static str> {
+    let mut ptr = 0;
+    while ptr < buffer.len() {
+        let len = buffer[ptr] as usize;
+        if len < HEADER_LENGTH + 4 || (len - HEADER_LENGTH) & 3 != 0 {
+            return Err("invalid event length");
+        }
+        if (len - HEADER_LENGTH) / 4 > MAX_WORDS {
+            return Err("too many data words in event");
+        }
+        if ptr + len > buffer.len() {
+            return Err("truncated event");
+        }
+        ptr += len;
+    }
+    Ok(())
+}
+
+/// Finds the offset of data word `word` of the event on `target` at `timestamp`
+/// in a trace terminated by a zero length.
+pub fn find_event_word(trace: &[u8], target: u32, timestamp: u64, word: usize) -> Option<usize> {
+    if word >= MAX_WORDS {
+        return None;
+    }
+    let mut ptr = 0;
+    while ptr < trace.len() && trace[ptr] != 0 {
+        let len = trace[ptr] as usize;
+        let event_target = (trace[ptr + 1] as u32) << 8
+            | (trace[ptr + 2] as u32) << 16
+            | (trace[ptr + 3] as u32) << 24
+            | trace[ptr + 12] as u32;
+        let event_timestamp = (0..8).fold(0u64, |acc, i| acc | (trace[ptr + 4 + i] as u64) << (8 * i));
+        if event_target == target && event_timestamp == timestamp {
+            let offset = ptr + HEADER_LENGTH + 4 * word;
+            return if offset + 4 <= ptr + len { Some(offset) } else { None };
+        }
+        ptr += len;
+    }
+    None
+}
+
+/// Overwrites a data word of a trace, returning its previous value.
+pub fn patch_word(trace: &mut [u8], offset: usize, value: u32) -> u32 {
+    let mut old = 0;
+    for i in 0..4 {
+        old |= (trace[offset + i] as u32) << (8 * i);
+        trace[offset + i] = (value >> (8 * i)) as u8;
+    }
+    old
+}
+
+/// Restores the words saved by `patch_word()`, given as offsets and
+/// previous values in the order they were patched.
+pub fn revert_patches(trace: &mut [u8], saved: &[(usize, u32)]) {
+    // in reverse, in case a word was patched more than once
+    for (offset, word) in saved.iter().rev() {
+        patch_word(trace, *offset, *word);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(target: u32, timestamp: u64, data: &[u32]) -> Vec<u8> {
+        let mut event = vec![(HEADER_LENGTH + 4 * data.len()) as u8];
+        event.extend_from_slice(&target.to_le_bytes()[1..]);
+        event.extend_from_slice(&timestamp.to_le_bytes());
+        event.push(target as u8);
+        for word in data.iter() {
+            event.extend_from_slice(&word.to_le_bytes());
+        }
+        event
+    }
+
+    #[test]
+    fn valid_trace_is_accepted() {
+        let mut trace = event(0x000100, 0, &[1]);
+        trace.extend(event(0x000200, 8, &[0; MAX_WORDS]));
+        assert_eq!(validate_trace(&trace), Ok(()));
+        assert_eq!(validate_trace(&[]), Ok(()));
+    }
+
+    #[test]
+    fn truncated_trace_is_rejected() {
+        let mut trace = event(0x000100, 0, &[1]);
+        trace.extend(event(0x000200, 8, &[1, 2]));
+        trace.pop();
+        assert_eq!(validate_trace(&trace), Err("truncated event"));
+    }
+
+    #[test]
+    fn invalid_event_length_is_rejected() {
+        // no data word
+        let mut trace = event(0x000100, 0, &[]);
+        assert_eq!(validate_trace(&trace), Err("invalid event length"));
+        // partial data word
+        trace = event(0x000100, 0, &[1]);
+        trace[0] -= 1;
+        trace.pop();
+        assert_eq!(validate_trace(&trace), Err("invalid event length"));
+    }
+
+    #[test]
+    fn oversized_event_is_rejected() {
+        let trace = event(0x000100, 0, &[0; MAX_WORDS + 1]);
+        assert_eq!(validate_trace(&trace), Err("too many data words in event"));
+    }
+
+    // two events on channel 1 and one on channel 2 of destination 3, with the end marker
+    fn trace() -> Vec<u8> {
+        let mut trace = event(0x03000100, 0, &[0x11111111]);
+        trace.extend(event(0x03000200, 0, &[0x22222222, 0x33333333]));
+        trace.extend(event(0x03000100, 8, &[0x44444444]));
+        trace.push(0);
+        trace
+    }
+
+    fn read_word(trace: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes([trace[offset], trace[offset + 1], trace[offset + 2], trace[offset + 3]])
+    }
+
+    #[test]
+    fn event_words_are_found() {
+        let trace = trace();
+        let offset = find_event_word(&trace, 0x03000100, 0, 0).unwrap();
+        assert_eq!(read_word(&trace, offset), 0x11111111);
+        let offset = find_event_word(&trace, 0x03000200, 0, 1).unwrap();
+        assert_eq!(read_word(&trace, offset), 0x33333333);
+        let offset = find_event_word(&trace, 0x03000100, 8, 0).unwrap();
+        assert_eq!(read_word(&trace, offset), 0x44444444);
+    }
+
+    #[test]
+    fn missing_event_words_are_not_found() {
+        let trace = trace();
+        // no such timestamp, channel or destination
+        assert_eq!(find_event_word(&trace, 0x03000100, 4, 0), None);
+        assert_eq!(find_event_word(&trace, 0x03000300, 0, 0), None);
+        assert_eq!(find_event_word(&trace, 0x04000100, 0, 0), None);
+        // word past the end of the event
+        assert_eq!(find_event_word(&trace, 0x03000100, 0, 1), None);
+        assert_eq!(find_event_word(&trace, 0x03000200, 0, 2), None);
+        // word past any event, as from a negative kernel word
+        assert_eq!(find_event_word(&trace, 0x03000100, 0, MAX_WORDS), None);
+        assert_eq!(find_event_word(&trace, 0x03000100, 0, -1i32 as usize), None);
+    }
+
+    #[test]
+    fn patched_word_is_replaced() {
+        let mut trace = trace();
+        let original = trace.clone();
+        let offset = find_event_word(&trace, 0x03000200, 0, 1).unwrap();
+        assert_eq!(patch_word(&mut trace, offset, 0xdeadbeef), 0x33333333);
+        assert_eq!(read_word(&trace, offset), 0xdeadbeef);
+        // nothing else changes
+        for i in (0..trace.len()).filter(|i| *i < offset || *i >= offset + 4) {
+            assert_eq!(trace[i], original[i]);
+        }
+    }
+
+    #[test]
+    fn patches_are_reverted() {
+        let mut trace = trace();
+        let original = trace.clone();
+        let first = find_event_word(&trace, 0x03000100, 0, 0).unwrap();
+        let second = find_event_word(&trace, 0x03000100, 8, 0).unwrap();
+        let mut saved = vec![
+            (first, patch_word(&mut trace, first, 1)),
+            (second, patch_word(&mut trace, second, 2)),
+        ];
+        // the same word patched again
+        saved.push((first, patch_word(&mut trace, first, 3)));
+        assert_ne!(trace, original);
+        revert_patches(&mut trace, &saved);
+        assert_eq!(trace, original);
+    }
+}
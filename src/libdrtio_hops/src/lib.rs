@@ -0,0 +1,68 @@
+//! Hop lookups in DRTIO routing tables, kept free of hardware dependencies
+//! so that they can be tested on the host.
+
+#![cfg_attr(not(test), no_std)]
+
+/// Repeater leading to a destination from a satellite at `rank` with
+/// `repeater_count` repeaters, if the destination is within its subtree.
+/// `hops` is the routing table entry of the destination: hops are 1-based
+/// repeater numbers, 0 meaning the destination is not below the satellite.
+pub fn downstream_repno(hops: &[u8], rank: u8, repeater_count: usize) -> Option<usize> {
+    let hop = hops[rank as usize] as usize;
+    if hop > 0 && hop <= repeater_count {
+        Some(hop - 1)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INVALID_HOP: u8 = 0xff;
+
+    // master - satellite 1 (rank 1, two repeaters)
+    //            repeater 1 - satellite 2
+    //            repeater 2 - satellite 3 (rank 2, one repeater)
+    //                           repeater 1 - satellite 4
+    fn routing_table() -> [[u8; 5]; 5] {
+        [
+            [0, INVALID_HOP, INVALID_HOP, INVALID_HOP, INVALID_HOP],
+            [1, 0, INVALID_HOP, INVALID_HOP, INVALID_HOP],
+            [1, 1, 0, INVALID_HOP, INVALID_HOP],
+            [1, 2, 0, INVALID_HOP, INVALID_HOP],
+            [1, 2, 1, 0, INVALID_HOP],
+        ]
+    }
+
+    #[test]
+    fn subtree_destinations_are_routed_down() {
+        let table = routing_table();
+        assert_eq!(downstream_repno(&table[2], 1, 2), Some(0));
+        assert_eq!(downstream_repno(&table[4], 1, 2), Some(1));
+    }
+
+    #[test]
+    fn last_hop_uses_last_repeater() {
+        let table = routing_table();
+        // destinations right behind the last (or only) repeater of a satellite
+        assert_eq!(downstream_repno(&table[3], 1, 2), Some(1));
+        assert_eq!(downstream_repno(&table[4], 2, 1), Some(0));
+    }
+
+    #[test]
+    fn other_destinations_are_routed_up() {
+        let table = routing_table();
+        // self, parent and sibling subtree
+        assert_eq!(downstream_repno(&table[1], 1, 2), None);
+        assert_eq!(downstream_repno(&table[0], 1, 2), None);
+        assert_eq!(downstream_repno(&table[2], 2, 1), None);
+    }
+
+    #[test]
+    fn missing_repeaters_are_not_used() {
+        let table = routing_table();
+        assert_eq!(downstream_repno(&table[3], 1, 1), None);
+    }
+}
@@ -5,6 +5,9 @@ version = "0.1.0"
 authors = ["M-Labs"]
 edition = "2018"
 
+[features]
+target_kasli_soc = ["libboard_artiq/target_kasli_soc"]
+
 [build-dependencies]
 build_zynq = { path = "../libbuild_zynq" }
 
@@ -26,6 +29,7 @@ libasync = { path = "@@ZYNQ_RS@@/libasync" }
 libregister = { path = "@@ZYNQ_RS@@/libregister" }
 libconfig = { path = "@@ZYNQ_RS@@/libconfig", features = ["fat_lfn", "ipv6"] }
 
+dma_trace = { path = "../libdma_trace" }
 dyld = { path = "../libdyld" }
 dwarf = { path = "../libdwarf" }
 unwind = { path = "../libunwind" }
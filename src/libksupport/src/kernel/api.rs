@@ -5,6 +5,10 @@ use libc::{c_char, c_int, size_t};
 use libm;
 use log::{info, warn};
 
+#[cfg(has_grabber)]
+use super::grabber;
+#[cfg(feature = "target_kasli_soc")]
+use super::io_expander;
 #[cfg(has_drtio)]
 use super::subkernel;
 use super::{cache,
@@ -104,6 +108,9 @@ pub fn resolve(required: &[u8]) -> Option<u32> {
         api!(dma_erase = dma::dma_erase),
         api!(dma_retrieve = dma::dma_retrieve),
         api!(dma_playback = dma::dma_playback),
+        api!(dma_playback_sequence = dma::dma_playback_sequence),
+        api!(dma_playback_start = dma::dma_playback_start),
+        api!(dma_playback_await = dma::dma_playback_await),
 
         // cache
         api!(cache_get = cache::get),
@@ -117,6 +124,16 @@ pub fn resolve(required: &[u8]) -> Option<u32> {
         api!(i2c_read = i2c::read),
         api!(i2c_switch_select = i2c::switch_select),
 
+        // i/o expander
+        #[cfg(feature = "target_kasli_soc")]
+        api!(io_expander_get = io_expander::get),
+        #[cfg(feature = "target_kasli_soc")]
+        api!(io_expander_set = io_expander::set),
+
+        // grabber
+        #[cfg(has_grabber)]
+        api!(grabber_status = grabber::status),
+
         // subkernel
         #[cfg(has_drtio)]
         api!(subkernel_load_run = subkernel::load_run),
@@ -126,6 +143,10 @@ pub fn resolve(required: &[u8]) -> Option<u32> {
         api!(subkernel_send_message = subkernel::send_message),
         #[cfg(has_drtio)]
         api!(subkernel_await_message = subkernel::await_message),
+        #[cfg(has_drtio)]
+        api!(subkernel_try_send_message = subkernel::try_send_message),
+        #[cfg(has_drtio)]
+        api!(subkernel_try_await_message = subkernel::try_await_message),
 
         // Double-precision floating-point arithmetic helper functions
         // RTABI chapter 4.1.2, Table 2
@@ -1,10 +1,12 @@
 use alloc::{string::String, vec::Vec};
-use core::mem;
+use core::{mem, slice, sync::atomic::Ordering};
 
 use cslice::CSlice;
+use dma_trace::{find_event_word, patch_word, revert_patches, HEADER_LENGTH, MAX_WORDS};
+use libcortex_a9::cache::dcci_slice;
 
 use super::{rtio, Message, KERNEL_CHANNEL_0TO1, KERNEL_CHANNEL_1TO0, KERNEL_IMAGE};
-use crate::{artiq_raise, pl::csr};
+use crate::{artiq_raise, pending_sequence_error, pl::csr, SEQUENCE_ERROR_CHANNEL, SEQUENCE_ERROR_COUNT};
 
 #[repr(C)]
 pub struct DmaTrace {
@@ -13,6 +15,51 @@ pub struct DmaTrace {
     uses_ddma: bool,
 }
 
+// Overrides a data word of the event on `target` at `timestamp` (relative to
+// the start of the trace) of trace `trace` in a sequence, for one playback
+#[repr(C)]
+pub struct DmaPatch {
+    trace: i32,
+    target: i32,
+    timestamp: i64,
+    word: i32,
+    value: i32,
+}
+
+enum PlaybackError {
+    Underflow { channel: u32, timestamp: u64 },
+    DestinationUnreachable { channel: u32, timestamp: u64 },
+    RemoteTimeout,
+    PatchTargetNotFound { channel: u32, timestamp: u64 },
+    InvalidPatchWord { word: i32 },
+    Busy,
+}
+
+// Errors reported by a playback handle
+const PLAYBACK_OK: i32 = 0;
+const PLAYBACK_UNDERFLOW: i32 = 1;
+const PLAYBACK_DESTINATION_UNREACHABLE: i32 = 2;
+const PLAYBACK_SEQUENCE_ERROR: i32 = 3;
+const PLAYBACK_REMOTE_FAILURE: i32 = 4;
+
+#[repr(C)]
+pub struct DmaPlaybackStatus {
+    error: i32,
+    channel: i32,
+    // 0 if unknown, as for sequence errors
+    timestamp: i64,
+}
+
+struct Playback {
+    handle: i32,
+    ptr: i32,
+    uses_ddma: bool,
+    // sequence errors noted and still latched at the start
+    sequence_errors: u32,
+    sequence_error_pending: bool,
+    old_cri_master: u32,
+}
+
 #[derive(Clone, Debug)]
 pub struct DmaRecorder {
     pub name: String,
@@ -22,11 +69,19 @@ pub struct DmaRecorder {
 }
 
 static mut RECORDER: Option<DmaRecorder> = None;
+static mut PLAYBACK: Option<Playback> = None;
+static mut NEXT_HANDLE: i32 = 0;
 
 pub unsafe fn init_dma_recorder() {
     // as static would remain after restart, we have to reset it,
     // without running its destructor.
     mem::forget(mem::replace(&mut RECORDER, None));
+    // a previous kernel may have left a playback running, give RTIO back
+    if let Some(playback) = PLAYBACK.take() {
+        while csr::rtio_dma::enable_read() != 0 {}
+        csr::cri_con::selected_write(playback.old_cri_master);
+        csr::rtio_dma::error_write(1);
+    }
 }
 
 pub extern "C" fn dma_record_start(name: CSlice<u8>) {
@@ -77,12 +132,16 @@ pub extern "C" fn dma_record_stop(duration: i64, enable_ddma: bool) {
             .unwrap()
             .send(Message::DmaPutRequest(recorder));
     }
+    match unsafe { KERNEL_CHANNEL_0TO1.as_mut().unwrap() }.recv() {
+        Message::DmaPutReply(true) => return,
+        Message::DmaPutReply(false) => (),
+        _ => panic!("Expected DmaPutReply after DmaPutRequest!"),
+    }
+    artiq_raise!("DMAError", "DMA memory limit exceeded");
 }
 
 #[inline(always)]
 unsafe fn dma_record_output_prepare(timestamp: i64, target: i32, words: usize) {
-    // See gateware/rtio/dma.py.
-    const HEADER_LENGTH: usize = /*length*/1 + /*channel*/3 + /*timestamp*/8 + /*address*/1;
     let length = HEADER_LENGTH + /*data*/words * 4;
 
     let buffer = &mut RECORDER.as_mut().unwrap().buffer;
@@ -165,91 +224,356 @@ pub extern "C" fn dma_retrieve(name: CSlice<u8>) -> DmaTrace {
     artiq_raise!("DMAError", "DMA trace not found");
 }
 
-pub extern "C" fn dma_playback(timestamp: i64, ptr: i32, _uses_ddma: bool) {
-    unsafe {
-        csr::rtio_dma::base_address_write(ptr as u32);
-        csr::rtio_dma::time_offset_write(timestamp as u64);
-
-        let old_cri_master = csr::cri_con::selected_read();
-        csr::cri_con::selected_write(1);
-        csr::rtio_dma::enable_write(1);
-        #[cfg(has_drtio)]
-        if _uses_ddma {
-            KERNEL_CHANNEL_1TO0
-                .as_mut()
-                .unwrap()
-                .send(Message::DmaStartRemoteRequest {
-                    id: ptr,
-                    timestamp: timestamp,
-                });
+// the local part of a trace, up to and including the end marker
+unsafe fn local_trace(address: i32) -> &'static mut [u8] {
+    let ptr = address as *mut u8;
+    let mut len = 0;
+    while *ptr.add(len) != 0 {
+        len += *ptr.add(len) as usize;
+    }
+    slice::from_raw_parts_mut(ptr, len + 1)
+}
+
+fn check_error(error: u8, channel: u32, timestamp: u64) -> Result<(), PlaybackError> {
+    if error & 1 != 0 {
+        return Err(PlaybackError::Underflow { channel, timestamp });
+    }
+    if error & 2 != 0 {
+        return Err(PlaybackError::DestinationUnreachable { channel, timestamp });
+    }
+    Ok(())
+}
+
+fn raise_playback_error(error: PlaybackError) -> ! {
+    match error {
+        PlaybackError::Underflow { channel, timestamp } => artiq_raise!(
+            "RTIOUnderflow",
+            "RTIO underflow at {1} mu, channel {rtio_channel_info:0}",
+            channel as i64,
+            timestamp as i64,
+            0
+        ),
+        PlaybackError::DestinationUnreachable { channel, timestamp } => artiq_raise!(
+            "RTIODestinationUnreachable",
+            "RTIO destination unreachable, output, at {1} mu, channel {rtio_channel_info:0}",
+            channel as i64,
+            timestamp as i64,
+            0
+        ),
+        PlaybackError::RemoteTimeout => artiq_raise!(
+            "DMAError",
+            "Error running DMA on satellite device, timed out waiting for results"
+        ),
+        PlaybackError::PatchTargetNotFound { channel, timestamp } => artiq_raise!(
+            "DMAError",
+            "no DMA event to patch at {1} mu, channel {rtio_channel_info:0}",
+            channel as i64,
+            timestamp as i64,
+            0
+        ),
+        PlaybackError::InvalidPatchWord { word } => {
+            artiq_raise!("DMAError", "invalid DMA patch data word {0}", word as i64, 0, 0)
         }
-        while csr::rtio_dma::enable_read() != 0 {}
-        csr::cri_con::selected_write(old_cri_master);
-
-        let error = csr::rtio_dma::error_read();
-        if error != 0 {
-            let timestamp = csr::rtio_dma::error_timestamp_read();
-            let channel = csr::rtio_dma::error_channel_read();
-            csr::rtio_dma::error_write(1);
-            if error & 1 != 0 {
-                artiq_raise!(
-                    "RTIOUnderflow",
-                    "RTIO underflow at {1} mu, channel {rtio_channel_info:0}",
-                    channel as i64,
-                    timestamp as i64,
-                    0
-                );
+        PlaybackError::Busy => artiq_raise!("DMAError", "DMA playback already in progress"),
+    }
+}
+
+unsafe fn start_playback(timestamp: i64, ptr: i32, _uses_ddma: bool) -> u32 {
+    csr::rtio_dma::base_address_write(ptr as u32);
+    csr::rtio_dma::time_offset_write(timestamp as u64);
+
+    let old_cri_master = csr::cri_con::selected_read();
+    csr::cri_con::selected_write(1);
+    csr::rtio_dma::enable_write(1);
+    #[cfg(has_drtio)]
+    if _uses_ddma {
+        KERNEL_CHANNEL_1TO0
+            .as_mut()
+            .unwrap()
+            .send(Message::DmaStartRemoteRequest {
+                id: ptr,
+                timestamp: timestamp,
+            });
+    }
+    old_cri_master
+}
+
+unsafe fn finish_playback(ptr: i32, _uses_ddma: bool, old_cri_master: u32) -> Result<(), PlaybackError> {
+    while csr::rtio_dma::enable_read() != 0 {}
+    csr::cri_con::selected_write(old_cri_master);
+
+    let error = csr::rtio_dma::error_read();
+    if error != 0 {
+        let timestamp = csr::rtio_dma::error_timestamp_read();
+        let channel = csr::rtio_dma::error_channel_read();
+        csr::rtio_dma::error_write(1);
+        check_error(error, channel, timestamp)?;
+    }
+    #[cfg(has_drtio)]
+    if _uses_ddma {
+        KERNEL_CHANNEL_1TO0
+            .as_mut()
+            .unwrap()
+            .send(Message::DmaAwaitRemoteRequest(ptr));
+        match KERNEL_CHANNEL_0TO1.as_mut().unwrap().recv() {
+            Message::DmaAwaitRemoteReply {
+                timeout,
+                error,
+                channel,
+                timestamp,
+            } => {
+                if timeout {
+                    return Err(PlaybackError::RemoteTimeout);
+                }
+                check_error(error, channel, timestamp)?;
             }
-            if error & 2 != 0 {
+            _ => panic!("Expected DmaAwaitRemoteReply after DmaAwaitRemoteRequest!"),
+        }
+    }
+    Ok(())
+}
+
+fn playback(timestamp: i64, ptr: i32, uses_ddma: bool) -> Result<(), PlaybackError> {
+    if unsafe { PLAYBACK.is_some() } {
+        return Err(PlaybackError::Busy);
+    }
+    unsafe {
+        let old_cri_master = start_playback(timestamp, ptr, uses_ddma);
+        finish_playback(ptr, uses_ddma, old_cri_master)
+    }
+}
+
+pub extern "C" fn dma_playback(timestamp: i64, ptr: i32, uses_ddma: bool) {
+    if let Err(error) = playback(timestamp, ptr, uses_ddma) {
+        raise_playback_error(error);
+    }
+}
+
+/// Starts playing back a trace and returns a handle to await its completion
+/// with. Until then the DMA core owns RTIO, and using it raises DMAError.
+pub extern "C" fn dma_playback_start(timestamp: i64, ptr: i32, uses_ddma: bool) -> i32 {
+    unsafe {
+        if PLAYBACK.is_some() {
+            raise_playback_error(PlaybackError::Busy);
+        }
+        NEXT_HANDLE = NEXT_HANDLE.wrapping_add(1);
+        PLAYBACK = Some(Playback {
+            handle: NEXT_HANDLE,
+            ptr,
+            uses_ddma,
+            sequence_errors: SEQUENCE_ERROR_COUNT.load(Ordering::SeqCst),
+            sequence_error_pending: pending_sequence_error().is_some(),
+            old_cri_master: start_playback(timestamp, ptr, uses_ddma),
+        });
+        NEXT_HANDLE
+    }
+}
+
+// Sequence errors are reported by the RTIO core rather than the DMA core, and
+// noted by core0 some time after they are latched; both are looked at, so that
+// an error is seen whether or not core0 got to it yet.
+fn sequence_error_since(playback: &Playback) -> Option<u32> {
+    let noted = SEQUENCE_ERROR_COUNT
+        .load(Ordering::SeqCst)
+        .wrapping_sub(playback.sequence_errors);
+    // the error latched at the start, if any, gets noted once
+    if noted > playback.sequence_error_pending as u32 {
+        return Some(SEQUENCE_ERROR_CHANNEL.load(Ordering::SeqCst));
+    }
+    match pending_sequence_error() {
+        // still the one latched at the start
+        Some(_) if playback.sequence_error_pending && noted == 0 => None,
+        pending => pending,
+    }
+}
+
+/// Raises DMAError if a playback started with dma_playback_start() owns RTIO.
+#[inline(always)]
+pub(super) fn check_rtio_available() {
+    if unsafe { PLAYBACK.is_some() } {
+        rtio_unavailable();
+    }
+}
+
+#[inline(never)]
+fn rtio_unavailable() -> ! {
+    artiq_raise!("DMAError", "RTIO is in use by a DMA playback, await it first")
+}
+
+/// Waits for the playback started with `handle` to end and reports the first
+/// error it ran into, if any, rather than raising it.
+pub extern "C" fn dma_playback_await(handle: i32) -> DmaPlaybackStatus {
+    let playback = unsafe {
+        match PLAYBACK.take() {
+            Some(playback) if playback.handle == handle => playback,
+            other => {
+                PLAYBACK = other;
                 artiq_raise!(
-                    "RTIODestinationUnreachable",
-                    "RTIO destination unreachable, output, at {1} mu, channel {rtio_channel_info:0}",
-                    channel as i64,
-                    timestamp as i64,
+                    "DMAError",
+                    "no DMA playback in progress with handle {0}",
+                    handle as i64,
+                    0,
                     0
-                );
+                )
             }
         }
-        #[cfg(has_drtio)]
-        if _uses_ddma {
-            KERNEL_CHANNEL_1TO0
-                .as_mut()
-                .unwrap()
-                .send(Message::DmaAwaitRemoteRequest(ptr));
-            match KERNEL_CHANNEL_0TO1.as_mut().unwrap().recv() {
-                Message::DmaAwaitRemoteReply {
-                    timeout,
-                    error,
-                    channel,
-                    timestamp,
-                } => {
-                    if timeout {
-                        artiq_raise!(
-                            "DMAError",
-                            "Error running DMA on satellite device, timed out waiting for results"
-                        );
-                    }
-                    if error & 1 != 0 {
-                        artiq_raise!(
-                            "RTIOUnderflow",
-                            "RTIO underflow at {1} mu, channel {rtio_channel_info:0}",
-                            channel as i64,
-                            timestamp as i64,
-                            0
-                        );
-                    }
-                    if error & 2 != 0 {
-                        artiq_raise!(
-                            "RTIODestinationUnreachable",
-                            "RTIO destination unreachable, output, at {1} mu, channel {rtio_channel_info:0}",
-                            channel as i64,
-                            timestamp as i64,
-                            0
-                        );
-                    }
+    };
+    let result = unsafe { finish_playback(playback.ptr, playback.uses_ddma, playback.old_cri_master) };
+    let (error, channel, timestamp) = match result {
+        Ok(()) => match sequence_error_since(&playback) {
+            Some(channel) => (PLAYBACK_SEQUENCE_ERROR, channel, 0),
+            None => (PLAYBACK_OK, 0, 0),
+        },
+        Err(PlaybackError::Underflow { channel, timestamp }) => (PLAYBACK_UNDERFLOW, channel, timestamp),
+        Err(PlaybackError::DestinationUnreachable { channel, timestamp }) => {
+            (PLAYBACK_DESTINATION_UNREACHABLE, channel, timestamp)
+        }
+        Err(_) => (PLAYBACK_REMOTE_FAILURE, 0, 0),
+    };
+    DmaPlaybackStatus {
+        error,
+        channel: channel as i32,
+        timestamp: timestamp as i64,
+    }
+}
+
+#[cfg(has_drtio)]
+fn patch_remote(trace: &DmaTrace, patch: &DmaPatch) -> bool {
+    unsafe {
+        KERNEL_CHANNEL_1TO0
+            .as_mut()
+            .unwrap()
+            .send(Message::DmaPatchRemoteRequest {
+                id: trace.address,
+                destination: (patch.target >> 24) as u8,
+                target: patch.target as u32,
+                timestamp: patch.timestamp as u64,
+                word: patch.word as u8,
+                value: patch.value as u32,
+            });
+        match KERNEL_CHANNEL_0TO1.as_mut().unwrap().recv() {
+            Message::DmaPatchRemoteReply(succeeded) => succeeded,
+            _ => panic!("Expected DmaPatchRemoteReply after DmaPatchRemoteRequest!"),
+        }
+    }
+}
+
+#[cfg(has_drtio)]
+fn revert_remote(trace: &DmaTrace, destination: u8) {
+    unsafe {
+        KERNEL_CHANNEL_1TO0
+            .as_mut()
+            .unwrap()
+            .send(Message::DmaRevertRemoteRequest {
+                id: trace.address,
+                destination,
+            });
+    }
+}
+
+fn target_not_found(patch: &DmaPatch) -> PlaybackError {
+    PlaybackError::PatchTargetNotFound {
+        channel: patch.target as u32 >> 8,
+        timestamp: patch.timestamp as u64,
+    }
+}
+
+// Checks that every patch has a target before any trace is played back;
+// remote targets are only known once their satellites are asked.
+fn check_patches(traces: &[DmaTrace], patches: &[DmaPatch]) -> Result<(), PlaybackError> {
+    for patch in patches.iter() {
+        // also keeps the word from being truncated on its way to a satellite
+        if patch.word < 0 || patch.word as usize >= MAX_WORDS {
+            return Err(PlaybackError::InvalidPatchWord { word: patch.word });
+        }
+        let trace = traces
+            .get(patch.trace as usize)
+            .ok_or_else(|| target_not_found(patch))?;
+        let local = unsafe { local_trace(trace.address) };
+        let found = find_event_word(local, patch.target as u32, patch.timestamp as u64, patch.word as usize).is_some();
+        if !found && !trace.uses_ddma {
+            return Err(target_not_found(patch));
+        }
+    }
+    Ok(())
+}
+
+// Patches the local part of a trace in place, saving the original words;
+// remote parts are patched by their satellites, which revert them after playback.
+// Either all the patches are applied or none is.
+fn patch_trace(
+    index: i32,
+    trace: &DmaTrace,
+    patches: &[DmaPatch],
+    saved: &mut Vec<(usize, u32)>,
+) -> Result<(), PlaybackError> {
+    let local = unsafe { local_trace(trace.address) };
+    // look the local targets up first, so that nothing is sent to the
+    // satellites for a trace that cannot be patched
+    let mut local_patches = Vec::new();
+    #[cfg(has_drtio)]
+    let mut remote_patches = Vec::new();
+    for patch in patches.iter().filter(|patch| patch.trace == index) {
+        match find_event_word(local, patch.target as u32, patch.timestamp as u64, patch.word as usize) {
+            Some(offset) => local_patches.push((offset, patch.value as u32)),
+            #[cfg(has_drtio)]
+            None if trace.uses_ddma => remote_patches.push(patch),
+            None => return Err(target_not_found(patch)),
+        }
+    }
+    #[cfg(has_drtio)]
+    {
+        let mut patched_destinations: Vec<u8> = Vec::new();
+        for patch in remote_patches.iter() {
+            let destination = (patch.target >> 24) as u8;
+            if !patch_remote(trace, patch) {
+                for destination in patched_destinations.iter() {
+                    revert_remote(trace, *destination);
                 }
-                _ => panic!("Expected DmaAwaitRemoteReply after DmaAwaitRemoteRequest!"),
+                return Err(target_not_found(patch));
             }
+            if !patched_destinations.contains(&destination) {
+                patched_destinations.push(destination);
+            }
+        }
+    }
+    for (offset, value) in local_patches.iter() {
+        saved.push((*offset, patch_word(local, *offset, *value)));
+    }
+    if !saved.is_empty() {
+        dcci_slice(local);
+    }
+    Ok(())
+}
+
+fn restore_trace(trace: &DmaTrace, saved: &[(usize, u32)]) {
+    if saved.is_empty() {
+        return;
+    }
+    let local = unsafe { local_trace(trace.address) };
+    revert_patches(local, saved);
+    dcci_slice(local);
+}
+
+/// Plays back the traces one after another, each starting when the previous
+/// one ends, with the given data words overridden for this playback only.
+pub extern "C" fn dma_playback_sequence(timestamp: i64, traces: &CSlice<DmaTrace>, patches: &CSlice<DmaPatch>) {
+    // checked before patching, playback() would leave the remote patches in place
+    if unsafe { PLAYBACK.is_some() } {
+        raise_playback_error(PlaybackError::Busy);
+    }
+    if let Err(error) = check_patches(traces.as_ref(), patches.as_ref()) {
+        raise_playback_error(error);
+    }
+    let mut timestamp = timestamp;
+    for (index, trace) in traces.as_ref().iter().enumerate() {
+        let mut saved = Vec::new();
+        let result = patch_trace(index as i32, trace, patches.as_ref(), &mut saved)
+            .and_then(|()| playback(timestamp, trace.address, trace.uses_ddma));
+        restore_trace(trace, &saved);
+        if let Err(error) = result {
+            raise_playback_error(error);
         }
+        timestamp += trace.duration;
     }
 }
@@ -0,0 +1,28 @@
+use libboard_artiq::grabber;
+
+use crate::artiq_raise;
+
+// Only the status is exported: the gateware gives access to the ROI engines
+// only through the grabber RTIO channels, which the ARTIQ grabber driver drives.
+
+#[repr(C)]
+pub struct GrabberStatus {
+    state: i32,
+    video_clock: i32,
+    frame_width: i32,
+    frame_height: i32,
+    lock_loss_count: i32,
+}
+
+pub extern "C" fn status(g: i32) -> GrabberStatus {
+    match grabber::status(g as usize) {
+        Some(status) => GrabberStatus {
+            state: status.state as i32,
+            video_clock: status.video_clock as i32,
+            frame_width: status.frame_size.0 as i32,
+            frame_height: status.frame_size.1 as i32,
+            lock_loss_count: status.lock_loss_count as i32,
+        },
+        None => artiq_raise!("IndexError", "grabber index out of range", g as i64, 0, 0),
+    }
+}
@@ -0,0 +1,29 @@
+use core::str;
+
+use cslice::CSlice;
+use libboard_artiq::io_expander;
+
+use crate::artiq_raise;
+
+fn pin_name<'a>(name: &'a CSlice<u8>) -> &'a str {
+    match str::from_utf8(name.as_ref()) {
+        Ok(name) => name,
+        Err(_) => artiq_raise!("I2CError", "invalid I/O expander pin name"),
+    }
+}
+
+// The I/O expanders are serviced only while no kernel runs, as kernels share
+// their I2C bus: reads return the last levels sampled before the kernel started.
+pub extern "C" fn get(name: CSlice<u8>) -> bool {
+    match io_expander::get_pin(pin_name(&name)) {
+        Ok(level) => level,
+        Err(e) => artiq_raise!("I2CError", e),
+    }
+}
+
+// takes effect once the kernel has ended
+pub extern "C" fn set(name: CSlice<u8>, level: bool) {
+    if let Err(e) = io_expander::set_pin(pin_name(&name), level) {
+        artiq_raise!("I2CError", e);
+    }
+}
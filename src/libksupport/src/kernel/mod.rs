@@ -10,7 +10,11 @@ pub use control::Control;
 mod api;
 pub mod core1;
 mod dma;
+#[cfg(has_grabber)]
+mod grabber;
 pub mod i2c;
+#[cfg(feature = "target_kasli_soc")]
+mod io_expander;
 mod rpc;
 #[cfg(ki_impl = "csr")]
 #[path = "rtio_csr.rs"]
@@ -30,6 +34,7 @@ pub enum SubkernelStatus {
     Timeout,
     IncorrectState,
     CommLost,
+    Cancelled,
     Exception(Vec<u8>),
     OtherError,
 }
@@ -65,6 +70,8 @@ pub enum Message {
     CachePutRequest(String, Vec<i32>),
 
     DmaPutRequest(DmaRecorder),
+    // false if the trace was rejected for exceeding the DMA memory limit
+    DmaPutReply(bool),
     DmaEraseRequest(String),
     DmaGetRequest(String),
     DmaGetReply(Option<(i32, i64, bool)>),
@@ -74,6 +81,22 @@ pub enum Message {
         timestamp: i64,
     },
     #[cfg(has_drtio)]
+    DmaPatchRemoteRequest {
+        id: i32,
+        destination: u8,
+        target: u32,
+        timestamp: u64,
+        word: u8,
+        value: u32,
+    },
+    #[cfg(has_drtio)]
+    DmaPatchRemoteReply(bool),
+    #[cfg(has_drtio)]
+    DmaRevertRemoteRequest {
+        id: i32,
+        destination: u8,
+    },
+    #[cfg(has_drtio)]
     DmaAwaitRemoteRequest(i32),
     #[cfg(has_drtio)]
     DmaAwaitRemoteReply {
@@ -160,6 +183,21 @@ pub enum Message {
         count: u8,
     },
     #[cfg(has_drtio)]
+    SubkernelMsgTrySend {
+        id: u32,
+        destination: Option<u8>,
+        data: Vec<u8>,
+    },
+    #[cfg(has_drtio)]
+    SubkernelMsgQueueFull,
+    #[cfg(has_drtio)]
+    SubkernelMsgTryRecvRequest {
+        id: i32,
+        tags: Vec<u8>,
+    },
+    #[cfg(has_drtio)]
+    SubkernelMsgRecvEmpty,
+    #[cfg(has_drtio)]
     SubkernelError(SubkernelStatus),
 }
 
@@ -4,6 +4,7 @@ use cslice::CSlice;
 use libcortex_a9::asm;
 use vcell::VolatileCell;
 
+use super::dma::check_rtio_available;
 #[cfg(has_drtio)]
 use super::{Message, KERNEL_CHANNEL_0TO1, KERNEL_CHANNEL_1TO0};
 use crate::{artiq_raise, pl::csr, resolve_channel_name, rtio_core};
@@ -125,6 +126,7 @@ unsafe fn process_exceptional_status(channel: i32, status: i32) {
 }
 
 pub extern "C" fn output(target: i32, data: i32) {
+    check_rtio_available();
     unsafe {
         // Clear status so we can observe response
         TRANSACTION_BUFFER.reply_status.set(0);
@@ -153,6 +155,7 @@ pub extern "C" fn output(target: i32, data: i32) {
 }
 
 pub extern "C" fn output_wide(target: i32, data: CSlice<i32>) {
+    check_rtio_available();
     unsafe {
         // Clear status so we can observe response
         TRANSACTION_BUFFER.reply_status.set(0);
@@ -181,6 +184,7 @@ pub extern "C" fn output_wide(target: i32, data: CSlice<i32>) {
 }
 
 pub extern "C" fn input_timestamp(timeout: i64, channel: i32) -> i64 {
+    check_rtio_available();
     unsafe {
         // Clear status so we can observe response
         TRANSACTION_BUFFER.reply_status.set(0);
@@ -236,6 +240,7 @@ pub extern "C" fn input_timestamp(timeout: i64, channel: i32) -> i64 {
 }
 
 pub extern "C" fn input_data(channel: i32) -> i32 {
+    check_rtio_available();
     unsafe {
         TRANSACTION_BUFFER.reply_status.set(0);
 
@@ -287,6 +292,7 @@ pub extern "C" fn input_data(channel: i32) -> i32 {
 }
 
 pub extern "C" fn input_timestamped_data(timeout: i64, channel: i32) -> TimestampedData {
+    check_rtio_available();
     unsafe {
         TRANSACTION_BUFFER.reply_status.set(0);
 
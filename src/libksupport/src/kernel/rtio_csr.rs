@@ -2,6 +2,7 @@ use core::ptr::{read_volatile, write_volatile};
 
 use cslice::CSlice;
 
+use super::dma::check_rtio_available;
 #[cfg(has_drtio)]
 use super::{Message, KERNEL_CHANNEL_0TO1, KERNEL_CHANNEL_1TO0};
 use crate::{artiq_raise, pl::csr, resolve_channel_name, rtio_core};
@@ -106,6 +107,7 @@ unsafe fn process_exceptional_status(channel: i32, status: u8) {
 }
 
 pub extern "C" fn output(target: i32, data: i32) {
+    check_rtio_available();
     unsafe {
         csr::rtio::target_write(target as u32);
         // writing target clears o_data
@@ -118,6 +120,7 @@ pub extern "C" fn output(target: i32, data: i32) {
 }
 
 pub extern "C" fn output_wide(target: i32, data: &CSlice<i32>) {
+    check_rtio_available();
     unsafe {
         csr::rtio::target_write(target as u32);
         // writing target clears o_data
@@ -132,6 +135,7 @@ pub extern "C" fn output_wide(target: i32, data: &CSlice<i32>) {
 }
 
 pub extern "C" fn input_timestamp(timeout: i64, channel: i32) -> i64 {
+    check_rtio_available();
     unsafe {
         csr::rtio::target_write((channel as u32) << 8);
         csr::rtio::i_timeout_write(timeout as u64);
@@ -176,6 +180,7 @@ pub extern "C" fn input_timestamp(timeout: i64, channel: i32) -> i64 {
 }
 
 pub extern "C" fn input_data(channel: i32) -> i32 {
+    check_rtio_available();
     unsafe {
         csr::rtio::target_write((channel as u32) << 8);
         csr::rtio::i_timeout_write(0xffffffff_ffffffff);
@@ -217,6 +222,7 @@ pub extern "C" fn input_data(channel: i32) -> i32 {
 }
 
 pub extern "C" fn input_timestamped_data(timeout: i64, channel: i32) -> TimestampedData {
+    check_rtio_available();
     unsafe {
         csr::rtio::target_write((channel as u32) << 8);
         csr::rtio::i_timeout_write(timeout as u64);
@@ -264,6 +270,7 @@ pub extern "C" fn input_timestamped_data(timeout: i64, channel: i32) -> Timestam
 }
 
 pub fn write_log(data: &[i8]) {
+    check_rtio_available();
     unsafe {
         csr::rtio::target_write(csr::CONFIG_RTIO_LOG_CHANNEL << 8);
 
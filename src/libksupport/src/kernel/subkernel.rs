@@ -45,6 +45,9 @@ pub extern "C" fn await_finish(id: u32, timeout: i64) {
         Message::SubkernelError(SubkernelStatus::CommLost) => {
             artiq_raise!("SubkernelError", "Lost communication with satellite")
         }
+        Message::SubkernelError(SubkernelStatus::Cancelled) => {
+            artiq_raise!("SubkernelError", "Subkernel was cancelled")
+        }
         Message::SubkernelError(SubkernelStatus::OtherError) => {
             artiq_raise!("SubkernelError", "An error occurred during subkernel operation")
         }
@@ -102,6 +105,9 @@ pub extern "C" fn await_message(id: i32, timeout: i64, tags: &CSlice<u8>, min: u
         Message::SubkernelError(SubkernelStatus::CommLost) => {
             artiq_raise!("SubkernelError", "Lost communication with satellite")
         }
+        Message::SubkernelError(SubkernelStatus::Cancelled) => {
+            artiq_raise!("SubkernelError", "Subkernel was cancelled")
+        }
         Message::SubkernelError(SubkernelStatus::OtherError) => {
             artiq_raise!("SubkernelError", "An error occurred during subkernel operation")
         }
@@ -110,3 +116,70 @@ pub extern "C" fn await_message(id: i32, timeout: i64, tags: &CSlice<u8>, min: u
     }
     // RpcRecvRequest should be called after this to receive message data
 }
+
+// Streaming variants: messages to a destination are sent a window of slices ahead of their
+// acks, try_send_message returns once its message is queued, or false instead of blocking
+// when too many messages to that destination are not acknowledged yet.
+pub extern "C" fn try_send_message(
+    id: u32,
+    is_return: bool,
+    destination: u8,
+    count: u8,
+    tag: &CSlice<u8>,
+    data: *const *const (),
+) -> bool {
+    let mut buffer = Vec::<u8>::new();
+    send_args(&mut buffer, 0, tag.as_ref(), data, false).expect("RPC encoding failed");
+    buffer[3] = count;
+    unsafe {
+        KERNEL_CHANNEL_1TO0
+            .as_mut()
+            .unwrap()
+            .send(Message::SubkernelMsgTrySend {
+                id: id,
+                destination: if is_return { None } else { Some(destination) },
+                data: buffer[3..].to_vec(),
+            });
+    }
+    match unsafe { KERNEL_CHANNEL_0TO1.as_mut().unwrap() }.recv() {
+        Message::SubkernelMsgSent => true,
+        Message::SubkernelMsgQueueFull => false,
+        _ => panic!("expected SubkernelMsgSent or SubkernelMsgQueueFull after SubkernelMsgTrySend"),
+    }
+}
+
+pub extern "C" fn try_await_message(id: i32, tags: &CSlice<u8>, min: u8, max: u8) -> bool {
+    unsafe {
+        KERNEL_CHANNEL_1TO0
+            .as_mut()
+            .unwrap()
+            .send(Message::SubkernelMsgTryRecvRequest {
+                id: id,
+                tags: tags.as_ref().to_vec(),
+            });
+    }
+    match unsafe { KERNEL_CHANNEL_0TO1.as_mut().unwrap() }.recv() {
+        Message::SubkernelMsgRecvReply { count } => {
+            if min > count || count > max {
+                artiq_raise!("SubkernelError", "Received more or less arguments than required")
+            }
+        }
+        Message::SubkernelMsgRecvEmpty => return false,
+        Message::SubkernelError(SubkernelStatus::IncorrectState) => {
+            artiq_raise!("SubkernelError", "Subkernel not running")
+        }
+        Message::SubkernelError(SubkernelStatus::CommLost) => {
+            artiq_raise!("SubkernelError", "Lost communication with satellite")
+        }
+        Message::SubkernelError(SubkernelStatus::Cancelled) => {
+            artiq_raise!("SubkernelError", "Subkernel was cancelled")
+        }
+        Message::SubkernelError(SubkernelStatus::Exception(raw_exception)) => eh_artiq::raise_raw(&raw_exception),
+        Message::SubkernelError(_) => {
+            artiq_raise!("SubkernelError", "An error occurred during subkernel operation")
+        }
+        _ => panic!("expected SubkernelMsgRecvReply or SubkernelMsgRecvEmpty after SubkernelMsgTryRecvRequest"),
+    }
+    // RpcRecvRequest should be called after this to receive message data
+    true
+}
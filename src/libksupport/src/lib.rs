@@ -9,6 +9,7 @@
 extern crate alloc;
 
 use alloc::{collections::BTreeMap, string::String};
+use core::sync::atomic::{AtomicU32, Ordering};
 
 use io::{Cursor, ProtoRead};
 use libasync::block_async;
@@ -46,6 +47,32 @@ pub const ASYNC_ERROR_COLLISION: u8 = 1 << 0;
 pub const ASYNC_ERROR_BUSY: u8 = 1 << 1;
 pub const ASYNC_ERROR_SEQUENCE_ERROR: u8 = 1 << 2;
 
+// Sequence errors seen so far and the channel of the last one, local or remote,
+// for DMA playback handles to report the errors occurring during a playback.
+pub static SEQUENCE_ERROR_COUNT: AtomicU32 = AtomicU32::new(0);
+pub static SEQUENCE_ERROR_CHANNEL: AtomicU32 = AtomicU32::new(0);
+
+pub fn note_sequence_error(channel: u32) {
+    SEQUENCE_ERROR_CHANNEL.store(channel, Ordering::SeqCst);
+    SEQUENCE_ERROR_COUNT.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Returns the channel of the sequence error latched by the RTIO core, if
+/// report_async_rtio_errors() has not noted and cleared it yet.
+pub fn pending_sequence_error() -> Option<u32> {
+    unsafe {
+        #[cfg(has_rtio_core)]
+        let errors = rtio_core::async_error_read();
+        #[cfg(has_drtiosat)]
+        let errors = rtio_core::protocol_error_read();
+        if errors & ASYNC_ERROR_SEQUENCE_ERROR != 0 {
+            Some(rtio_core::sequence_error_channel_read() as u32)
+        } else {
+            None
+        }
+    }
+}
+
 pub unsafe fn get_async_errors() -> u8 {
     let errors = SEEN_ASYNC_ERRORS;
     SEEN_ASYNC_ERRORS = 0;
@@ -97,6 +124,7 @@ pub async fn report_async_rtio_errors() {
                     channel,
                     resolve_channel_name(channel as u32)
                 );
+                note_sequence_error(channel as u32);
             }
             SEEN_ASYNC_ERRORS = errors;
             #[cfg(has_rtio_core)]
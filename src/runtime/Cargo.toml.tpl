@@ -7,7 +7,7 @@ edition = "2018"
 
 [features]
 target_zc706 = ["libboard_zynq/target_zc706", "libsupport_zynq/target_zc706", "libconfig/target_zc706", "libboard_artiq/target_zc706"]
-target_kasli_soc = ["libboard_zynq/target_kasli_soc", "libsupport_zynq/target_kasli_soc", "libconfig/target_kasli_soc", "libboard_artiq/target_kasli_soc"]
+target_kasli_soc = ["libboard_zynq/target_kasli_soc", "libsupport_zynq/target_kasli_soc", "libconfig/target_kasli_soc", "libboard_artiq/target_kasli_soc", "ksupport/target_kasli_soc"]
 default = ["target_zc706"]
 
 [build-dependencies]
@@ -17,6 +17,7 @@ build_zynq = { path = "../libbuild_zynq" }
 num-traits = { version = "0.2", default-features = false }
 num-derive = "0.3"
 cslice = "0.3"
+crc = { version = "1.7", default-features = false }
 log = "0.4"
 embedded-hal = "0.2"
 core_io = { version = "0.1", features = ["collections"] }
@@ -34,6 +35,7 @@ libasync = { path = "@@ZYNQ_RS@@/libasync" }
 libregister = { path = "@@ZYNQ_RS@@/libregister" }
 libconfig = { path = "@@ZYNQ_RS@@/libconfig", features = ["fat_lfn", "ipv6"] }
 
+dma_trace = { path = "../libdma_trace" }
 dyld = { path = "../libdyld" }
 dwarf = { path = "../libdwarf" }
 unwind = { path = "../libunwind" }
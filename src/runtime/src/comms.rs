@@ -1,5 +1,6 @@
 use alloc::{collections::BTreeMap, rc::Rc, string::String, vec, vec::Vec};
-use core::{cell::RefCell, fmt, slice, str};
+use core::{cell::RefCell, fmt, slice, str,
+           sync::atomic::{AtomicBool, Ordering}};
 
 use core_io::Error as IoError;
 use cslice::CSlice;
@@ -95,6 +96,7 @@ enum Request {
     RPCReply = 7,
     RPCException = 8,
     UploadSubkernel = 9,
+    UploadDmaTrace = 10,
 }
 
 #[derive(Debug, FromPrimitive, ToPrimitive)]
@@ -111,6 +113,27 @@ enum Reply {
 }
 
 static CACHE_STORE: Mutex<BTreeMap<String, Vec<i32>>> = Mutex::new(BTreeMap::new());
+static KERNEL_RUNNING: AtomicBool = AtomicBool::new(false);
+
+pub fn kernel_running() -> bool {
+    KERNEL_RUNNING.load(Ordering::SeqCst)
+}
+
+// clears the running flag however the kernel run ends
+struct RunningGuard;
+
+impl RunningGuard {
+    fn new() -> RunningGuard {
+        KERNEL_RUNNING.store(true, Ordering::SeqCst);
+        RunningGuard
+    }
+}
+
+impl Drop for RunningGuard {
+    fn drop(&mut self) {
+        KERNEL_RUNNING.store(false, Ordering::SeqCst);
+    }
+}
 
 async fn write_header(stream: &TcpStream, reply: Reply) -> Result<()> {
     stream
@@ -149,6 +172,9 @@ async fn read_bytes(stream: &TcpStream, max_length: usize) -> Result<Vec<u8>> {
     Ok(buffer)
 }
 
+// upper bound on a DMA trace uploaded by the host, the memory limit applies on top of it
+const DMA_UPLOAD_MAX: usize = 64 * 1024 * 1024;
+
 const RETRY_LIMIT: usize = 100;
 
 async fn fast_send(sender: &mut Sender<'_, kernel::Message>, content: kernel::Message) {
@@ -190,8 +216,10 @@ async fn handle_run_kernel(
     _up_destinations: &Rc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
     aux_mutex: &Rc<Mutex<bool>>,
     routing_table: &drtio_routing::RoutingTable,
+    cfg: &Config,
     timer: GlobalTimer,
 ) -> Result<()> {
+    let _running = RunningGuard::new();
     control.borrow_mut().tx.async_send(kernel::Message::StartRequest).await;
     loop {
         let reply = control.borrow_mut().rx.async_recv().await;
@@ -353,9 +381,26 @@ async fn handle_run_kernel(
                     .await;
             }
             kernel::Message::DmaPutRequest(recorder) => {
-                let _id = rtio_dma::put_record(aux_mutex, routing_table, timer, recorder).await;
-                #[cfg(has_drtio)]
-                rtio_dma::remote_dma::upload_traces(aux_mutex, routing_table, timer, _id).await;
+                let name = recorder.name.clone();
+                match rtio_dma::put_record(cfg, aux_mutex, routing_table, timer, recorder).await {
+                    Ok(_id) => {
+                        control
+                            .borrow_mut()
+                            .tx
+                            .async_send(kernel::Message::DmaPutReply(true))
+                            .await;
+                        #[cfg(has_drtio)]
+                        rtio_dma::remote_dma::upload_traces(aux_mutex, routing_table, timer, _id).await;
+                    }
+                    Err(e) => {
+                        error!("cannot store DMA trace {} ({})", name, e);
+                        control
+                            .borrow_mut()
+                            .tx
+                            .async_send(kernel::Message::DmaPutReply(false))
+                            .await;
+                    }
+                }
             }
             kernel::Message::DmaEraseRequest(name) => {
                 // prevent possible OOM when we have large DMA record replacement.
@@ -374,6 +419,37 @@ async fn handle_run_kernel(
                 rtio_dma::remote_dma::playback(aux_mutex, routing_table, timer, id as u32, timestamp as u64).await;
             }
             #[cfg(has_drtio)]
+            kernel::Message::DmaPatchRemoteRequest {
+                id,
+                destination,
+                target,
+                timestamp,
+                word,
+                value,
+            } => {
+                let succeeded = rtio_dma::remote_dma::patch(
+                    aux_mutex,
+                    routing_table,
+                    timer,
+                    id as u32,
+                    destination,
+                    target,
+                    timestamp,
+                    word,
+                    value,
+                )
+                .await;
+                control
+                    .borrow_mut()
+                    .tx
+                    .async_send(kernel::Message::DmaPatchRemoteReply(succeeded))
+                    .await;
+            }
+            #[cfg(has_drtio)]
+            kernel::Message::DmaRevertRemoteRequest { id, destination } => {
+                rtio_dma::remote_dma::revert_patches(aux_mutex, routing_table, timer, id as u32, destination).await;
+            }
+            #[cfg(has_drtio)]
             kernel::Message::DmaAwaitRemoteRequest(id) => {
                 let result = rtio_dma::remote_dma::await_done(id as u32, Some(10_000), timer).await;
                 let reply = match result {
@@ -459,6 +535,8 @@ async fn handle_run_kernel(
                     Ok(res) => {
                         if res.status == subkernel::FinishStatus::CommLost {
                             kernel::Message::SubkernelError(kernel::SubkernelStatus::CommLost)
+                        } else if res.status == subkernel::FinishStatus::Cancelled {
+                            kernel::Message::SubkernelError(kernel::SubkernelStatus::Cancelled)
                         } else if let Some(exception) = res.exception {
                             kernel::Message::SubkernelError(kernel::SubkernelStatus::Exception(exception))
                         } else {
@@ -499,6 +577,9 @@ async fn handle_run_kernel(
                         kernel::Message::SubkernelError(kernel::SubkernelStatus::IncorrectState)
                     }
                     Err(SubkernelError::CommLost) => kernel::Message::SubkernelError(kernel::SubkernelStatus::CommLost),
+                    Err(SubkernelError::Cancelled) => {
+                        kernel::Message::SubkernelError(kernel::SubkernelStatus::Cancelled)
+                    }
                     Err(SubkernelError::SubkernelException) => {
                         // just retrieve the exception
                         let status = subkernel::await_finish(aux_mutex, routing_table, timer, id as u32, timeout)
@@ -510,42 +591,47 @@ async fn handle_run_kernel(
                 };
                 control.borrow_mut().tx.async_send(response).await;
                 if let Ok(message) = message_received {
-                    // receive code almost identical to RPC recv, except we are not reading from a stream
-                    let mut reader = Cursor::new(message.data);
-                    let mut current_tags: &[u8] = &tags;
-                    let mut i = 0;
-                    loop {
-                        // kernel has to consume all arguments in the whole message
-                        let slot = match fast_recv(&mut control.borrow_mut().rx).await {
-                            kernel::Message::RpcRecvRequest(slot) => slot,
-                            other => panic!("expected root value slot from core1, not {:?}", other),
-                        };
-                        let remaining_tags = rpc::recv_return(&mut reader, &current_tags, slot, &mut |size| {
-                            if size == 0 {
-                                0 as *mut ()
-                            } else {
-                                let mut control = control.borrow_mut();
-                                control.tx.send(kernel::Message::RpcRecvReply(Ok(size)));
-                                match control.rx.recv() {
-                                    kernel::Message::RpcRecvRequest(slot) => slot,
-                                    other => {
-                                        panic!("expected nested value slot from kernel CPU, not {:?}", other)
-                                    }
-                                }
-                            }
-                        })?;
-                        control
-                            .borrow_mut()
-                            .tx
-                            .async_send(kernel::Message::RpcRecvReply(Ok(0)))
-                            .await;
-                        i += 1;
-                        if i < message.count {
-                            current_tags = remaining_tags;
-                        } else {
-                            break;
-                        }
+                    pass_message_to_kernel(control, &message, &tags).await?;
+                }
+            }
+            #[cfg(has_drtio)]
+            kernel::Message::SubkernelMsgTrySend { id, destination, data } => {
+                let res =
+                    subkernel::message_try_send(aux_mutex, routing_table, timer, id, destination.unwrap(), data).await;
+                let response = match res {
+                    Ok(true) => kernel::Message::SubkernelMsgSent,
+                    Ok(false) => kernel::Message::SubkernelMsgQueueFull,
+                    Err(e) => {
+                        error!("error sending subkernel message: {:?}", e);
+                        kernel::Message::SubkernelMsgSent
+                    }
+                };
+                control.borrow_mut().tx.async_send(response).await;
+            }
+            #[cfg(has_drtio)]
+            kernel::Message::SubkernelMsgTryRecvRequest { id, tags } => {
+                let message_received = subkernel::message_try_receive(id as u32).await;
+                let response = match message_received {
+                    Ok(Some(ref message)) => kernel::Message::SubkernelMsgRecvReply { count: message.count },
+                    Ok(None) => kernel::Message::SubkernelMsgRecvEmpty,
+                    Err(SubkernelError::IncorrectState) => {
+                        kernel::Message::SubkernelError(kernel::SubkernelStatus::IncorrectState)
+                    }
+                    Err(SubkernelError::CommLost) => kernel::Message::SubkernelError(kernel::SubkernelStatus::CommLost),
+                    Err(SubkernelError::Cancelled) => {
+                        kernel::Message::SubkernelError(kernel::SubkernelStatus::Cancelled)
+                    }
+                    Err(SubkernelError::SubkernelException) => {
+                        let status = subkernel::await_finish(aux_mutex, routing_table, timer, id as u32, 0)
+                            .await
+                            .unwrap();
+                        kernel::Message::SubkernelError(kernel::SubkernelStatus::Exception(status.exception.unwrap()))
                     }
+                    Err(_) => kernel::Message::SubkernelError(kernel::SubkernelStatus::OtherError),
+                };
+                control.borrow_mut().tx.async_send(response).await;
+                if let Ok(Some(message)) = message_received {
+                    pass_message_to_kernel(control, &message, &tags).await?;
                 }
             }
             #[cfg(has_drtio)]
@@ -570,6 +656,51 @@ async fn handle_run_kernel(
     Ok(())
 }
 
+#[cfg(has_drtio)]
+async fn pass_message_to_kernel(
+    control: &Rc<RefCell<kernel::Control>>,
+    message: &subkernel::Message,
+    tags: &[u8],
+) -> Result<()> {
+    // receive code almost identical to RPC recv, except we are not reading from a stream
+    let mut reader = Cursor::new(&message.data);
+    let mut current_tags: &[u8] = tags;
+    let mut i = 0;
+    loop {
+        // kernel has to consume all arguments in the whole message
+        let slot = match fast_recv(&mut control.borrow_mut().rx).await {
+            kernel::Message::RpcRecvRequest(slot) => slot,
+            other => panic!("expected root value slot from core1, not {:?}", other),
+        };
+        let remaining_tags = rpc::recv_return(&mut reader, &current_tags, slot, &mut |size| {
+            if size == 0 {
+                0 as *mut ()
+            } else {
+                let mut control = control.borrow_mut();
+                control.tx.send(kernel::Message::RpcRecvReply(Ok(size)));
+                match control.rx.recv() {
+                    kernel::Message::RpcRecvRequest(slot) => slot,
+                    other => {
+                        panic!("expected nested value slot from kernel CPU, not {:?}", other)
+                    }
+                }
+            }
+        })?;
+        control
+            .borrow_mut()
+            .tx
+            .async_send(kernel::Message::RpcRecvReply(Ok(0)))
+            .await;
+        i += 1;
+        if i < message.count {
+            current_tags = remaining_tags;
+        } else {
+            break;
+        }
+    }
+    Ok(())
+}
+
 async fn handle_flash_kernel(
     buffer: &Vec<u8>,
     control: &Rc<RefCell<kernel::Control>>,
@@ -659,12 +790,58 @@ async fn load_kernel(
     }
 }
 
+async fn handle_dma_upload(
+    stream: &TcpStream,
+    aux_mutex: &Rc<Mutex<bool>>,
+    routing_table: &drtio_routing::RoutingTable,
+    cfg: &Config,
+    timer: GlobalTimer,
+) -> Result<()> {
+    let name = read_bytes(stream, 1024).await?;
+    let duration = read_i64(stream).await?;
+    let enable_ddma = read_bool(stream).await?;
+    let buffer = read_bytes(stream, DMA_UPLOAD_MAX).await?;
+    let name = match String::from_utf8(name) {
+        Ok(name) => name,
+        Err(_) => {
+            write_header(stream, Reply::LoadFailed).await?;
+            write_chunk(stream, b"DMA trace name is not valid UTF-8").await?;
+            return Ok(());
+        }
+    };
+    if let Err(e) = rtio_dma::validate_trace(&buffer) {
+        error!("rejected DMA trace {} ({})", name, e);
+        write_header(stream, Reply::LoadFailed).await?;
+        write_chunk(stream, e.as_bytes()).await?;
+        return Ok(());
+    }
+    let recorder = kernel::DmaRecorder {
+        name,
+        buffer,
+        duration,
+        enable_ddma,
+    };
+    match rtio_dma::put_record(cfg, aux_mutex, routing_table, timer, recorder).await {
+        Ok(_id) => {
+            #[cfg(has_drtio)]
+            rtio_dma::remote_dma::upload_traces(aux_mutex, routing_table, timer, _id).await;
+            write_header(stream, Reply::LoadCompleted).await?;
+        }
+        Err(e) => {
+            write_header(stream, Reply::LoadFailed).await?;
+            write_chunk(stream, e.as_bytes()).await?;
+        }
+    }
+    Ok(())
+}
+
 async fn handle_connection(
     stream: &mut TcpStream,
     control: Rc<RefCell<kernel::Control>>,
     up_destinations: &Rc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
     aux_mutex: &Rc<Mutex<bool>>,
     routing_table: &drtio_routing::RoutingTable,
+    cfg: &Config,
     timer: GlobalTimer,
 ) -> Result<()> {
     stream.set_ack_delay(None);
@@ -699,6 +876,7 @@ async fn handle_connection(
                     &up_destinations,
                     aux_mutex,
                     routing_table,
+                    cfg,
                     timer,
                 )
                 .await?;
@@ -726,6 +904,9 @@ async fn handle_connection(
                     return Err(Error::UnexpectedPattern);
                 }
             }
+            Request::UploadDmaTrace => {
+                handle_dma_upload(stream, aux_mutex, routing_table, cfg, timer).await?;
+            }
             _ => {
                 error!("unexpected request from host: {:?}", request);
                 return Err(Error::UnrecognizedPacket);
@@ -740,6 +921,7 @@ async fn load_and_run_idle_kernel(
     up_destinations: &Rc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
     aux_mutex: &Rc<Mutex<bool>>,
     routing_table: &drtio_routing::RoutingTable,
+    cfg: &Config,
     timer: GlobalTimer,
 ) {
     info!("Loading idle kernel");
@@ -749,13 +931,14 @@ async fn load_and_run_idle_kernel(
         _ => (),
     }
     info!("Running idle kernel");
-    let _ = handle_run_kernel(None, control, up_destinations, aux_mutex, routing_table, timer)
+    let _ = handle_run_kernel(None, control, up_destinations, aux_mutex, routing_table, cfg, timer)
         .await
         .map_err(|_| warn!("error running idle kernel"));
     info!("Idle kernel terminated");
 }
 
 pub fn main(timer: GlobalTimer, cfg: Config) {
+    let cfg = Rc::new(cfg);
     let net_addresses = net_settings::get_addresses(&cfg);
     info!("network addresses: {}", net_addresses);
 
@@ -810,6 +993,7 @@ pub fn main(timer: GlobalTimer, cfg: Config) {
 
     rtio_mgt::startup(&aux_mutex, &drtio_routing_table, &up_destinations, &cfg, timer);
     ksupport::setup_device_map(&cfg);
+    rtio_dma::init(&cfg);
 
     analyzer::start(&aux_mutex, &drtio_routing_table, &up_destinations, timer);
     moninj::start(timer, &aux_mutex, &drtio_routing_table);
@@ -834,6 +1018,7 @@ pub fn main(timer: GlobalTimer, cfg: Config) {
                 &up_destinations,
                 &aux_mutex,
                 &routing_table,
+                &cfg,
                 timer,
             ));
             info!("Startup kernel finished!");
@@ -842,7 +1027,15 @@ pub fn main(timer: GlobalTimer, cfg: Config) {
         }
     }
 
-    mgmt::start(cfg);
+    mgmt::start(
+        cfg.clone(),
+        Some(mgmt::DrtioContext {
+            aux_mutex: aux_mutex.clone(),
+            routing_table: drtio_routing_table.clone(),
+            up_destinations: up_destinations.clone(),
+            timer,
+        }),
+    );
 
     task::spawn(async move {
         let connection = Rc::new(Semaphore::new(0, 1));
@@ -855,12 +1048,13 @@ pub fn main(timer: GlobalTimer, cfg: Config) {
             let up_destinations = up_destinations.clone();
             let aux_mutex = aux_mutex.clone();
             let routing_table = drtio_routing_table.clone();
+            let cfg = cfg.clone();
             task::spawn(async move {
                 let routing_table = routing_table.borrow();
                 select_biased! {
                     _ = (async {
                         if let Some(buffer) = &*idle_kernel {
-                            load_and_run_idle_kernel(&buffer, &control, &up_destinations, &aux_mutex, &routing_table, timer).await;
+                            load_and_run_idle_kernel(&buffer, &control, &up_destinations, &aux_mutex, &routing_table, &cfg, timer).await;
                         }
                     }).fuse() => (),
                     _ = terminate.async_wait().fuse() => ()
@@ -885,6 +1079,7 @@ pub fn main(timer: GlobalTimer, cfg: Config) {
             let up_destinations = up_destinations.clone();
             let aux_mutex = aux_mutex.clone();
             let routing_table = drtio_routing_table.clone();
+            let cfg = cfg.clone();
 
             // we make sure the value of terminate is 0 before we start
             let _ = terminate.try_wait();
@@ -892,11 +1087,11 @@ pub fn main(timer: GlobalTimer, cfg: Config) {
                 let routing_table = routing_table.borrow();
                 select_biased! {
                     _ = (async {
-                        let _ = handle_connection(&mut stream, control.clone(), &up_destinations, &aux_mutex, &routing_table, timer)
+                        let _ = handle_connection(&mut stream, control.clone(), &up_destinations, &aux_mutex, &routing_table, &cfg, timer)
                             .await
                             .map_err(|e| warn!("connection terminated: {}", e));
                         if let Some(buffer) = &*idle_kernel {
-                            load_and_run_idle_kernel(&buffer, &control, &up_destinations, &aux_mutex, &routing_table, timer).await;
+                            load_and_run_idle_kernel(&buffer, &control, &up_destinations, &aux_mutex, &routing_table, &cfg, timer).await;
                         }
                     }).fuse() => (),
                     _ = terminate.async_wait().fuse() => ()
@@ -952,7 +1147,7 @@ pub fn soft_panic_main(timer: GlobalTimer, cfg: Config) -> ! {
 
     Sockets::init(32);
 
-    mgmt::start(cfg);
+    mgmt::start(Rc::new(cfg), None);
 
     // getting eth settings disables the LED as it resets GPIO
     // need to re-enable it here
@@ -8,7 +8,7 @@
 #[macro_use]
 extern crate alloc;
 
-#[cfg(all(feature = "target_kasli_soc", has_virtual_leds))]
+#[cfg(feature = "target_kasli_soc")]
 use core::cell::RefCell;
 
 use ksupport;
@@ -43,22 +43,38 @@ extern "C" {
     static __exceptions_start: u32;
 }
 
-#[cfg(all(feature = "target_kasli_soc", has_virtual_leds))]
+#[cfg(feature = "target_kasli_soc")]
 async fn io_expanders_service(
+    timer: GlobalTimer,
     i2c_bus: RefCell<&mut libboard_zynq::i2c::I2c>,
     io_expander0: RefCell<io_expander::IoExpander>,
     io_expander1: RefCell<io_expander::IoExpander>,
 ) {
     loop {
         task::r#yield().await;
-        io_expander0
+        // kernels drive the same bus and switch from core1; leave it to them
+        // until they end, pin changes made meanwhile are applied afterwards
+        if comms::kernel_running() {
+            continue;
+        }
+        if let Err(e) = io_expander0.borrow_mut().service(&mut i2c_bus.borrow_mut()) {
+            warn!("I2C I/O expander #0 service failed ({})", e);
+        }
+        if let Err(e) = io_expander1.borrow_mut().service(&mut i2c_bus.borrow_mut()) {
+            warn!("I2C I/O expander #1 service failed ({})", e);
+        }
+        if let Err(e) = io_expander0
             .borrow_mut()
-            .service(&mut i2c_bus.borrow_mut())
-            .expect("I2C I/O expander #0 service failed");
-        io_expander1
+            .poll_inputs(&mut i2c_bus.borrow_mut(), &timer)
+        {
+            warn!("I2C I/O expander #0 input poll failed ({})", e);
+        }
+        if let Err(e) = io_expander1
             .borrow_mut()
-            .service(&mut i2c_bus.borrow_mut())
-            .expect("I2C I/O expander #1 service failed");
+            .poll_inputs(&mut i2c_bus.borrow_mut(), &timer)
+        {
+            warn!("I2C I/O expander #1 input poll failed ({})", e);
+        }
     }
 }
 
@@ -78,6 +94,28 @@ mod grabber {
     }
 }
 
+#[cfg(has_wrpll)]
+mod wrpll {
+    use libasync::delay;
+    use libboard_artiq::si549::wrpll;
+    use libboard_zynq::time::Milliseconds;
+    use log::{info, warn};
+
+    use crate::GlobalTimer;
+    pub async fn wrpll_thread(timer: GlobalTimer) {
+        let mut countdown = timer.countdown();
+        loop {
+            while let Some(event) = wrpll::take_event() {
+                match event {
+                    wrpll::Event::ReferenceLost => warn!("WRPLL reference lost, entering holdover"),
+                    wrpll::Event::ReferenceRecovered => info!("WRPLL reference recovered, re-acquiring lock"),
+                }
+            }
+            delay(&mut countdown, Milliseconds(200)).await;
+        }
+    }
+}
+
 static mut LOG_BUFFER: [u8; 1 << 17] = [0; 1 << 17];
 
 #[no_mangle]
@@ -100,6 +138,14 @@ pub fn main_core0() {
 
     info!("gateware ident: {}", identifier_read(&mut [0; 64]));
 
+    let cfg = match Config::new() {
+        Ok(cfg) => cfg,
+        Err(err) => {
+            warn!("config initialization failed: {}", err);
+            Config::new_dummy()
+        }
+    };
+
     ksupport::kernel::i2c::init();
     #[cfg(feature = "target_kasli_soc")]
     {
@@ -113,39 +159,20 @@ pub fn main_core0() {
             .init(i2c_bus)
             .expect("I2C I/O expander #1 initialization failed");
 
-        // Drive CLK_SEL to true
-        #[cfg(has_si549)]
-        io_expander0.set(1, 7, true);
-
-        // Drive TX_DISABLE to false on SFP0..3
-        io_expander0.set(0, 1, false);
-        io_expander1.set(0, 1, false);
-        io_expander0.set(1, 1, false);
-        io_expander1.set(1, 1, false);
-
-        // Enable EEM power
-        #[cfg(hw_rev = "v1.2")]
-        io_expander1.set(0, 7, true);
+        // CLK_SEL, SFP TX_DISABLE and EEM power, with overrides from config
+        io_expander::config(&cfg);
 
         io_expander0.service(i2c_bus).unwrap();
         io_expander1.service(i2c_bus).unwrap();
 
-        #[cfg(has_virtual_leds)]
         task::spawn(io_expanders_service(
+            timer,
             RefCell::new(i2c_bus),
             RefCell::new(io_expander0),
             RefCell::new(io_expander1),
         ));
     }
 
-    let cfg = match Config::new() {
-        Ok(cfg) => cfg,
-        Err(err) => {
-            warn!("config initialization failed: {}", err);
-            Config::new_dummy()
-        }
-    };
-
     rtio_clocking::init(&mut timer, &cfg);
 
     #[cfg(has_drtio_eem)]
@@ -154,6 +181,9 @@ pub fn main_core0() {
     #[cfg(has_grabber)]
     task::spawn(grabber::grabber_thread(timer));
 
+    #[cfg(has_wrpll)]
+    task::spawn(wrpll::wrpll_thread(timer));
+
     task::spawn(ksupport::report_async_rtio_errors());
 
     comms::main(timer, cfg);
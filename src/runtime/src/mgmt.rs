@@ -3,14 +3,26 @@ use core::cell::RefCell;
 
 use futures::{future::poll_fn, task::Poll};
 use libasync::{smoltcp::TcpStream, task};
-use libboard_artiq::logger::{BufferLogger, LogBufferRef};
-use libboard_zynq::{slcr, smoltcp};
+#[cfg(has_drtio_eem)]
+use libboard_artiq::drtio_eem;
+#[cfg(has_grabber)]
+use libboard_artiq::grabber;
+#[cfg(feature = "target_kasli_soc")]
+use libboard_artiq::io_expander;
+#[cfg(has_wrpll)]
+use libboard_artiq::si549;
+use libboard_artiq::{drtio_routing::{RoutingTable, DEST_COUNT},
+                     logger::{BufferLogger, LogBufferRef}};
+use libboard_zynq::{slcr, smoltcp, timer::GlobalTimer};
 use libconfig::Config;
+use libcortex_a9::mutex::Mutex;
 use log::{self, debug, error, info, warn, LevelFilter};
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
 
-use crate::proto_async::*;
+use crate::{comms, proto_async::*, rtio_clocking, rtio_dma, rtio_mgt};
+#[cfg(has_drtio)]
+use crate::{rtio_mgt::drtio, subkernel};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Error {
@@ -51,6 +63,33 @@ pub enum Request {
     ConfigRead = 12,
     ConfigWrite = 13,
     ConfigRemove = 14,
+
+    DrtioLatency = 15,
+    RepeaterStats = 16,
+    SetLinkEnabled = 17,
+    SetRepeaterEnabled = 18,
+    SetDestinationEnabled = 19,
+    SatelliteFirmwareUpdate = 20,
+    SatelliteReboot = 21,
+    SetRtioClock = 22,
+    WrpllTelemetry = 23,
+    SetWrpllFilter = 24,
+    WrpllStatus = 25,
+    CalibrateWrpllSkew = 26,
+    RecalibrateEemDrtio = 27,
+    GrabberStatus = 28,
+    IoExpanderPins = 29,
+    SetIoExpanderPin = 30,
+    PersistDmaTrace = 31,
+    ForgetDmaTrace = 32,
+    ListDmaTraces = 33,
+    EraseDmaTrace = 34,
+    EraseAllDmaTraces = 35,
+    DmaMemoryUsage = 36,
+    ListSubkernels = 37,
+    CancelSubkernel = 38,
+    ClearSubkernels = 39,
+    QuerySubkernelCache = 40,
 }
 
 #[repr(i8)]
@@ -60,6 +99,37 @@ pub enum Reply {
     RebootImminent = 3,
     Error = 6,
     ConfigData = 7,
+    DrtioLatency = 8,
+    RepeaterStats = 9,
+    ClockSwitchFailed = 10,
+    WrpllTelemetry = 11,
+    WrpllStatus = 12,
+    EemDrtioCalibration = 13,
+    GrabberStatus = 14,
+    IoExpanderPins = 15,
+    DmaTraces = 16,
+    DmaMemoryUsage = 17,
+    Subkernels = 18,
+    SubkernelCache = 19,
+}
+
+/// DRTIO state owned by comms, not available in soft panic mode
+#[derive(Clone)]
+pub struct DrtioContext {
+    pub aux_mutex: Rc<Mutex<bool>>,
+    pub routing_table: Rc<RefCell<RoutingTable>>,
+    pub up_destinations: Rc<RefCell<[bool; DEST_COUNT]>>,
+    pub timer: GlobalTimer,
+}
+
+// A grabber of the master (destination 0) or of a satellite
+struct GrabberEntry {
+    destination: u8,
+    grabber: u8,
+    state: u8,
+    video_clock: u32,
+    frame_size: (u16, u16),
+    lock_loss_count: u32,
 }
 
 async fn read_log_level_filter(stream: &mut TcpStream) -> Result<log::LevelFilter> {
@@ -111,7 +181,27 @@ async fn read_key(stream: &mut TcpStream) -> Result<String> {
     Ok(String::from_utf8(buffer).unwrap())
 }
 
-async fn handle_connection(stream: &mut TcpStream, pull_id: Rc<RefCell<u32>>, cfg: Rc<Config>) -> Result<()> {
+#[cfg(has_drtio)]
+fn subkernel_state_code(state: subkernel::SubkernelState) -> i8 {
+    match state {
+        subkernel::SubkernelState::NotLoaded => 0,
+        subkernel::SubkernelState::Uploaded => 1,
+        subkernel::SubkernelState::Running => 2,
+        subkernel::SubkernelState::Finished { status } => match status {
+            subkernel::FinishStatus::Ok => 3,
+            subkernel::FinishStatus::CommLost => 4,
+            subkernel::FinishStatus::Exception(_) => 5,
+            subkernel::FinishStatus::Cancelled => 6,
+        },
+    }
+}
+
+async fn handle_connection(
+    stream: &mut TcpStream,
+    pull_id: Rc<RefCell<u32>>,
+    cfg: Rc<Config>,
+    drtio_context: Option<DrtioContext>,
+) -> Result<()> {
     if !expect(&stream, b"ARTIQ management\n").await? {
         return Err(Error::UnexpectedPattern);
     }
@@ -219,6 +309,643 @@ async fn handle_connection(stream: &mut TcpStream, pull_id: Rc<RefCell<u32>>, cf
                     write_i8(stream, Reply::Error as i8).await?;
                 }
             }
+            Request::DrtioLatency => {
+                let destination = read_i8(stream).await? as u8;
+                let count = read_i32(stream).await?;
+                match &drtio_context {
+                    #[cfg(has_drtio)]
+                    Some(ctx) if count > 0 => {
+                        let routing_table = ctx.routing_table.borrow();
+                        let result =
+                            drtio::measure_latency(&ctx.aux_mutex, &routing_table, ctx.timer, destination, count as u32)
+                                .await;
+                        match result {
+                            Ok(stats) => {
+                                info!(
+                                    "[DEST#{}] latency: {}/{} replies, min/avg/max {}/{}/{} mu",
+                                    destination, stats.received, stats.sent, stats.min, stats.avg, stats.max
+                                );
+                                write_i8(stream, Reply::DrtioLatency as i8).await?;
+                                write_i32(stream, stats.sent as i32).await?;
+                                write_i32(stream, stats.received as i32).await?;
+                                write_i64(stream, stats.min as i64).await?;
+                                write_i64(stream, stats.avg as i64).await?;
+                                write_i64(stream, stats.max as i64).await?;
+                                write_i64(stream, stats.elapsed_us as i64).await?;
+                            }
+                            Err(e) => {
+                                warn!("[DEST#{}] latency probe failed ({})", destination, e);
+                                write_i8(stream, Reply::Error as i8).await?;
+                            }
+                        }
+                    }
+                    _ => {
+                        warn!("[DEST#{}] latency probe not available", destination);
+                        write_i8(stream, Reply::Error as i8).await?;
+                    }
+                }
+            }
+            Request::RepeaterStats => {
+                let destination = read_i8(stream).await? as u8;
+                let repno = read_i8(stream).await? as u8;
+                match &drtio_context {
+                    #[cfg(has_drtio)]
+                    Some(ctx) => {
+                        let routing_table = ctx.routing_table.borrow();
+                        match drtio::repeater_stats(&ctx.aux_mutex, &routing_table, ctx.timer, destination, repno).await
+                        {
+                            Ok(Some(stats)) => {
+                                write_i8(stream, Reply::RepeaterStats as i8).await?;
+                                write_bool(stream, stats.up).await?;
+                                write_i32(stream, stats.forwarded_packets as i32).await?;
+                                write_i32(stream, stats.forward_timeouts as i32).await?;
+                                write_i32(stream, stats.rtio_errors as i32).await?;
+                                write_i32(stream, stats.tsc_sync_attempts as i32).await?;
+                            }
+                            Ok(None) => {
+                                warn!("[DEST#{}] no repeater #{}", destination, repno);
+                                write_i8(stream, Reply::Error as i8).await?;
+                            }
+                            Err(e) => {
+                                warn!("[DEST#{}] failed to get repeater statistics ({})", destination, e);
+                                write_i8(stream, Reply::Error as i8).await?;
+                            }
+                        }
+                    }
+                    _ => {
+                        warn!("[DEST#{}] repeater #{} statistics not available", destination, repno);
+                        write_i8(stream, Reply::Error as i8).await?;
+                    }
+                }
+            }
+            Request::SetLinkEnabled => {
+                let linkno = read_i8(stream).await? as u8;
+                let enabled = read_bool(stream).await?;
+                #[cfg(has_drtio)]
+                let result = drtio::set_link_enabled(&cfg, linkno, enabled);
+                #[cfg(not(has_drtio))]
+                let result: core::result::Result<(), &str> = Err("DRTIO not available");
+                match result {
+                    Ok(()) => {
+                        info!("[LINK#{}] {} by management", linkno, if enabled { "enabled" } else { "disabled" });
+                        write_i8(stream, Reply::Success as i8).await?;
+                    }
+                    Err(e) => {
+                        warn!("[LINK#{}] failed to change link state ({})", linkno, e);
+                        write_i8(stream, Reply::Error as i8).await?;
+                    }
+                }
+            }
+            Request::SetDestinationEnabled => {
+                let destination = read_i8(stream).await? as u8;
+                let enabled = read_bool(stream).await?;
+                match &drtio_context {
+                    #[cfg(has_drtio)]
+                    Some(ctx) => {
+                        let routing_table = ctx.routing_table.borrow();
+                        match drtio::set_destination_enabled(&cfg, &routing_table, destination, enabled) {
+                            Ok(()) => {
+                                info!(
+                                    "[DEST#{}] {} by management",
+                                    destination,
+                                    if enabled { "enabled" } else { "disabled" }
+                                );
+                                write_i8(stream, Reply::Success as i8).await?;
+                            }
+                            Err(e) => {
+                                warn!("[DEST#{}] failed to change destination state ({})", destination, e);
+                                write_i8(stream, Reply::Error as i8).await?;
+                            }
+                        }
+                    }
+                    _ => {
+                        warn!("[DEST#{}] destination control not available", destination);
+                        write_i8(stream, Reply::Error as i8).await?;
+                    }
+                }
+            }
+            Request::SetRepeaterEnabled => {
+                let destination = read_i8(stream).await? as u8;
+                let repno = read_i8(stream).await? as u8;
+                let enabled = read_bool(stream).await?;
+                match &drtio_context {
+                    #[cfg(has_drtio)]
+                    Some(ctx) => {
+                        let routing_table = ctx.routing_table.borrow();
+                        let result = drtio::repeater_set_enabled(
+                            &ctx.aux_mutex,
+                            &routing_table,
+                            &ctx.up_destinations,
+                            ctx.timer,
+                            destination,
+                            repno,
+                            enabled,
+                        )
+                        .await;
+                        match result {
+                            Ok(true) => write_i8(stream, Reply::Success as i8).await?,
+                            Ok(false) => {
+                                warn!("[DEST#{}] no repeater #{}", destination, repno);
+                                write_i8(stream, Reply::Error as i8).await?;
+                            }
+                            Err(e) => {
+                                warn!("[DEST#{}] failed to change repeater state ({})", destination, e);
+                                write_i8(stream, Reply::Error as i8).await?;
+                            }
+                        }
+                    }
+                    _ => {
+                        warn!("[DEST#{}] repeater #{} control not available", destination, repno);
+                        write_i8(stream, Reply::Error as i8).await?;
+                    }
+                }
+            }
+            Request::SatelliteFirmwareUpdate => {
+                let destination = read_i8(stream).await? as u8;
+                let len = read_i32(stream).await?;
+                let len = if len <= 0 { 0 } else { len as usize };
+                let mut buffer = Vec::with_capacity(len);
+                unsafe {
+                    buffer.set_len(len);
+                }
+                read_chunk(stream, &mut buffer).await?;
+                match &drtio_context {
+                    #[cfg(has_drtio)]
+                    Some(ctx) if len > 0 => {
+                        info!("[DEST#{}] updating firmware ({} bytes)", destination, len);
+                        let routing_table = ctx.routing_table.borrow();
+                        match drtio::firmware_upload(&ctx.aux_mutex, &routing_table, ctx.timer, destination, &buffer).await
+                        {
+                            Ok(()) => {
+                                info!("[DEST#{}] firmware update complete", destination);
+                                write_i8(stream, Reply::Success as i8).await?;
+                            }
+                            Err(e) => {
+                                error!("[DEST#{}] firmware update failed ({})", destination, e);
+                                write_i8(stream, Reply::Error as i8).await?;
+                            }
+                        }
+                    }
+                    _ => {
+                        warn!("[DEST#{}] firmware update not available", destination);
+                        write_i8(stream, Reply::Error as i8).await?;
+                    }
+                }
+            }
+            Request::SatelliteReboot => {
+                let destination = read_i8(stream).await? as u8;
+                match &drtio_context {
+                    #[cfg(has_drtio)]
+                    Some(ctx) => {
+                        info!("[DEST#{}] rebooting", destination);
+                        let routing_table = ctx.routing_table.borrow();
+                        match drtio::reboot_satellite(&ctx.aux_mutex, &routing_table, ctx.timer, destination).await {
+                            Ok(()) => write_i8(stream, Reply::RebootImminent as i8).await?,
+                            Err(e) => {
+                                warn!("[DEST#{}] reboot failed ({})", destination, e);
+                                write_i8(stream, Reply::Error as i8).await?;
+                            }
+                        }
+                    }
+                    _ => {
+                        warn!("[DEST#{}] reboot not available", destination);
+                        write_i8(stream, Reply::Error as i8).await?;
+                    }
+                }
+            }
+            Request::SetRtioClock => {
+                let setting = read_key(stream).await?;
+                match (rtio_clocking::parse_rtio_clock(&setting), &drtio_context) {
+                    (None, _) => {
+                        warn!("unrecognised rtio_clock setting: {}", setting);
+                        write_i8(stream, Reply::Error as i8).await?;
+                    }
+                    (Some(_), _) if comms::kernel_running() => {
+                        warn!("cannot switch RTIO clock while a kernel is running");
+                        write_i8(stream, Reply::Error as i8).await?;
+                    }
+                    (Some(clk), Some(ctx)) => {
+                        let mut timer = ctx.timer;
+                        match rtio_clocking::switch(&mut timer, clk) {
+                            Ok(()) => {
+                                rtio_mgt::drtio::resync_links();
+                                write_i8(stream, Reply::Success as i8).await?;
+                            }
+                            Err(e) => {
+                                error!("RTIO clock switch failed ({})", e);
+                                write_i8(stream, Reply::ClockSwitchFailed as i8).await?;
+                                write_chunk(stream, e.as_bytes()).await?;
+                            }
+                        }
+                    }
+                    (Some(_), None) => {
+                        warn!("RTIO clock switch not available");
+                        write_i8(stream, Reply::Error as i8).await?;
+                    }
+                }
+            }
+            Request::WrpllTelemetry => {
+                #[cfg(has_wrpll)]
+                {
+                    let mut samples = Vec::new();
+                    samples.resize(si549::wrpll::TELEMETRY_LEN, si549::wrpll::Sample::default());
+                    let (total, count) = si549::wrpll::telemetry(&mut samples);
+                    write_i8(stream, Reply::WrpllTelemetry as i8).await?;
+                    write_i32(stream, total as i32).await?;
+                    write_i32(stream, count as i32).await?;
+                    for sample in samples[..count].iter() {
+                        write_i32(stream, sample.period_err).await?;
+                        write_i32(stream, sample.phase_err).await?;
+                        write_i32(stream, sample.helper_adpll).await?;
+                        write_i32(stream, sample.main_adpll).await?;
+                    }
+                }
+                #[cfg(not(has_wrpll))]
+                {
+                    warn!("WRPLL telemetry not available");
+                    write_i8(stream, Reply::Error as i8).await?;
+                }
+            }
+            Request::SetWrpllFilter => {
+                let coefficients = read_key(stream).await?;
+                #[cfg(has_wrpll)]
+                match coefficients.parse() {
+                    Ok(params) => {
+                        si549::wrpll::set_filter(params);
+                        write_i8(stream, Reply::Success as i8).await?;
+                    }
+                    Err(e) => {
+                        warn!("invalid WRPLL filter \"{}\" ({})", coefficients, e);
+                        write_i8(stream, Reply::Error as i8).await?;
+                    }
+                }
+                #[cfg(not(has_wrpll))]
+                {
+                    warn!("WRPLL filter \"{}\" not applied, WRPLL not available", coefficients);
+                    write_i8(stream, Reply::Error as i8).await?;
+                }
+            }
+            Request::WrpllStatus => {
+                #[cfg(has_wrpll)]
+                {
+                    let (holdover, holdover_count) = si549::wrpll::holdover_status();
+                    write_i8(stream, Reply::WrpllStatus as i8).await?;
+                    write_bool(stream, holdover).await?;
+                    write_i32(stream, holdover_count as i32).await?;
+                }
+                #[cfg(not(has_wrpll))]
+                {
+                    warn!("WRPLL status not available");
+                    write_i8(stream, Reply::Error as i8).await?;
+                }
+            }
+            Request::CalibrateWrpllSkew => {
+                let destination = read_i8(stream).await? as u8;
+                match &drtio_context {
+                    #[cfg(has_drtio)]
+                    Some(ctx) => {
+                        info!("[DEST#{}] requesting WRPLL skew calibration", destination);
+                        let routing_table = ctx.routing_table.borrow();
+                        match drtio::wrpll_recalibrate(&ctx.aux_mutex, &routing_table, ctx.timer, destination).await {
+                            Ok(true) => write_i8(stream, Reply::Success as i8).await?,
+                            Ok(false) => {
+                                warn!("[DEST#{}] WRPLL skew calibration failed or not supported", destination);
+                                write_i8(stream, Reply::Error as i8).await?;
+                            }
+                            Err(e) => {
+                                warn!("[DEST#{}] WRPLL skew calibration failed ({})", destination, e);
+                                write_i8(stream, Reply::Error as i8).await?;
+                            }
+                        }
+                    }
+                    _ => {
+                        warn!("[DEST#{}] WRPLL skew calibration not available", destination);
+                        write_i8(stream, Reply::Error as i8).await?;
+                    }
+                }
+            }
+            Request::RecalibrateEemDrtio => {
+                let trx_no = read_i8(stream).await? as u8;
+                match &drtio_context {
+                    _ if comms::kernel_running() => {
+                        warn!("cannot recalibrate EEM transceiver while a kernel is running");
+                        write_i8(stream, Reply::Error as i8).await?;
+                    }
+                    #[cfg(has_drtio_eem)]
+                    Some(ctx) => {
+                        let mut timer = ctx.timer;
+                        match drtio_eem::recalibrate(trx_no, &mut timer, &cfg) {
+                            Ok(status) => {
+                                rtio_mgt::drtio::resync_links();
+                                write_i8(stream, Reply::EemDrtioCalibration as i8).await?;
+                                for (delay, score) in status.delay.iter().zip(status.score.iter()) {
+                                    write_i8(stream, *delay as i8).await?;
+                                    // IEEE 754 single precision
+                                    write_i32(stream, score.to_bits() as i32).await?;
+                                }
+                            }
+                            Err(e) => {
+                                error!("EEM transceiver {} recalibration failed ({})", trx_no, e);
+                                write_i8(stream, Reply::Error as i8).await?;
+                            }
+                        }
+                    }
+                    _ => {
+                        warn!("EEM DRTIO recalibration not available");
+                        write_i8(stream, Reply::Error as i8).await?;
+                    }
+                }
+            }
+            Request::GrabberStatus => {
+                #[allow(unused_mut)]
+                let mut entries: Vec<GrabberEntry> = Vec::new();
+                #[cfg(has_grabber)]
+                for g in 0..grabber::GRABBER_COUNT {
+                    let status = grabber::status(g).unwrap();
+                    entries.push(GrabberEntry {
+                        destination: 0,
+                        grabber: g as u8,
+                        state: status.state as u8,
+                        video_clock: status.video_clock,
+                        frame_size: status.frame_size,
+                        lock_loss_count: status.lock_loss_count,
+                    });
+                }
+                #[cfg(has_drtio)]
+                if let Some(ctx) = &drtio_context {
+                    let routing_table = ctx.routing_table.borrow();
+                    let up_destinations = *ctx.up_destinations.borrow();
+                    for destination in 0..DEST_COUNT {
+                        // local destinations are covered above
+                        if !up_destinations[destination] || routing_table.0[destination][0] == 0 {
+                            continue;
+                        }
+                        match drtio::grabber_status(&ctx.aux_mutex, &routing_table, ctx.timer, destination as u8).await
+                        {
+                            Ok(statuses) => {
+                                for (g, status) in statuses.iter().enumerate() {
+                                    entries.push(GrabberEntry {
+                                        destination: destination as u8,
+                                        grabber: g as u8,
+                                        state: status.state,
+                                        video_clock: status.video_clock,
+                                        frame_size: status.frame_size,
+                                        lock_loss_count: status.lock_loss_count,
+                                    });
+                                }
+                            }
+                            Err(e) => warn!("[DEST#{}] grabber status unavailable ({})", destination, e),
+                        }
+                    }
+                }
+                write_i8(stream, Reply::GrabberStatus as i8).await?;
+                write_i32(stream, entries.len() as i32).await?;
+                for entry in entries.iter() {
+                    write_i8(stream, entry.destination as i8).await?;
+                    write_i8(stream, entry.grabber as i8).await?;
+                    write_i8(stream, entry.state as i8).await?;
+                    write_i32(stream, entry.video_clock as i32).await?;
+                    write_i32(stream, entry.frame_size.0 as i32).await?;
+                    write_i32(stream, entry.frame_size.1 as i32).await?;
+                    write_i32(stream, entry.lock_loss_count as i32).await?;
+                }
+            }
+            Request::IoExpanderPins => {
+                #[cfg(feature = "target_kasli_soc")]
+                {
+                    let pins = io_expander::pins();
+                    write_i8(stream, Reply::IoExpanderPins as i8).await?;
+                    write_i32(stream, pins.len() as i32).await?;
+                    for pin in pins.iter() {
+                        write_chunk(stream, pin.name.as_bytes()).await?;
+                        write_bool(stream, pin.output).await?;
+                        write_bool(stream, pin.level).await?;
+                    }
+                }
+                #[cfg(not(feature = "target_kasli_soc"))]
+                {
+                    warn!("I/O expanders not available");
+                    write_i8(stream, Reply::Error as i8).await?;
+                }
+            }
+            Request::SetIoExpanderPin => {
+                let name = read_key(stream).await?;
+                let _level = read_bool(stream).await?;
+                #[cfg(feature = "target_kasli_soc")]
+                match io_expander::set_pin(&name, _level) {
+                    Ok(()) => write_i8(stream, Reply::Success as i8).await?,
+                    Err(e) => {
+                        warn!("cannot set I/O expander pin {} ({})", name, e);
+                        write_i8(stream, Reply::Error as i8).await?;
+                    }
+                }
+                #[cfg(not(feature = "target_kasli_soc"))]
+                {
+                    warn!("I/O expanders not available, cannot set {}", name);
+                    write_i8(stream, Reply::Error as i8).await?;
+                }
+            }
+            Request::PersistDmaTrace => {
+                let name = read_key(stream).await?;
+                match rtio_dma::persist(&cfg, &name).await {
+                    Ok(()) => write_i8(stream, Reply::Success as i8).await?,
+                    Err(e) => {
+                        warn!("cannot persist DMA trace {} ({})", name, e);
+                        write_i8(stream, Reply::Error as i8).await?;
+                    }
+                }
+            }
+            Request::ForgetDmaTrace => {
+                let name = read_key(stream).await?;
+                match rtio_dma::forget(&cfg, &name) {
+                    Ok(()) => write_i8(stream, Reply::Success as i8).await?,
+                    Err(e) => {
+                        warn!("cannot forget DMA trace {} ({})", name, e);
+                        write_i8(stream, Reply::Error as i8).await?;
+                    }
+                }
+            }
+            Request::ListDmaTraces => {
+                let traces = rtio_dma::list().await;
+                write_i8(stream, Reply::DmaTraces as i8).await?;
+                write_i32(stream, traces.len() as i32).await?;
+                for trace in traces.iter() {
+                    write_chunk(stream, trace.name.as_bytes()).await?;
+                    write_i32(stream, trace.size as i32).await?;
+                    write_i64(stream, trace.duration).await?;
+                    write_bool(stream, !trace.destinations.is_empty()).await?;
+                    write_i8(stream, trace.destinations.len() as i8).await?;
+                    for destination in trace.destinations.iter() {
+                        write_i8(stream, *destination as i8).await?;
+                    }
+                }
+            }
+            Request::EraseDmaTrace => {
+                let name = read_key(stream).await?;
+                match &drtio_context {
+                    // the kernel may be playing the trace back
+                    _ if comms::kernel_running() => {
+                        warn!("cannot erase DMA trace {} while a kernel is running", name);
+                        write_i8(stream, Reply::Error as i8).await?;
+                    }
+                    Some(ctx) => {
+                        let routing_table = ctx.routing_table.borrow();
+                        if rtio_dma::erase(name.clone(), &ctx.aux_mutex, &routing_table, ctx.timer).await {
+                            // do not bring the erased trace back at the next boot
+                            let _ = rtio_dma::forget(&cfg, &name);
+                            write_i8(stream, Reply::Success as i8).await?;
+                        } else {
+                            warn!("DMA trace {} not found", name);
+                            write_i8(stream, Reply::Error as i8).await?;
+                        }
+                    }
+                    None => {
+                        warn!("DMA not available");
+                        write_i8(stream, Reply::Error as i8).await?;
+                    }
+                }
+            }
+            Request::EraseAllDmaTraces => match &drtio_context {
+                _ if comms::kernel_running() => {
+                    warn!("cannot erase DMA traces while a kernel is running");
+                    write_i8(stream, Reply::Error as i8).await?;
+                }
+                Some(ctx) => {
+                    let routing_table = ctx.routing_table.borrow();
+                    rtio_dma::erase_all(&ctx.aux_mutex, &routing_table, ctx.timer).await;
+                    match rtio_dma::forget_all(&cfg) {
+                        Ok(()) => write_i8(stream, Reply::Success as i8).await?,
+                        Err(e) => {
+                            warn!("cannot forget persisted DMA traces ({})", e);
+                            write_i8(stream, Reply::Error as i8).await?;
+                        }
+                    }
+                }
+                None => {
+                    warn!("DMA not available");
+                    write_i8(stream, Reply::Error as i8).await?;
+                }
+            },
+            Request::DmaMemoryUsage => {
+                let used = rtio_dma::memory_usage().await;
+                write_i8(stream, Reply::DmaMemoryUsage as i8).await?;
+                write_i32(stream, used as i32).await?;
+                // 0 if unlimited
+                write_i32(stream, rtio_dma::memory_limit() as i32).await?;
+            }
+            Request::ListSubkernels => {
+                write_i8(stream, Reply::Subkernels as i8).await?;
+                #[cfg(has_drtio)]
+                {
+                    let subkernels = subkernel::list().await;
+                    write_i32(stream, subkernels.len() as i32).await?;
+                    for entry in subkernels.iter() {
+                        write_i32(stream, entry.id as i32).await?;
+                        write_i8(stream, entry.destination as i8).await?;
+                        write_i8(stream, subkernel_state_code(entry.state)).await?;
+                        write_i32(stream, entry.size as i32).await?;
+                    }
+                }
+                #[cfg(not(has_drtio))]
+                write_i32(stream, 0).await?;
+            }
+            Request::CancelSubkernel => {
+                let id = read_i32(stream).await? as u32;
+                match &drtio_context {
+                    #[cfg(has_drtio)]
+                    Some(ctx) => {
+                        let routing_table = ctx.routing_table.borrow();
+                        match subkernel::cancel(&ctx.aux_mutex, &routing_table, ctx.timer, id).await {
+                            Ok(()) => {
+                                info!("subkernel {} cancelled", id);
+                                write_i8(stream, Reply::Success as i8).await?;
+                            }
+                            Err(e) => {
+                                warn!("cannot cancel subkernel {} ({:?})", id, e);
+                                write_i8(stream, Reply::Error as i8).await?;
+                            }
+                        }
+                    }
+                    _ => {
+                        warn!("cannot cancel subkernel {}, subkernels not available", id);
+                        write_i8(stream, Reply::Error as i8).await?;
+                    }
+                }
+            }
+            Request::ClearSubkernels => match &drtio_context {
+                // the kernel may still refer to its subkernels
+                _ if comms::kernel_running() => {
+                    warn!("cannot clear subkernels while a kernel is running");
+                    write_i8(stream, Reply::Error as i8).await?;
+                }
+                #[cfg(has_drtio)]
+                Some(ctx) => {
+                    let routing_table = ctx.routing_table.borrow();
+                    subkernel::cancel_all(&ctx.aux_mutex, &routing_table, ctx.timer).await;
+                    subkernel::clear_subkernels().await;
+                    write_i8(stream, Reply::Success as i8).await?;
+                }
+                _ => {
+                    warn!("subkernels not available");
+                    write_i8(stream, Reply::Error as i8).await?;
+                }
+            },
+            Request::QuerySubkernelCache => {
+                // subkernels are identified by the CRC-32 and length of their ELF
+                let destination = read_i8(stream).await? as u8;
+                let count = read_i32(stream).await?;
+                let mut queries = Vec::new();
+                for _ in 0..count {
+                    let hash = read_i32(stream).await? as u32;
+                    let length = read_i32(stream).await? as u32;
+                    queries.push((hash, length));
+                }
+                match &drtio_context {
+                    #[cfg(has_drtio)]
+                    Some(ctx) => {
+                        let routing_table = ctx.routing_table.borrow();
+                        let mut present = Vec::new();
+                        let mut result = Ok(());
+                        for (hash, length) in queries.iter() {
+                            match drtio::subkernel_query_cache(
+                                &ctx.aux_mutex,
+                                &routing_table,
+                                ctx.timer,
+                                0,
+                                destination,
+                                *hash,
+                                *length,
+                                false,
+                            )
+                            .await
+                            {
+                                Ok(cached) => present.push(cached),
+                                Err(e) => {
+                                    result = Err(e);
+                                    break;
+                                }
+                            }
+                        }
+                        match result {
+                            Ok(()) => {
+                                write_i8(stream, Reply::SubkernelCache as i8).await?;
+                                write_i32(stream, present.len() as i32).await?;
+                                for cached in present.iter() {
+                                    write_bool(stream, *cached).await?;
+                                }
+                            }
+                            Err(e) => {
+                                warn!("[DEST#{}] subkernel cache query failed ({})", destination, e);
+                                write_i8(stream, Reply::Error as i8).await?;
+                            }
+                        }
+                    }
+                    _ => {
+                        warn!("[DEST#{}] subkernel cache not available ({} queries)", destination, queries.len());
+                        write_i8(stream, Reply::Error as i8).await?;
+                    }
+                }
+            }
             Request::Reboot => {
                 info!("rebooting");
                 write_i8(stream, Reply::RebootImminent as i8).await?;
@@ -229,17 +956,17 @@ async fn handle_connection(stream: &mut TcpStream, pull_id: Rc<RefCell<u32>>, cf
     }
 }
 
-pub fn start(cfg: Config) {
+pub fn start(cfg: Rc<Config>, drtio_context: Option<DrtioContext>) {
     task::spawn(async move {
         let pull_id = Rc::new(RefCell::new(0u32));
-        let cfg = Rc::new(cfg);
         loop {
             let mut stream = TcpStream::accept(1380, 2048, 2048).await.unwrap();
             let pull_id = pull_id.clone();
             let cfg = cfg.clone();
+            let drtio_context = drtio_context.clone();
             task::spawn(async move {
                 info!("received connection");
-                let _ = handle_connection(&mut stream, pull_id, cfg)
+                let _ = handle_connection(&mut stream, pull_id, cfg, drtio_context)
                     .await
                     .map_err(|e| warn!("connection terminated: {:?}", e));
                 let _ = stream.flush().await;
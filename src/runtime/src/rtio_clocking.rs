@@ -24,28 +24,55 @@ pub enum RtioClock {
     Ext0_Synth0_80to125,
     Ext0_Synth0_100to125,
     Ext0_Synth0_125to125,
+    // any other external reference and output frequency, in MHz
+    Ext0_Synth0(u32, u32),
+}
+
+// Reference and output frequencies in MHz of the external synthesized clocks
+#[cfg(has_si549)]
+fn synth_frequencies(clk: RtioClock) -> Option<(u32, u32)> {
+    match clk {
+        RtioClock::Ext0_Synth0_10to125 => Some((10, 125)),
+        RtioClock::Ext0_Synth0_80to125 => Some((80, 125)),
+        RtioClock::Ext0_Synth0_100to125 => Some((100, 125)),
+        RtioClock::Ext0_Synth0_125to125 => Some((125, 125)),
+        RtioClock::Ext0_Synth0(ref_freq, out_freq) => Some((ref_freq, out_freq)),
+        _ => None,
+    }
+}
+
+pub fn parse_rtio_clock(clk: &str) -> Option<RtioClock> {
+    match clk {
+        "int_125" => Some(RtioClock::Int_125),
+        "int_100" => Some(RtioClock::Int_100),
+        "int_150" => Some(RtioClock::Int_150),
+        "ext0_bypass" => Some(RtioClock::Ext0_Bypass),
+        "ext0_bypass_125" => Some(RtioClock::Ext0_Bypass),
+        "ext0_bypass_100" => Some(RtioClock::Ext0_Bypass),
+        "ext0_synth0_10to125" => Some(RtioClock::Ext0_Synth0_10to125),
+        "ext0_synth0_80to125" => Some(RtioClock::Ext0_Synth0_80to125),
+        "ext0_synth0_100to125" => Some(RtioClock::Ext0_Synth0_100to125),
+        "ext0_synth0_125to125" => Some(RtioClock::Ext0_Synth0_125to125),
+        _ => {
+            let mut freqs = clk.strip_prefix("ext0_synth0_")?.splitn(2, "to");
+            let ref_freq = freqs.next()?.parse().ok()?;
+            let out_freq = freqs.next()?.parse().ok()?;
+            if ref_freq == 0 || out_freq == 0 {
+                return None;
+            }
+            Some(RtioClock::Ext0_Synth0(ref_freq, out_freq))
+        }
+    }
 }
 
 #[allow(unreachable_code)]
 fn get_rtio_clock_cfg(cfg: &Config) -> RtioClock {
     let mut res = RtioClock::Default;
     if let Ok(clk) = cfg.read_str("rtio_clock") {
-        res = match clk.as_ref() {
-            "int_125" => RtioClock::Int_125,
-            "int_100" => RtioClock::Int_100,
-            "int_150" => RtioClock::Int_150,
-            "ext0_bypass" => RtioClock::Ext0_Bypass,
-            "ext0_bypass_125" => RtioClock::Ext0_Bypass,
-            "ext0_bypass_100" => RtioClock::Ext0_Bypass,
-            "ext0_synth0_10to125" => RtioClock::Ext0_Synth0_10to125,
-            "ext0_synth0_80to125" => RtioClock::Ext0_Synth0_80to125,
-            "ext0_synth0_100to125" => RtioClock::Ext0_Synth0_100to125,
-            "ext0_synth0_125to125" => RtioClock::Ext0_Synth0_125to125,
-            _ => {
-                warn!("Unrecognised rtio_clock setting. Falling back to default.");
-                RtioClock::Default
-            }
-        };
+        res = parse_rtio_clock(clk.as_ref()).unwrap_or_else(|| {
+            warn!("Unrecognised rtio_clock setting. Falling back to default.");
+            RtioClock::Default
+        });
     } else {
         warn!("error reading configuration. Falling back to default.");
     }
@@ -70,7 +97,7 @@ fn get_rtio_clock_cfg(cfg: &Config) -> RtioClock {
 }
 
 #[cfg(not(has_drtio))]
-fn init_rtio(timer: &mut GlobalTimer) {
+fn init_rtio(timer: &mut GlobalTimer) -> Result<(), &'static str> {
     info!("Switching SYS clocks...");
     unsafe {
         pl::csr::sys_crg::clock_switch_write(1);
@@ -82,16 +109,17 @@ fn init_rtio(timer: &mut GlobalTimer) {
     if clk == 1 {
         info!("SYS CLK switched successfully");
     } else {
-        panic!("SYS CLK did not switch");
+        return Err("SYS CLK did not switch");
     }
     unsafe {
         pl::csr::rtio_core::reset_phy_write(1);
     }
     info!("SYS PLL locked");
+    Ok(())
 }
 
 #[cfg(has_drtio)]
-fn init_drtio(timer: &mut GlobalTimer) {
+fn init_drtio(timer: &mut GlobalTimer) -> Result<(), &'static str> {
     unsafe {
         pl::csr::gt_drtio::stable_clkin_write(1);
     }
@@ -101,7 +129,7 @@ fn init_drtio(timer: &mut GlobalTimer) {
     if clk == 1 {
         info!("SYS CLK switched successfully");
     } else {
-        panic!("SYS CLK did not switch");
+        return Err("SYS CLK did not switch");
     }
     unsafe {
         pl::csr::rtio_core::reset_phy_write(1);
@@ -110,6 +138,7 @@ fn init_drtio(timer: &mut GlobalTimer) {
         #[cfg(has_drtio_eem)]
         pl::csr::eem_transceiver::txenable_write(0xffffffffu32 as _);
     }
+    Ok(())
 }
 
 // Si5324 input to select for locking to an external clock.
@@ -117,7 +146,7 @@ fn init_drtio(timer: &mut GlobalTimer) {
 const SI5324_EXT_INPUT: si5324::Input = si5324::Input::Ckin1;
 
 #[cfg(has_si5324)]
-fn setup_si5324(i2c: &mut I2c, timer: &mut GlobalTimer, clk: RtioClock) {
+fn setup_si5324(i2c: &mut I2c, timer: &mut GlobalTimer, clk: RtioClock) -> Result<(), &'static str> {
     let (si5324_settings, si5324_ref_input) = match clk {
         RtioClock::Ext0_Synth0_10to125 => {
             // 125 MHz output from 10 MHz CLKINx reference, 504 Hz BW
@@ -187,6 +216,17 @@ fn setup_si5324(i2c: &mut I2c, timer: &mut GlobalTimer, clk: RtioClock) {
                 SI5324_EXT_INPUT,
             )
         }
+        RtioClock::Ext0_Synth0(ref_freq, out_freq) => {
+            // no preconfigured setting, solve for the dividers
+            info!(
+                "using {}MHz reference to make {}MHz RTIO clock with PLL",
+                ref_freq, out_freq
+            );
+            (
+                si5324::FrequencySettings::solve(ref_freq as u64 * 1_000_000, out_freq as u64 * 1_000_000, 4, false)?,
+                SI5324_EXT_INPUT,
+            )
+        }
         RtioClock::Int_150 => {
             // 150MHz output, from crystal
             info!("using internal 150MHz RTIO clock");
@@ -259,190 +299,116 @@ fn setup_si5324(i2c: &mut I2c, timer: &mut GlobalTimer, clk: RtioClock) {
             )
         }
     };
-    si5324::setup(i2c, &si5324_settings, si5324_ref_input, timer).expect("cannot initialize Si5324");
+    si5324::setup(i2c, &si5324_settings, si5324_ref_input, timer)
 }
 
 #[cfg(all(has_si549, has_wrpll))]
-fn wrpll_setup(timer: &mut GlobalTimer, clk: RtioClock, si549_settings: &si549::FrequencySetting) {
-    // register values are directly copied from preconfigured mmcm
-    let (mmcm_setting, mmcm_bypass) = match clk {
-        RtioClock::Ext0_Synth0_10to125 => (
-            si549::wrpll_refclk::MmcmSetting {
-                // CLKFBOUT_MULT = 62.5, DIVCLK_DIVIDE = 1 , CLKOUT0_DIVIDE = 5
-                clkout0_reg1: 0x1083,
-                clkout0_reg2: 0x0080,
-                clkfbout_reg1: 0x179e,
-                clkfbout_reg2: 0x4c00,
-                div_reg: 0x1041,
-                lock_reg1: 0x00fa,
-                lock_reg2: 0x7c01,
-                lock_reg3: 0xffe9,
-                power_reg: 0x9900,
-                filt_reg1: 0x1008,
-                filt_reg2: 0x8800,
-            },
-            false,
-        ),
-        RtioClock::Ext0_Synth0_80to125 => (
-            si549::wrpll_refclk::MmcmSetting {
-                // CLKFBOUT_MULT = 15.625, DIVCLK_DIVIDE = 1 , CLKOUT0_DIVIDE = 10
-                clkout0_reg1: 0x1145,
-                clkout0_reg2: 0x0000,
-                clkfbout_reg1: 0x11c7,
-                clkfbout_reg2: 0x5880,
-                div_reg: 0x1041,
-                lock_reg1: 0x028a,
-                lock_reg2: 0x7c01,
-                lock_reg3: 0xffe9,
-                power_reg: 0x9900,
-                filt_reg1: 0x9908,
-                filt_reg2: 0x8100,
-            },
-            false,
-        ),
-        RtioClock::Ext0_Synth0_100to125 => (
-            si549::wrpll_refclk::MmcmSetting {
-                // CLKFBOUT_MULT = 12.5, DIVCLK_DIVIDE = 1 , CLKOUT0_DIVIDE = 10
-                clkout0_reg1: 0x1145,
-                clkout0_reg2: 0x0000,
-                clkfbout_reg1: 0x1145,
-                clkfbout_reg2: 0x4c00,
-                div_reg: 0x1041,
-                lock_reg1: 0x0339,
-                lock_reg2: 0x7c01,
-                lock_reg3: 0xffe9,
-                power_reg: 0x9900,
-                filt_reg1: 0x9108,
-                filt_reg2: 0x0100,
-            },
-            false,
-        ),
-        RtioClock::Ext0_Synth0_125to125 => (
-            si549::wrpll_refclk::MmcmSetting {
-                // CLKFBOUT_MULT = 10, DIVCLK_DIVIDE = 1 , CLKOUT0_DIVIDE = 10
-                clkout0_reg1: 0x1145,
-                clkout0_reg2: 0x0000,
-                clkfbout_reg1: 0x1145,
-                clkfbout_reg2: 0x0000,
-                div_reg: 0x1041,
-                lock_reg1: 0x03e8,
-                lock_reg2: 0x7001,
-                lock_reg3: 0xf3e9,
-                power_reg: 0x0100,
-                filt_reg1: 0x9908,
-                filt_reg2: 0x1100,
-            },
-            true,
-        ),
-        _ => unreachable!(),
-    };
+fn wrpll_setup(
+    timer: &mut GlobalTimer,
+    clk: RtioClock,
+    si549_settings: &si549::FrequencySetting,
+) -> Result<(), &'static str> {
+    let (ref_freq, out_freq) = synth_frequencies(clk).unwrap();
+    let mmcm_setting = si549::wrpll_refclk::MmcmSetting::new(ref_freq as u64 * 1_000_000, out_freq as u64 * 1_000_000)?;
+    let mmcm_bypass = ref_freq == out_freq;
 
-    si549::helper_setup(timer, &si549_settings).expect("cannot initialize helper Si549");
-    si549::wrpll_refclk::setup(timer, mmcm_setting, mmcm_bypass).expect("cannot initialize ref clk for wrpll");
+    si549::helper_setup(timer, &si549_settings)?;
+    si549::wrpll_refclk::setup(timer, mmcm_setting, mmcm_bypass)?;
     si549::wrpll::select_recovered_clock(true, timer);
+    Ok(())
 }
 
 #[cfg(has_si549)]
-fn get_si549_setting(clk: RtioClock) -> si549::FrequencySetting {
-    match clk {
-        RtioClock::Ext0_Synth0_10to125 => {
-            info!("using 10MHz reference to make 125MHz RTIO clock with WRPLL");
-        }
-        RtioClock::Ext0_Synth0_80to125 => {
-            info!("using 80MHz reference to make 125MHz RTIO clock with WRPLL");
-        }
-        RtioClock::Ext0_Synth0_100to125 => {
-            info!("using 100MHz reference to make 125MHz RTIO clock with WRPLL");
-        }
-        RtioClock::Ext0_Synth0_125to125 => {
-            info!("using 125MHz reference to make 125MHz RTIO clock with WRPLL");
-        }
+fn get_si549_setting(clk: RtioClock) -> Result<si549::FrequencySetting, &'static str> {
+    let freq = match clk {
         RtioClock::Int_100 => {
             info!("using internal 100MHz RTIO clock");
+            100
         }
         RtioClock::Int_125 => {
             info!("using internal 125MHz RTIO clock");
+            125
         }
-        _ => {
-            warn!(
-                "rtio_clock setting '{:?}' is unsupported. Falling back to default internal 125MHz RTIO clock.",
-                clk
-            );
-        }
-    };
-
-    match clk {
-        RtioClock::Int_100 => {
-            si549::FrequencySetting {
-                main: si549::DividerConfig {
-                    hsdiv: 0x06C,
-                    lsdiv: 0,
-                    fbdiv: 0x046C5F49797,
-                },
-                helper: si549::DividerConfig {
-                    // 100MHz*32767/32768
-                    hsdiv: 0x06C,
-                    lsdiv: 0,
-                    fbdiv: 0x046C5670BBD,
-                },
+        _ => match synth_frequencies(clk) {
+            Some((ref_freq, out_freq)) => {
+                info!(
+                    "using {}MHz reference to make {}MHz RTIO clock with WRPLL",
+                    ref_freq, out_freq
+                );
+                out_freq
             }
-        }
-        _ => {
-            // Everything else use 125MHz
-            si549::FrequencySetting {
-                main: si549::DividerConfig {
-                    hsdiv: 0x058,
-                    lsdiv: 0,
-                    fbdiv: 0x04815791F25,
-                },
-                helper: si549::DividerConfig {
-                    // 125MHz*32767/32768
-                    hsdiv: 0x058,
-                    lsdiv: 0,
-                    fbdiv: 0x04814E8F442,
-                },
+            None => {
+                warn!(
+                    "rtio_clock setting '{:?}' is unsupported. Falling back to default internal 125MHz RTIO clock.",
+                    clk
+                );
+                125
             }
-        }
-    }
+        },
+    };
+    si549::FrequencySetting::new(freq as u64 * 1_000_000)
 }
 
-pub fn init(timer: &mut GlobalTimer, cfg: &Config) {
-    let clk = get_rtio_clock_cfg(cfg);
+fn setup_clock(timer: &mut GlobalTimer, clk: RtioClock) -> Result<(), &'static str> {
     #[cfg(has_si5324)]
     {
         let i2c = unsafe { (&mut i2c::I2C_BUS).as_mut().unwrap() };
         match clk {
             RtioClock::Ext0_Bypass => {
                 info!("bypassing the PLL for RTIO clock");
-                si5324::bypass(i2c, SI5324_EXT_INPUT, timer).expect("cannot bypass Si5324")
+                si5324::bypass(i2c, SI5324_EXT_INPUT, timer)?
             }
-            _ => setup_si5324(i2c, timer, clk),
+            _ => setup_si5324(i2c, timer, clk)?,
         }
     }
 
     #[cfg(has_si549)]
-    let si549_settings = get_si549_setting(clk);
+    let si549_settings = get_si549_setting(clk)?;
 
     #[cfg(has_si549)]
-    si549::main_setup(timer, &si549_settings).expect("cannot initialize main Si549");
+    si549::main_setup(timer, &si549_settings)?;
 
     #[cfg(has_drtio)]
-    init_drtio(timer);
+    init_drtio(timer)?;
 
     #[cfg(not(has_drtio))]
-    init_rtio(timer);
+    init_rtio(timer)?;
 
     #[cfg(all(has_si549, has_wrpll))]
     {
         // SYS CLK switch will reset CSRs that are used by WRPLL
-        match clk {
-            RtioClock::Ext0_Synth0_10to125
-            | RtioClock::Ext0_Synth0_80to125
-            | RtioClock::Ext0_Synth0_100to125
-            | RtioClock::Ext0_Synth0_125to125 => {
-                wrpll_setup(timer, clk, &si549_settings);
-            }
-            _ => {}
+        if synth_frequencies(clk).is_some() {
+            wrpll_setup(timer, clk, &si549_settings)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn init(timer: &mut GlobalTimer, cfg: &Config) {
+    let clk = get_rtio_clock_cfg(cfg);
+    #[cfg(has_wrpll)]
+    si549::wrpll::config_filter(cfg);
+    setup_clock(timer, clk).expect("cannot initialize RTIO clock");
+}
+
+/// Reconfigures the RTIO clock of a running system. The caller must make sure
+/// that no kernel is running, and DRTIO links have to be re-synchronized afterwards.
+pub fn switch(timer: &mut GlobalTimer, clk: RtioClock) -> Result<(), &'static str> {
+    info!("switching RTIO clock to {:?}", clk);
+    #[cfg(all(has_si549, has_wrpll))]
+    si549::wrpll::select_recovered_clock(false, timer);
+    // run from the bootstrap clock while the synthesizers are reprogrammed
+    unsafe {
+        #[cfg(has_drtio)]
+        {
+            pl::csr::gt_drtio::txenable_write(0);
+            pl::csr::gt_drtio::stable_clkin_write(0);
         }
+        #[cfg(not(has_drtio))]
+        pl::csr::sys_crg::clock_switch_write(0);
     }
+    timer.delay_ms(50);
+    setup_clock(timer, clk)?;
+    info!("RTIO clock switched to {:?}", clk);
+    Ok(())
 }
@@ -1,17 +1,36 @@
 use alloc::{collections::BTreeMap, rc::Rc, string::String, vec::Vec};
 #[cfg(has_drtio)]
 use core::mem;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
+use core_io::Error as IoError;
+pub use dma_trace::validate_trace;
+use io::{Cursor, ProtoRead, ProtoWrite};
 use ksupport::kernel::DmaRecorder;
-#[cfg(has_drtio)]
 use libasync::task;
-use libboard_artiq::drtio_routing::RoutingTable;
+use libboard_artiq::{drtio_routing::RoutingTable, identifier_read};
 use libboard_zynq::timer::GlobalTimer;
+use libconfig::Config;
 use libcortex_a9::{cache::dcci_slice, mutex::Mutex};
+use log::{info, warn};
 
 const ALIGNMENT: usize = 16 * 8;
 
+// comma-separated names of the traces persisted in config
+const PERSISTED_KEY: &str = "dma_traces";
+
 static DMA_RECORD_STORE: Mutex<BTreeMap<String, (u32, Vec<u8>, i64)>> = Mutex::new(BTreeMap::new());
+// in bytes, 0 if unlimited
+static MEMORY_LIMIT: AtomicUsize = AtomicUsize::new(0);
+
+pub struct TraceInfo {
+    pub name: String,
+    // including the remote parts
+    pub size: usize,
+    pub duration: i64,
+    // destinations holding a part of the trace, empty if DDMA is not used
+    pub destinations: Vec<u8>,
+}
 
 #[cfg(has_drtio)]
 pub mod remote_dma {
@@ -216,6 +235,20 @@ pub mod remote_dma {
         pub async fn is_empty(&self) -> bool {
             self.traces.async_lock().await.is_empty()
         }
+
+        pub async fn get_sizes(&self) -> BTreeMap<u8, usize> {
+            let lock = self.traces.async_lock().await;
+            lock.iter()
+                .map(|(destination, trace)| (*destination, trace.get_trace().len()))
+                .collect()
+        }
+
+        pub async fn get_traces(&self) -> BTreeMap<u8, Vec<u8>> {
+            let lock = self.traces.async_lock().await;
+            lock.iter()
+                .map(|(destination, trace)| (*destination, trace.get_trace().clone()))
+                .collect()
+        }
     }
 
     static mut TRACES: BTreeMap<u32, TraceSet> = BTreeMap::new();
@@ -253,6 +286,65 @@ pub mod remote_dma {
         trace_set.playback(aux_mutex, routing_table, timer, timestamp).await;
     }
 
+    // the satellite reverts the patch once the trace has been played back
+    pub async fn patch(
+        aux_mutex: &Rc<Mutex<bool>>,
+        routing_table: &RoutingTable,
+        timer: GlobalTimer,
+        id: u32,
+        destination: u8,
+        target: u32,
+        timestamp: u64,
+        word: u8,
+        value: u32,
+    ) -> bool {
+        let loaded = match unsafe { TRACES.get(&id) } {
+            Some(trace_set) => match trace_set.traces.async_lock().await.get(&destination) {
+                Some(trace) => trace.state == RemoteState::Loaded,
+                None => false,
+            },
+            None => false,
+        };
+        if !loaded {
+            return false;
+        }
+        let result = drtio::ddma_send_patch(
+            aux_mutex,
+            routing_table,
+            timer,
+            id,
+            destination,
+            target,
+            timestamp,
+            word,
+            value,
+        )
+        .await;
+        match result {
+            Ok(()) => true,
+            Err(e) => {
+                error!("Error patching DMA trace on destination {}: {}", destination, e);
+                false
+            }
+        }
+    }
+
+    // drops the patches of a trace that will not be played back
+    pub async fn revert_patches(
+        aux_mutex: &Rc<Mutex<bool>>,
+        routing_table: &RoutingTable,
+        timer: GlobalTimer,
+        id: u32,
+        destination: u8,
+    ) {
+        if let Err(e) = drtio::ddma_send_patch_revert(aux_mutex, routing_table, timer, id, destination).await {
+            error!(
+                "Error reverting DMA trace patches on destination {}: {}",
+                destination, e
+            );
+        }
+    }
+
     pub async fn playback_done(id: u32, destination: u8, error: u8, channel: u32, timestamp: u64) {
         let trace_set = unsafe { TRACES.get_mut(&id).unwrap() };
         trace_set.playback_done(destination, error, channel, timestamp).await;
@@ -273,18 +365,58 @@ pub mod remote_dma {
         }
     }
 
+    pub async fn get_sizes(id: u32) -> BTreeMap<u8, usize> {
+        match unsafe { TRACES.get(&id) } {
+            Some(trace_set) => trace_set.get_sizes().await,
+            None => BTreeMap::new(),
+        }
+    }
+
+    pub async fn get_traces(id: u32) -> BTreeMap<u8, Vec<u8>> {
+        match unsafe { TRACES.get(&id) } {
+            Some(trace_set) => trace_set.get_traces().await,
+            None => BTreeMap::new(),
+        }
+    }
+
     pub async fn has_remote_traces(id: u32) -> bool {
         let trace_set = unsafe { TRACES.get_mut(&id).unwrap() };
         !(trace_set.is_empty().await)
     }
 }
 
+// Aligns the local part of a trace for the DMA engine and stores it,
+// returning its address and the record it replaces, if any.
+fn store_local(name: String, mut buffer: Vec<u8>, duration: i64) -> (u32, Option<(u32, Vec<u8>, i64)>) {
+    // trailing zero to indicate end of buffer
+    buffer.push(0);
+    buffer.reserve(ALIGNMENT - 1);
+    let original_length = buffer.len();
+    let padding = ALIGNMENT - buffer.as_ptr() as usize % ALIGNMENT;
+    let padding = if padding == ALIGNMENT { 0 } else { padding };
+    for _ in 0..padding {
+        buffer.push(0);
+    }
+    buffer.copy_within(0..original_length, padding);
+    dcci_slice(&buffer);
+
+    let ptr = buffer[padding..].as_ptr() as u32;
+
+    let old_record = DMA_RECORD_STORE.lock().insert(name, (ptr, buffer, duration));
+    (ptr, old_record)
+}
+
+/// Stores a recorded trace. If a trace with that name is persisted, the copy
+/// in config is replaced as well.
 pub async fn put_record(
+    cfg: &Config,
     _aux_mutex: &Rc<Mutex<bool>>,
     _routing_table: &RoutingTable,
     _timer: GlobalTimer,
-    mut recorder: DmaRecorder,
-) -> u32 {
+    recorder: DmaRecorder,
+) -> Result<u32, &'static str> {
+    #[cfg(has_drtio)]
+    let mut recorder = recorder;
     #[cfg(has_drtio)]
     let mut remote_traces: BTreeMap<u8, Vec<u8>> = BTreeMap::new();
 
@@ -314,23 +446,13 @@ pub async fn put_record(
         }
         mem::swap(&mut recorder.buffer, &mut local_trace);
     }
-    // trailing zero to indicate end of buffer
-    recorder.buffer.push(0);
-    recorder.buffer.reserve(ALIGNMENT - 1);
-    let original_length = recorder.buffer.len();
-    let padding = ALIGNMENT - recorder.buffer.as_ptr() as usize % ALIGNMENT;
-    let padding = if padding == ALIGNMENT { 0 } else { padding };
-    for _ in 0..padding {
-        recorder.buffer.push(0);
-    }
-    recorder.buffer.copy_within(0..original_length, padding);
-    dcci_slice(&recorder.buffer);
-
-    let ptr = recorder.buffer[padding..].as_ptr() as u32;
-
-    let _old_record = DMA_RECORD_STORE
-        .lock()
-        .insert(recorder.name, (ptr, recorder.buffer, recorder.duration));
+    #[cfg(has_drtio)]
+    let remote_size: usize = remote_traces.values().map(|trace| trace.len()).sum();
+    #[cfg(not(has_drtio))]
+    let remote_size = 0;
+    check_memory(&recorder.name, recorder.buffer.len() + remote_size).await?;
+    let name = recorder.name.clone();
+    let (ptr, _old_record) = store_local(recorder.name, recorder.buffer, recorder.duration);
 
     #[cfg(has_drtio)]
     {
@@ -340,15 +462,85 @@ pub async fn put_record(
         remote_dma::add_traces(ptr, remote_traces);
     }
 
-    ptr
+    if persisted_names(cfg).iter().any(|persisted| *persisted == name) {
+        if let Err(e) = persist(cfg, &name).await {
+            warn!("cannot refresh persisted DMA trace {} ({})", name, e);
+        }
+    }
+
+    Ok(ptr)
 }
 
-pub async fn erase(name: String, _aux_mutex: &Rc<Mutex<bool>>, _routing_table: &RoutingTable, _timer: GlobalTimer) {
-    let _entry = DMA_RECORD_STORE.lock().remove(&name);
+// Returns false if there is no trace with that name. A persisted copy is kept,
+// as kernels erase a trace before recording it again; use forget() to drop it.
+pub async fn erase(
+    name: String,
+    _aux_mutex: &Rc<Mutex<bool>>,
+    _routing_table: &RoutingTable,
+    _timer: GlobalTimer,
+) -> bool {
+    let entry = DMA_RECORD_STORE.lock().remove(&name);
     #[cfg(has_drtio)]
-    if let Some((id, _v, _d)) = _entry {
+    if let Some((id, _v, _d)) = entry {
         remote_dma::erase(_aux_mutex, _routing_table, _timer, id).await;
     }
+    entry.is_some()
+}
+
+pub async fn erase_all(aux_mutex: &Rc<Mutex<bool>>, routing_table: &RoutingTable, timer: GlobalTimer) {
+    let names: Vec<String> = DMA_RECORD_STORE.lock().keys().cloned().collect();
+    for name in names {
+        erase(name, aux_mutex, routing_table, timer).await;
+    }
+}
+
+pub async fn list() -> Vec<TraceInfo> {
+    let records: Vec<(String, u32, usize, i64)> = DMA_RECORD_STORE
+        .lock()
+        .iter()
+        .map(|(name, (ptr, buffer, duration))| (name.clone(), *ptr, buffer.len(), *duration))
+        .collect();
+    let mut traces = Vec::with_capacity(records.len());
+    for (name, _ptr, size, duration) in records {
+        #[cfg(has_drtio)]
+        let remote_sizes = remote_dma::get_sizes(_ptr).await;
+        #[cfg(not(has_drtio))]
+        let remote_sizes: BTreeMap<u8, usize> = BTreeMap::new();
+        traces.push(TraceInfo {
+            name,
+            size: size + remote_sizes.values().sum::<usize>(),
+            duration,
+            destinations: remote_sizes.keys().cloned().collect(),
+        });
+    }
+    traces
+}
+
+pub async fn memory_usage() -> usize {
+    list().await.iter().map(|trace| trace.size).sum()
+}
+
+pub fn memory_limit() -> usize {
+    MEMORY_LIMIT.load(Ordering::Relaxed)
+}
+
+async fn check_memory(name: &str, size: usize) -> Result<(), &'static str> {
+    let limit = memory_limit();
+    if limit == 0 {
+        return Ok(());
+    }
+    // a trace with the same name gets replaced, it does not count
+    let used: usize = list()
+        .await
+        .iter()
+        .filter(|trace| trace.name != name)
+        .map(|trace| trace.size)
+        .sum();
+    // worst case alignment padding and end marker
+    if used + size + ALIGNMENT > limit {
+        return Err("DMA memory limit exceeded");
+    }
+    Ok(())
 }
 
 pub async fn retrieve(name: String) -> Option<(i32, i64, bool)> {
@@ -359,3 +551,154 @@ pub async fn retrieve(name: String) -> Option<(i32, i64, bool)> {
     let uses_ddma = false;
     Some((ptr as i32, duration, uses_ddma))
 }
+
+fn persisted_key(name: &str) -> String {
+    format!("dma_trace_{}", name)
+}
+
+fn persisted_names(cfg: &Config) -> Vec<String> {
+    match cfg.read_str(PERSISTED_KEY) {
+        Ok(names) => names
+            .split(',')
+            .filter(|name| !name.is_empty())
+            .map(String::from)
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn write_persisted_names(cfg: &Config, names: &[String]) -> Result<(), &'static str> {
+    cfg.write(PERSISTED_KEY, names.join(",").into_bytes())
+        .map_err(|_| "failed to write DMA trace list to config")
+}
+
+fn serialize_trace(
+    ident: &str,
+    duration: i64,
+    events: &[u8],
+    remote_traces: &BTreeMap<u8, Vec<u8>>,
+) -> Result<Vec<u8>, IoError> {
+    let mut writer = Cursor::new(Vec::new());
+    writer.write_string(ident)?;
+    writer.write_i64(duration)?;
+    writer.write_bytes(events)?;
+    writer.write_u8(remote_traces.len() as u8)?;
+    for (destination, trace) in remote_traces.iter() {
+        writer.write_u8(*destination)?;
+        writer.write_bytes(trace)?;
+    }
+    Ok(writer.into_inner())
+}
+
+/// Stores a recorded trace, including its DDMA parts, into config so that it
+/// is available again after a reboot.
+pub async fn persist(cfg: &Config, name: &str) -> Result<(), &'static str> {
+    // the name becomes part of a file name on the SD card
+    let valid = |c: u8| c.is_ascii_alphanumeric() || c == b'_' || c == b'-';
+    if name.is_empty() || !name.bytes().all(valid) {
+        return Err("only alphanumeric DMA trace names can be persisted");
+    }
+    let ptr = DMA_RECORD_STORE.lock().get(name).ok_or("DMA trace not found")?.0;
+    #[cfg(has_drtio)]
+    let remote_traces = remote_dma::get_traces(ptr).await;
+    #[cfg(not(has_drtio))]
+    let remote_traces = BTreeMap::new();
+
+    let record = {
+        let store = DMA_RECORD_STORE.lock();
+        let (stored_ptr, buffer, duration) = store.get(name).ok_or("DMA trace not found")?;
+        if *stored_ptr != ptr {
+            return Err("DMA trace was replaced while persisting");
+        }
+        // skip the alignment padding and the end-of-trace marker
+        let offset = (ptr - buffer.as_ptr() as u32) as usize;
+        let events = &buffer[offset..buffer.len() - 1];
+        serialize_trace(identifier_read(&mut [0; 64]), *duration, events, &remote_traces)
+            .map_err(|_| "failed to serialize DMA trace")?
+    };
+    cfg.write(&persisted_key(name), record)
+        .map_err(|_| "failed to write DMA trace to config")?;
+
+    let mut names = persisted_names(cfg);
+    if !names.iter().any(|persisted| persisted == name) {
+        names.push(String::from(name));
+        write_persisted_names(cfg, &names)?;
+    }
+    info!("persisted DMA trace {}", name);
+    Ok(())
+}
+
+/// Removes all traces from config. The copies in memory are kept.
+pub fn forget_all(cfg: &Config) -> Result<(), &'static str> {
+    let names = persisted_names(cfg);
+    write_persisted_names(cfg, &[])?;
+    for name in names.iter() {
+        let _ = cfg.remove(&persisted_key(name));
+    }
+    Ok(())
+}
+
+/// Removes a trace from config. The copy in memory, if any, is kept.
+pub fn forget(cfg: &Config, name: &str) -> Result<(), &'static str> {
+    let mut names = persisted_names(cfg);
+    let count = names.len();
+    names.retain(|persisted| persisted != name);
+    if names.len() == count {
+        return Err("DMA trace is not persisted");
+    }
+    write_persisted_names(cfg, &names)?;
+    let _ = cfg.remove(&persisted_key(name));
+    Ok(())
+}
+
+async fn load_trace(cfg: &Config, name: &str, ident: &str) -> Result<(), &'static str> {
+    const TRUNCATED: &str = "truncated record";
+    let record = cfg.read(&persisted_key(name)).map_err(|_| "record not found")?;
+    let mut reader = Cursor::new(record);
+    let record_ident = reader.read_string().map_err(|_| TRUNCATED)?;
+    if record_ident != ident {
+        return Err("recorded with a different gateware");
+    }
+    let duration = reader.read_u64().map_err(|_| TRUNCATED)? as i64;
+    let events = reader.read_bytes().map_err(|_| TRUNCATED)?;
+    let mut remote_traces: BTreeMap<u8, Vec<u8>> = BTreeMap::new();
+    for _ in 0..reader.read_u8().map_err(|_| TRUNCATED)? {
+        let destination = reader.read_u8().map_err(|_| TRUNCATED)?;
+        remote_traces.insert(destination, reader.read_bytes().map_err(|_| TRUNCATED)?);
+    }
+    #[cfg(not(has_drtio))]
+    if !remote_traces.is_empty() {
+        return Err("DDMA is not supported by this gateware");
+    }
+
+    let remote_size: usize = remote_traces.values().map(|trace| trace.len()).sum();
+    check_memory(name, events.len() + remote_size).await?;
+    let (_ptr, _) = store_local(String::from(name), events, duration);
+    // remote parts are uploaded as the destinations come up
+    #[cfg(has_drtio)]
+    remote_dma::add_traces(_ptr, remote_traces);
+    Ok(())
+}
+
+/// Reads the DMA memory limit and loads the traces persisted in config,
+/// skipping those recorded against another gateware.
+pub fn init(cfg: &Config) {
+    if let Ok(limit) = cfg.read_str("dma_memory_limit") {
+        match limit.parse::<usize>() {
+            Ok(limit) => {
+                info!("DMA memory limited to {} bytes", limit);
+                MEMORY_LIMIT.store(limit, Ordering::Relaxed);
+            }
+            Err(_) => warn!("invalid dma_memory_limit in config, ignoring"),
+        }
+    }
+
+    let mut ident_buf = [0; 64];
+    let ident = identifier_read(&mut ident_buf);
+    for name in persisted_names(cfg).iter() {
+        match task::block_on(load_trace(cfg, name, ident)) {
+            Ok(()) => info!("loaded persisted DMA trace {}", name),
+            Err(e) => warn!("cannot load persisted DMA trace {} ({})", name, e),
+        }
+    }
+}
@@ -9,14 +9,17 @@ use log::{info, warn};
 
 #[cfg(has_drtio)]
 pub mod drtio {
-    use alloc::vec::Vec;
-    use core::fmt;
+    use alloc::{string::String, vec::Vec};
+    use core::{fmt,
+               sync::atomic::{AtomicBool, AtomicU16, Ordering}};
 
+    use crc::crc32;
     use embedded_hal::blocking::delay::DelayMs;
-    use ksupport::{kernel::Message as KernelMessage, resolve_channel_name, ASYNC_ERROR_BUSY, ASYNC_ERROR_COLLISION,
-                   ASYNC_ERROR_SEQUENCE_ERROR, SEEN_ASYNC_ERRORS};
+    use ksupport::{kernel::Message as KernelMessage, note_sequence_error, resolve_channel_name, ASYNC_ERROR_BUSY,
+                   ASYNC_ERROR_COLLISION, ASYNC_ERROR_SEQUENCE_ERROR, SEEN_ASYNC_ERRORS};
     use libasync::{delay, task};
-    use libboard_artiq::{drtioaux::Error as DrtioError,
+    use libboard_artiq::{drtio_routing::RetryPolicy,
+                         drtioaux::Error as DrtioError,
                          drtioaux_async,
                          drtioaux_async::Packet,
                          drtioaux_proto::{PayloadStatus, MASTER_PAYLOAD_MAX_SIZE}};
@@ -35,8 +38,14 @@ pub mod drtio {
         DmaAddTraceFail(u8),
         DmaEraseFail(u8),
         DmaPlaybackFail(u8),
+        DmaPatchFail(u8),
         SubkernelAddFail(u8),
         SubkernelRunFail(u8),
+        SubkernelStopFail(u8),
+        NoRoute(u8),
+        InvalidLink(u8),
+        FirmwareAddFail(u8),
+        FirmwareCommitFail(u8),
     }
 
     impl fmt::Display for Error {
@@ -49,8 +58,14 @@ pub mod drtio {
                 Error::DmaAddTraceFail(dest) => write!(f, "error adding DMA trace on satellite #{}", dest),
                 Error::DmaEraseFail(dest) => write!(f, "error erasing DMA trace on satellite #{}", dest),
                 Error::DmaPlaybackFail(dest) => write!(f, "error playing back DMA trace on satellite #{}", dest),
+                Error::DmaPatchFail(dest) => write!(f, "error patching DMA trace on satellite #{}", dest),
                 Error::SubkernelAddFail(dest) => write!(f, "error adding subkernel on satellite #{}", dest),
                 Error::SubkernelRunFail(dest) => write!(f, "error on subkernel run request on satellite #{}", dest),
+                Error::SubkernelStopFail(dest) => write!(f, "error stopping subkernel on satellite #{}", dest),
+                Error::NoRoute(dest) => write!(f, "no DRTIO route to destination #{}", dest),
+                Error::InvalidLink(linkno) => write!(f, "invalid link #{}", linkno),
+                Error::FirmwareAddFail(dest) => write!(f, "error adding firmware data on satellite #{}", dest),
+                Error::FirmwareCommitFail(dest) => write!(f, "error writing firmware on satellite #{}", dest),
             }
         }
     }
@@ -61,21 +76,107 @@ pub mod drtio {
         }
     }
 
+    static DISABLED_LINKS: Mutex<[bool; csr::DRTIO.len()]> = Mutex::new([false; csr::DRTIO.len()]);
+    static DISABLED_DESTINATIONS: Mutex<[bool; drtio_routing::DEST_COUNT]> =
+        Mutex::new([false; drtio_routing::DEST_COUNT]);
+    static RESYNC_LINKS: AtomicBool = AtomicBool::new(false);
+    // sequence number of the last echo request, replies to any other are late
+    static ECHO_SEQNO: AtomicU16 = AtomicU16::new(0);
+    static ECHO_PENDING: AtomicBool = AtomicBool::new(false);
+
     pub fn startup(
         aux_mutex: &Rc<Mutex<bool>>,
         routing_table: &Rc<RefCell<RoutingTable>>,
         up_destinations: &Rc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
+        cfg: &Config,
         timer: GlobalTimer,
     ) {
+        let retry_policy = drtio_routing::config_retry_policy(cfg, 100);
+        load_disabled(cfg, "drtio_disabled_links", &mut *DISABLED_LINKS.lock());
+        load_disabled(cfg, "drtio_disabled_destinations", &mut *DISABLED_DESTINATIONS.lock());
+
         let aux_mutex = aux_mutex.clone();
         let routing_table = routing_table.clone();
         let up_destinations = up_destinations.clone();
         task::spawn(async move {
             let routing_table = routing_table.borrow();
-            link_task(&aux_mutex, &routing_table, &up_destinations, timer).await;
+            link_task(&aux_mutex, &routing_table, &up_destinations, retry_policy, timer).await;
         });
     }
 
+    // disabled links and destinations are stored as comma-separated lists, e.g. "1,3"
+    fn load_disabled(cfg: &Config, key: &str, disabled: &mut [bool]) {
+        if let Ok(list) = cfg.read_str(key) {
+            for entry in list.split(',').map(|entry| entry.trim()).filter(|entry| !entry.is_empty()) {
+                match entry.parse::<usize>() {
+                    Ok(n) if n < disabled.len() => {
+                        info!("{} #{} is disabled in configuration", key, n);
+                        disabled[n] = true;
+                    }
+                    _ => warn!("invalid entry in {}: {}", key, entry),
+                }
+            }
+        }
+    }
+
+    fn save_disabled(cfg: &Config, key: &str, disabled: &[bool]) {
+        let list: Vec<String> = disabled
+            .iter()
+            .enumerate()
+            .filter(|(_, &disabled)| disabled)
+            .map(|(n, _)| format!("{}", n))
+            .collect();
+        let result = if !list.is_empty() {
+            cfg.write(key, list.join(",").into_bytes())
+        } else if cfg.read(key).is_ok() {
+            cfg.remove(key)
+        } else {
+            Ok(())
+        };
+        if let Err(e) = result {
+            warn!("failed to save {} ({:?})", key, e);
+        }
+    }
+
+    pub fn set_link_enabled(cfg: &Config, linkno: u8, enabled: bool) -> Result<(), Error> {
+        if linkno as usize >= csr::DRTIO.len() {
+            return Err(Error::InvalidLink(linkno));
+        }
+        let mut disabled = DISABLED_LINKS.lock();
+        disabled[linkno as usize] = !enabled;
+        save_disabled(cfg, "drtio_disabled_links", &*disabled);
+        Ok(())
+    }
+
+    pub fn set_destination_enabled(
+        cfg: &Config,
+        routing_table: &RoutingTable,
+        destination: u8,
+        enabled: bool,
+    ) -> Result<(), Error> {
+        remote_linkno(routing_table, destination)?;
+        let mut disabled = DISABLED_DESTINATIONS.lock();
+        disabled[destination as usize] = !enabled;
+        save_disabled(cfg, "drtio_disabled_destinations", &*disabled);
+        Ok(())
+    }
+
+    fn remote_linkno(routing_table: &RoutingTable, destination: u8) -> Result<u8, Error> {
+        let hop = routing_table.0[destination as usize][0];
+        if hop == 0 || hop as usize > csr::DRTIO.len() {
+            return Err(Error::NoRoute(destination));
+        }
+        Ok(hop - 1)
+    }
+
+    fn link_disabled(linkno: u8) -> bool {
+        DISABLED_LINKS.lock()[linkno as usize]
+    }
+
+    fn destination_disabled(destination: u8) -> bool {
+        DISABLED_DESTINATIONS.lock()[destination as usize]
+    }
+
     async fn link_rx_up(linkno: u8) -> bool {
         let linkno = linkno as usize;
         unsafe { (csr::DRTIO[linkno].rx_up_read)() == 1 }
@@ -83,6 +184,12 @@ pub mod drtio {
 
     async fn process_async_packets(linkno: u8, routing_table: &RoutingTable, packet: Packet) -> Option<Packet> {
         match packet {
+            Packet::DestinationEchoReply { destination: 0, seqno }
+                if !ECHO_PENDING.load(Ordering::SeqCst) || seqno != ECHO_SEQNO.load(Ordering::SeqCst) =>
+            {
+                info!("[LINK#{}] dropping late echo reply", linkno);
+                None
+            }
             Packet::DmaPlaybackStatus {
                 id,
                 source,
@@ -113,9 +220,25 @@ pub mod drtio {
             } => {
                 subkernel::message_handle_incoming(id, status, length as usize, &data).await;
                 // acknowledge receiving part of the message
-                drtioaux_async::send(linkno, &Packet::SubkernelMessageAck { destination: source })
-                    .await
-                    .unwrap();
+                drtioaux_async::send(
+                    linkno,
+                    &Packet::SubkernelMessageAck {
+                        source: 0,
+                        destination: source,
+                    },
+                )
+                .await
+                .unwrap();
+                None
+            }
+            Packet::SubkernelMessageAck { source, destination: 0 } => {
+                // every acknowledged slice opens the window for the next one
+                if !subkernel::stream_slice_acked(source).await {
+                    warn!("[LINK#{}] unsolicited SubkernelMessageAck from {}", linkno, source);
+                }
+                for packet in subkernel::stream_next_slices(source).await {
+                    drtioaux_async::send(linkno, &packet).await.unwrap();
+                }
                 None
             }
             // routable packets
@@ -125,12 +248,20 @@ pub mod drtio {
             | Packet::DmaRemoveTraceReply { destination, .. }
             | Packet::DmaPlaybackRequest { destination, .. }
             | Packet::DmaPlaybackReply { destination, .. }
+            | Packet::DmaPatchRequest { destination, .. }
+            | Packet::DmaPatchReply { destination, .. }
+            | Packet::DmaPatchRevertRequest { destination, .. }
+            | Packet::DmaPatchRevertReply { destination, .. }
             | Packet::SubkernelLoadRunRequest { destination, .. }
             | Packet::SubkernelLoadRunReply { destination, .. }
             | Packet::SubkernelMessage { destination, .. }
             | Packet::SubkernelMessageAck { destination, .. }
             | Packet::SubkernelException { destination, .. }
             | Packet::SubkernelExceptionRequest { destination, .. }
+            | Packet::SubkernelStopRequest { destination, .. }
+            | Packet::SubkernelStopReply { destination, .. }
+            | Packet::DestinationEchoRequest { destination, .. }
+            | Packet::DestinationEchoReply { destination, .. }
             | Packet::DmaPlaybackStatus { destination, .. }
             | Packet::SubkernelFinished { destination, .. } => {
                 if destination == 0 {
@@ -176,13 +307,155 @@ pub mod drtio {
         let _lock = aux_mutex.async_lock().await;
         drtioaux_async::send(linkno, request).await.unwrap();
         loop {
-            let packet = recv_aux_timeout(linkno, 200, timer).await?;
+            let packet = recv_aux_timeout(linkno, request.reply_timeout() as u64, timer).await?;
             if let Some(packet) = process_async_packets(linkno, routing_table, packet).await {
                 return Ok(packet);
             }
         }
     }
 
+    fn rtio_counter() -> u64 {
+        unsafe {
+            csr::rtio::counter_update_write(1);
+            csr::rtio::counter_read()
+        }
+    }
+
+    async fn echo_round_trip(
+        aux_mutex: &Mutex<bool>,
+        linkno: u8,
+        routing_table: &RoutingTable,
+        timer: GlobalTimer,
+        destination: u8,
+    ) -> Result<u64, Error> {
+        if !link_rx_up(linkno).await {
+            return Err(Error::LinkDown);
+        }
+        // lock first, so that waiting for other aux users is not counted in
+        let _lock = aux_mutex.async_lock().await;
+        let seqno = ECHO_SEQNO.fetch_add(1, Ordering::SeqCst).wrapping_add(1);
+        ECHO_PENDING.store(true, Ordering::SeqCst);
+        let start = rtio_counter();
+        drtioaux_async::send(
+            linkno,
+            &Packet::DestinationEchoRequest {
+                source: 0,
+                destination: destination,
+                seqno: seqno,
+            },
+        )
+        .await
+        .unwrap();
+        let result = loop {
+            let packet = match recv_aux_timeout(linkno, 200, timer).await {
+                Ok(packet) => packet,
+                Err(e) => break Err(e),
+            };
+            // replies to earlier, timed out requests are dropped by process_async_packets
+            if let Some(packet) = process_async_packets(linkno, routing_table, packet).await {
+                let end = rtio_counter();
+                break match packet {
+                    Packet::DestinationEchoReply { destination: 0, .. } => Ok(end - start),
+                    _ => Err(Error::UnexpectedReply),
+                };
+            }
+        };
+        ECHO_PENDING.store(false, Ordering::SeqCst);
+        result
+    }
+
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct LatencyStats {
+        pub sent: u32,
+        pub received: u32,
+        // round-trip times, in RTIO machine units
+        pub min: u64,
+        pub avg: u64,
+        pub max: u64,
+        // wall time taken by the whole probe
+        pub elapsed_us: u64,
+    }
+
+    pub async fn measure_latency(
+        aux_mutex: &Rc<Mutex<bool>>,
+        routing_table: &RoutingTable,
+        timer: GlobalTimer,
+        destination: u8,
+        count: u32,
+    ) -> Result<LatencyStats, Error> {
+        let linkno = remote_linkno(routing_table, destination)?;
+        let mut stats = LatencyStats::default();
+        let mut total: u64 = 0;
+        let start_us = timer.get_us().0;
+        for _ in 0..count {
+            stats.sent += 1;
+            match echo_round_trip(aux_mutex, linkno, routing_table, timer, destination).await {
+                Ok(rtt) => {
+                    if stats.received == 0 || rtt < stats.min {
+                        stats.min = rtt;
+                    }
+                    if rtt > stats.max {
+                        stats.max = rtt;
+                    }
+                    total += rtt;
+                    stats.received += 1;
+                }
+                Err(Error::Timeout) => warn!("[DEST#{}] echo request timed out", destination),
+                Err(e) => return Err(e),
+            }
+        }
+        stats.elapsed_us = timer.get_us().0 - start_us;
+        if stats.received > 0 {
+            stats.avg = total / stats.received as u64;
+        }
+        Ok(stats)
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct RepeaterStats {
+        pub up: bool,
+        pub forwarded_packets: u32,
+        pub forward_timeouts: u32,
+        pub rtio_errors: u32,
+        pub tsc_sync_attempts: u32,
+    }
+
+    pub async fn repeater_stats(
+        aux_mutex: &Rc<Mutex<bool>>,
+        routing_table: &RoutingTable,
+        timer: GlobalTimer,
+        destination: u8,
+        repno: u8,
+    ) -> Result<Option<RepeaterStats>, Error> {
+        let linkno = remote_linkno(routing_table, destination)?;
+        let reply = aux_transact(
+            aux_mutex,
+            linkno,
+            routing_table,
+            &Packet::RepeaterStatsRequest { destination, repno },
+            timer,
+        )
+        .await?;
+        match reply {
+            Packet::RepeaterStatsReply { succeeded: false, .. } => Ok(None),
+            Packet::RepeaterStatsReply {
+                succeeded: true,
+                up,
+                forwarded_packets,
+                forward_timeouts,
+                rtio_errors,
+                tsc_sync_attempts,
+            } => Ok(Some(RepeaterStats {
+                up,
+                forwarded_packets,
+                forward_timeouts,
+                rtio_errors,
+                tsc_sync_attempts,
+            })),
+            _ => Err(Error::UnexpectedReply),
+        }
+    }
+
     async fn drain_buffer(linkno: u8, draining_time: Milliseconds, timer: GlobalTimer) {
         let max_time = timer.get_time() + draining_time;
         while timer.get_time() < max_time {
@@ -194,6 +467,7 @@ pub mod drtio {
         aux_mutex: &Rc<Mutex<bool>>,
         linkno: u8,
         routing_table: &RoutingTable,
+        max_count: u16,
         timer: GlobalTimer,
     ) -> u32 {
         let mut count = 0;
@@ -202,7 +476,7 @@ pub mod drtio {
                 return 0;
             }
             count += 1;
-            if count > 100 {
+            if count > max_count as u32 {
                 return 0;
             }
             let reply = aux_transact(aux_mutex, linkno, routing_table, &Packet::EchoRequest, timer).await;
@@ -371,7 +645,15 @@ pub mod drtio {
                 }
             } else if hop as usize <= csr::DRTIO.len() {
                 let linkno = hop - 1;
-                if destination_up(up_destinations, destination).await {
+                if destination_disabled(destination) {
+                    // taken offline by management, do not poll it
+                    if destination_up(up_destinations, destination).await {
+                        info!("[DEST#{}] destination administratively disabled", destination);
+                        destination_set_up(routing_table, up_destinations, destination, false).await;
+                        remote_dma::destination_changed(aux_mutex, routing_table, timer, destination, false).await;
+                        subkernel::destination_changed(aux_mutex, routing_table, timer, destination, false).await;
+                    }
+                } else if destination_up(up_destinations, destination).await {
                     if up_links[linkno as usize] {
                         let reply = aux_transact(
                             aux_mutex,
@@ -400,6 +682,7 @@ pub mod drtio {
                                     channel,
                                     resolve_channel_name(global_ch)
                                 );
+                                note_sequence_error(global_ch);
                                 unsafe { SEEN_ASYNC_ERRORS |= ASYNC_ERROR_SEQUENCE_ERROR };
                             }
                             Ok(Packet::DestinationCollisionReply { channel }) => {
@@ -465,12 +748,32 @@ pub mod drtio {
         aux_mutex: &Rc<Mutex<bool>>,
         routing_table: &RoutingTable,
         up_destinations: &Rc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
+        retry_policy: RetryPolicy,
         timer: GlobalTimer,
     ) {
         let mut up_links = [false; csr::DRTIO.len()];
+        // consecutive failed bring-ups and the time of the next attempt, per link
+        let mut failures = [0u32; csr::DRTIO.len()];
+        let mut retry_at = [0u64; csr::DRTIO.len()];
         loop {
+            if RESYNC_LINKS.swap(false, Ordering::SeqCst) {
+                info!("re-synchronizing all DRTIO links");
+                up_links = [false; csr::DRTIO.len()];
+                failures = [0; csr::DRTIO.len()];
+                retry_at = [0; csr::DRTIO.len()];
+            }
             for linkno in 0..csr::DRTIO.len() {
                 let linkno = linkno as u8;
+                if link_disabled(linkno) {
+                    if up_links[linkno as usize] {
+                        info!("[LINK#{}] link administratively disabled", linkno);
+                        up_links[linkno as usize] = false;
+                    }
+                    // start afresh once re-enabled
+                    failures[linkno as usize] = 0;
+                    retry_at[linkno as usize] = 0;
+                    continue;
+                }
                 if up_links[linkno as usize] {
                     /* link was previously up */
                     if link_rx_up(linkno).await {
@@ -482,24 +785,45 @@ pub mod drtio {
                     }
                 } else {
                     /* link was previously down */
-                    if link_rx_up(linkno).await {
-                        info!("[LINK#{}] link RX became up, pinging", linkno);
-                        let ping_count = ping_remote(aux_mutex, linkno, routing_table, timer).await;
-                        if ping_count > 0 {
-                            info!("[LINK#{}] remote replied after {} packets", linkno, ping_count);
-                            up_links[linkno as usize] = true;
-                            if let Err(e) = sync_tsc(aux_mutex, linkno, timer).await {
-                                error!("[LINK#{}] failed to sync TSC ({})", linkno, e);
-                            }
-                            if let Err(e) = load_routing_table(aux_mutex, linkno, routing_table, timer).await {
-                                error!("[LINK#{}] failed to load routing table ({})", linkno, e);
-                            }
-                            if let Err(e) = set_rank(aux_mutex, linkno, 1 as u8, routing_table, timer).await {
-                                error!("[LINK#{}] failed to set rank ({})", linkno, e);
-                            }
-                            info!("[LINK#{}] link initialization completed", linkno);
+                    if !link_rx_up(linkno).await {
+                        // a new cable or a rebooted satellite gets a fresh set of retries
+                        failures[linkno as usize] = 0;
+                        retry_at[linkno as usize] = 0;
+                        continue;
+                    }
+                    if retry_policy.exhausted(failures[linkno as usize])
+                        || timer.get_time().0 < retry_at[linkno as usize]
+                    {
+                        continue;
+                    }
+                    info!("[LINK#{}] link RX became up, pinging", linkno);
+                    let ping_count =
+                        ping_remote(aux_mutex, linkno, routing_table, retry_policy.ping_count, timer).await;
+                    if ping_count > 0 {
+                        info!("[LINK#{}] remote replied after {} packets", linkno, ping_count);
+                        up_links[linkno as usize] = true;
+                        failures[linkno as usize] = 0;
+                        if let Err(e) = sync_tsc(aux_mutex, linkno, timer).await {
+                            error!("[LINK#{}] failed to sync TSC ({})", linkno, e);
+                        }
+                        if let Err(e) = load_routing_table(aux_mutex, linkno, routing_table, timer).await {
+                            error!("[LINK#{}] failed to load routing table ({})", linkno, e);
+                        }
+                        if let Err(e) = set_rank(aux_mutex, linkno, 1 as u8, routing_table, timer).await {
+                            error!("[LINK#{}] failed to set rank ({})", linkno, e);
+                        }
+                        info!("[LINK#{}] link initialization completed", linkno);
+                    } else {
+                        failures[linkno as usize] += 1;
+                        if retry_policy.exhausted(failures[linkno as usize]) {
+                            error!(
+                                "[LINK#{}] ping failed {} times, giving up until re-enabled or RX is lost",
+                                linkno, failures[linkno as usize]
+                            );
                         } else {
-                            error!("[LINK#{}] ping failed", linkno);
+                            let backoff = retry_policy.backoff(failures[linkno as usize]);
+                            error!("[LINK#{}] ping failed, retrying in {} ms", linkno, backoff);
+                            retry_at[linkno as usize] = timer.get_time().0 + backoff;
                         }
                     }
                 }
@@ -510,6 +834,64 @@ pub mod drtio {
         }
     }
 
+    /// Brings all links down so that they are pinged, TSC-synced and
+    /// given the routing table again, e.g. after an RTIO clock switch.
+    pub fn resync_links() {
+        RESYNC_LINKS.store(true, Ordering::SeqCst);
+    }
+
+    // Whether the route to `downstream` goes through repeater `repno` of the satellite at `destination`
+    fn behind_repeater(routing_table: &RoutingTable, destination: u8, repno: u8, downstream: u8) -> bool {
+        let route = &routing_table.0[destination as usize];
+        let rank = match route.iter().skip(1).position(|&hop| hop == 0) {
+            Some(rank) => rank + 1,
+            None => return false,
+        };
+        let downstream_route = &routing_table.0[downstream as usize];
+        downstream_route[..rank] == route[..rank] && downstream_route[rank] == repno + 1
+    }
+
+    pub async fn repeater_set_enabled(
+        aux_mutex: &Rc<Mutex<bool>>,
+        routing_table: &RoutingTable,
+        up_destinations: &Rc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
+        timer: GlobalTimer,
+        destination: u8,
+        repno: u8,
+        enabled: bool,
+    ) -> Result<bool, Error> {
+        let linkno = remote_linkno(routing_table, destination)?;
+        let reply = aux_transact(
+            aux_mutex,
+            linkno,
+            routing_table,
+            &Packet::RepeaterSetEnabledRequest {
+                destination,
+                repno,
+                enabled,
+            },
+            timer,
+        )
+        .await?;
+        let succeeded = match reply {
+            Packet::RepeaterSetEnabledReply { succeeded } => succeeded,
+            _ => return Err(Error::UnexpectedReply),
+        };
+        if succeeded && !enabled {
+            // the survey would only notice once polling them fails
+            for downstream in 0..drtio_routing::DEST_COUNT as u8 {
+                if behind_repeater(routing_table, destination, repno, downstream)
+                    && destination_up(up_destinations, downstream).await
+                {
+                    destination_set_up(routing_table, up_destinations, downstream, false).await;
+                    remote_dma::destination_changed(aux_mutex, routing_table, timer, downstream, false).await;
+                    subkernel::destination_changed(aux_mutex, routing_table, timer, downstream, false).await;
+                }
+            }
+        }
+        Ok(succeeded)
+    }
+
     pub async fn reset(aux_mutex: &Rc<Mutex<bool>>, routing_table: &RoutingTable, mut timer: GlobalTimer) {
         for linkno in 0..csr::DRTIO.len() {
             unsafe {
@@ -677,6 +1059,80 @@ pub mod drtio {
         }
     }
 
+    pub async fn ddma_send_patch(
+        aux_mutex: &Rc<Mutex<bool>>,
+        routing_table: &RoutingTable,
+        timer: GlobalTimer,
+        id: u32,
+        destination: u8,
+        target: u32,
+        timestamp: u64,
+        word: u8,
+        value: u32,
+    ) -> Result<(), Error> {
+        let linkno = routing_table.0[destination as usize][0] - 1;
+        let reply = aux_transact(
+            aux_mutex,
+            linkno,
+            routing_table,
+            &Packet::DmaPatchRequest {
+                source: 0,
+                destination: destination,
+                id: id,
+                target: target,
+                timestamp: timestamp,
+                word: word,
+                value: value,
+            },
+            timer,
+        )
+        .await?;
+        match reply {
+            Packet::DmaPatchReply {
+                destination: 0,
+                succeeded: true,
+            } => Ok(()),
+            Packet::DmaPatchReply {
+                destination: 0,
+                succeeded: false,
+            } => Err(Error::DmaPatchFail(destination)),
+            _ => Err(Error::UnexpectedReply),
+        }
+    }
+
+    pub async fn ddma_send_patch_revert(
+        aux_mutex: &Rc<Mutex<bool>>,
+        routing_table: &RoutingTable,
+        timer: GlobalTimer,
+        id: u32,
+        destination: u8,
+    ) -> Result<(), Error> {
+        let linkno = routing_table.0[destination as usize][0] - 1;
+        let reply = aux_transact(
+            aux_mutex,
+            linkno,
+            routing_table,
+            &Packet::DmaPatchRevertRequest {
+                source: 0,
+                destination: destination,
+                id: id,
+            },
+            timer,
+        )
+        .await?;
+        match reply {
+            Packet::DmaPatchRevertReply {
+                destination: 0,
+                succeeded: true,
+            } => Ok(()),
+            Packet::DmaPatchRevertReply {
+                destination: 0,
+                succeeded: false,
+            } => Err(Error::DmaPatchFail(destination)),
+            _ => Err(Error::UnexpectedReply),
+        }
+    }
+
     async fn analyzer_get_data(
         aux_mutex: &Rc<Mutex<bool>>,
         routing_table: &RoutingTable,
@@ -781,6 +1237,37 @@ pub mod drtio {
         .await
     }
 
+    pub async fn subkernel_query_cache(
+        aux_mutex: &Rc<Mutex<bool>>,
+        routing_table: &RoutingTable,
+        timer: GlobalTimer,
+        id: u32,
+        destination: u8,
+        hash: u32,
+        length: u32,
+        bind: bool,
+    ) -> Result<bool, Error> {
+        let linkno = routing_table.0[destination as usize][0] - 1;
+        let reply = aux_transact(
+            aux_mutex,
+            linkno,
+            routing_table,
+            &Packet::SubkernelCacheQuery {
+                destination: destination,
+                id: id,
+                hash: hash,
+                length: length,
+                bind: bind,
+            },
+            timer,
+        )
+        .await?;
+        match reply {
+            Packet::SubkernelCacheReply { present } => Ok(present),
+            _ => Err(Error::UnexpectedReply),
+        }
+    }
+
     pub async fn subkernel_load(
         aux_mutex: &Rc<Mutex<bool>>,
         routing_table: &RoutingTable,
@@ -818,6 +1305,39 @@ pub mod drtio {
         }
     }
 
+    pub async fn subkernel_stop(
+        aux_mutex: &Rc<Mutex<bool>>,
+        routing_table: &RoutingTable,
+        timer: GlobalTimer,
+        id: u32,
+        destination: u8,
+    ) -> Result<(), Error> {
+        let linkno = routing_table.0[destination as usize][0] - 1;
+        let reply = aux_transact(
+            aux_mutex,
+            linkno,
+            routing_table,
+            &Packet::SubkernelStopRequest {
+                source: 0,
+                destination: destination,
+                id: id,
+            },
+            timer,
+        )
+        .await?;
+        match reply {
+            Packet::SubkernelStopReply {
+                destination: 0,
+                succeeded: true,
+            } => Ok(()),
+            Packet::SubkernelStopReply {
+                destination: 0,
+                succeeded: false,
+            } => Err(Error::SubkernelStopFail(destination)),
+            _ => Err(Error::UnexpectedReply),
+        }
+    }
+
     pub async fn subkernel_retrieve_exception(
         aux_mutex: &Rc<Mutex<bool>>,
         routing_table: &RoutingTable,
@@ -855,35 +1375,183 @@ pub mod drtio {
         }
     }
 
-    pub async fn subkernel_send_message(
+    // Takes in what the link received meanwhile, acks included,
+    // then sends the slices which the stream window lets out.
+    pub async fn subkernel_stream_service(
         aux_mutex: &Rc<Mutex<bool>>,
         routing_table: &RoutingTable,
-        timer: GlobalTimer,
-        id: u32,
         destination: u8,
-        message: &[u8],
     ) -> Result<(), Error> {
         let linkno = routing_table.0[destination as usize][0] - 1;
+        if !link_rx_up(linkno).await {
+            return Err(Error::LinkDown);
+        }
+        let _lock = aux_mutex.async_lock().await;
+        loop {
+            match drtioaux_async::recv(linkno).await {
+                Ok(Some(packet)) => {
+                    if let Some(packet) = process_async_packets(linkno, routing_table, packet).await {
+                        warn!("[LINK#{}] unsolicited aux packet: {:?}", linkno, packet);
+                    }
+                }
+                Ok(None) => break,
+                Err(_) => {
+                    warn!("[LINK#{}] aux packet error", linkno);
+                    break;
+                }
+            }
+        }
+        for packet in subkernel::stream_next_slices(destination).await {
+            drtioaux_async::send(linkno, &packet).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn firmware_upload(
+        aux_mutex: &Rc<Mutex<bool>>,
+        routing_table: &RoutingTable,
+        timer: GlobalTimer,
+        destination: u8,
+        image: &[u8],
+    ) -> Result<(), Error> {
+        let linkno = remote_linkno(routing_table, destination)?;
         partition_data(
             linkno,
             aux_mutex,
             routing_table,
             timer,
-            message,
-            |slice, status, len| Packet::SubkernelMessage {
-                source: 0,
+            image,
+            |slice, status, len| Packet::FirmwareAddDataRequest {
                 destination: destination,
-                id: id,
                 status: status,
                 length: len as u16,
                 data: *slice,
             },
             |reply| match reply {
-                Packet::SubkernelMessageAck { .. } => Ok(()),
+                Packet::FirmwareAddDataReply { succeeded: true } => Ok(()),
+                Packet::FirmwareAddDataReply { succeeded: false } => Err(Error::FirmwareAddFail(destination)),
                 _ => Err(Error::UnexpectedReply),
             },
         )
-        .await
+        .await?;
+        // the satellite verifies the image before writing it to its boot storage
+        let reply = aux_transact(
+            aux_mutex,
+            linkno,
+            routing_table,
+            &Packet::FirmwareCommitRequest {
+                destination: destination,
+                length: image.len() as u32,
+                checksum: crc32::checksum_ieee(image),
+            },
+            timer,
+        )
+        .await?;
+        match reply {
+            Packet::FirmwareCommitReply { succeeded: true } => Ok(()),
+            Packet::FirmwareCommitReply { succeeded: false } => Err(Error::FirmwareCommitFail(destination)),
+            _ => Err(Error::UnexpectedReply),
+        }
+    }
+
+    pub async fn reboot_satellite(
+        aux_mutex: &Rc<Mutex<bool>>,
+        routing_table: &RoutingTable,
+        timer: GlobalTimer,
+        destination: u8,
+    ) -> Result<(), Error> {
+        let linkno = remote_linkno(routing_table, destination)?;
+        let reply = aux_transact(
+            aux_mutex,
+            linkno,
+            routing_table,
+            &Packet::RebootRequest {
+                destination: destination,
+            },
+            timer,
+        )
+        .await?;
+        match reply {
+            Packet::RebootAck => Ok(()),
+            _ => Err(Error::UnexpectedReply),
+        }
+    }
+
+    pub async fn wrpll_recalibrate(
+        aux_mutex: &Rc<Mutex<bool>>,
+        routing_table: &RoutingTable,
+        timer: GlobalTimer,
+        destination: u8,
+    ) -> Result<bool, Error> {
+        let linkno = remote_linkno(routing_table, destination)?;
+        let reply = aux_transact(
+            aux_mutex,
+            linkno,
+            routing_table,
+            &Packet::WrpllCalibrateRequest {
+                destination: destination,
+            },
+            timer,
+        )
+        .await?;
+        match reply {
+            Packet::WrpllCalibrateReply { succeeded } => Ok(succeeded),
+            _ => Err(Error::UnexpectedReply),
+        }
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct GrabberStatus {
+        pub state: u8,
+        pub video_clock: u32,
+        pub frame_size: (u16, u16),
+        pub lock_loss_count: u32,
+    }
+
+    pub async fn grabber_status(
+        aux_mutex: &Rc<Mutex<bool>>,
+        routing_table: &RoutingTable,
+        timer: GlobalTimer,
+        destination: u8,
+    ) -> Result<Vec<GrabberStatus>, Error> {
+        let linkno = remote_linkno(routing_table, destination)?;
+        let mut statuses = Vec::new();
+        let mut grabber = 0;
+        loop {
+            let reply = aux_transact(
+                aux_mutex,
+                linkno,
+                routing_table,
+                &Packet::GrabberStatusRequest {
+                    destination: destination,
+                    grabber: grabber,
+                },
+                timer,
+            )
+            .await?;
+            match reply {
+                Packet::GrabberStatusReply {
+                    count,
+                    state,
+                    video_clock,
+                    frame_width,
+                    frame_height,
+                    lock_loss_count,
+                } => {
+                    if grabber >= count {
+                        return Ok(statuses);
+                    }
+                    statuses.push(GrabberStatus {
+                        state: state,
+                        video_clock: video_clock,
+                        frame_size: (frame_width, frame_height),
+                        lock_loss_count: lock_loss_count,
+                    });
+                    grabber += 1;
+                }
+                _ => return Err(Error::UnexpectedReply),
+            }
+        }
     }
 
     pub async fn i2c_send_basic(
@@ -980,12 +1648,15 @@ pub mod drtio {
         _aux_mutex: &Rc<Mutex<bool>>,
         _routing_table: &Rc<RefCell<RoutingTable>>,
         _up_destinations: &Rc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
+        _cfg: &Config,
         _timer: GlobalTimer,
     ) {
     }
 
     #[allow(dead_code)]
     pub fn reset(_aux_mutex: Rc<Mutex<bool>>, _routing_table: &RoutingTable, mut _timer: GlobalTimer) {}
+
+    pub fn resync_links() {}
 }
 
 fn toggle_sed_spread(val: u8) {
@@ -1018,7 +1689,7 @@ pub fn startup(
     timer: GlobalTimer,
 ) {
     setup_sed_spread(cfg);
-    drtio::startup(aux_mutex, routing_table, up_destinations, timer);
+    drtio::startup(aux_mutex, routing_table, up_destinations, cfg, timer);
     unsafe {
         csr::rtio_core::reset_phy_write(1);
     }
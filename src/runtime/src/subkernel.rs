@@ -1,11 +1,15 @@
-use alloc::{collections::BTreeMap, rc::Rc, vec::Vec};
+use alloc::{collections::{BTreeMap, VecDeque},
+            rc::Rc,
+            vec::Vec};
+use core::cmp::min;
 
+use crc::crc32;
 use libasync::task;
 use libboard_artiq::{drtio_routing::RoutingTable,
-                     drtioaux_proto::{PayloadStatus, MASTER_PAYLOAD_MAX_SIZE}};
+                     drtioaux_proto::{Packet, PayloadStatus, MASTER_PAYLOAD_MAX_SIZE}};
 use libboard_zynq::{time::Milliseconds, timer::GlobalTimer};
 use libcortex_a9::mutex::Mutex;
-use log::{error, warn};
+use log::{error, info, warn};
 
 use crate::rtio_mgt::{drtio, drtio::Error as DrtioError};
 
@@ -13,6 +17,7 @@ use crate::rtio_mgt::{drtio, drtio::Error as DrtioError};
 pub enum FinishStatus {
     Ok,
     CommLost,
+    Cancelled,
     Exception(u8), // exception source
 }
 
@@ -31,6 +36,7 @@ pub enum Error {
     SubkernelNotFound,
     SubkernelException,
     CommLost,
+    Cancelled,
     DrtioError(DrtioError),
 }
 
@@ -46,6 +52,13 @@ pub struct SubkernelFinished {
     pub exception: Option<Vec<u8>>,
 }
 
+pub struct SubkernelInfo {
+    pub id: u32,
+    pub destination: u8,
+    pub state: SubkernelState,
+    pub size: usize,
+}
+
 struct Subkernel {
     pub destination: u8,
     pub data: Vec<u8>,
@@ -71,6 +84,24 @@ pub async fn add_subkernel(id: u32, destination: u8, kernel: Vec<u8>) {
         .insert(id, Subkernel::new(destination, kernel));
 }
 
+// satellites keep subkernels between sessions, skip the upload if the same one is already there
+async fn upload_if_missing(
+    aux_mutex: &Rc<Mutex<bool>>,
+    routing_table: &RoutingTable,
+    timer: GlobalTimer,
+    id: u32,
+    destination: u8,
+    data: &Vec<u8>,
+) -> Result<(), DrtioError> {
+    let hash = crc32::checksum_ieee(data);
+    let length = data.len() as u32;
+    if drtio::subkernel_query_cache(aux_mutex, routing_table, timer, id, destination, hash, length, true).await? {
+        info!("subkernel {} already on destination {}", id, destination);
+        return Ok(());
+    }
+    drtio::subkernel_upload(aux_mutex, routing_table, timer, id, destination, data).await
+}
+
 pub async fn upload(
     aux_mutex: &Rc<Mutex<bool>>,
     routing_table: &RoutingTable,
@@ -78,7 +109,7 @@ pub async fn upload(
     id: u32,
 ) -> Result<(), Error> {
     if let Some(subkernel) = SUBKERNELS.async_lock().await.get_mut(&id) {
-        drtio::subkernel_upload(
+        upload_if_missing(
             aux_mutex,
             routing_table,
             timer,
@@ -129,6 +160,63 @@ pub async fn clear_subkernels() {
     SUBKERNELS.async_lock().await.clear();
     MESSAGE_QUEUE.async_lock().await.clear();
     CURRENT_MESSAGES.async_lock().await.clear();
+    STREAMS.async_lock().await.clear();
+}
+
+pub async fn list() -> Vec<SubkernelInfo> {
+    SUBKERNELS
+        .async_lock()
+        .await
+        .iter()
+        .map(|(id, subkernel)| SubkernelInfo {
+            id: *id,
+            destination: subkernel.destination,
+            state: subkernel.state,
+            size: subkernel.data.len(),
+        })
+        .collect()
+}
+
+pub async fn cancel(
+    aux_mutex: &Rc<Mutex<bool>>,
+    routing_table: &RoutingTable,
+    timer: GlobalTimer,
+    id: u32,
+) -> Result<(), Error> {
+    let destination = match SUBKERNELS.async_lock().await.get(&id) {
+        Some(subkernel) if subkernel.state != SubkernelState::Running => return Err(Error::IncorrectState),
+        Some(subkernel) => subkernel.destination,
+        None => return Err(Error::SubkernelNotFound),
+    };
+    // not locked while waiting for the reply, as packets processed meanwhile
+    // (e.g. SubkernelFinished) lock SUBKERNELS too
+    drtio::subkernel_stop(aux_mutex, routing_table, timer, id, destination).await?;
+    if let Some(subkernel) = SUBKERNELS.async_lock().await.get_mut(&id) {
+        // unless it finished meanwhile, unblocks anything awaiting the subkernel
+        // on the main kernel side
+        if subkernel.state == SubkernelState::Running {
+            subkernel.state = SubkernelState::Finished {
+                status: FinishStatus::Cancelled,
+            };
+        }
+    }
+    Ok(())
+}
+
+pub async fn cancel_all(aux_mutex: &Rc<Mutex<bool>>, routing_table: &RoutingTable, timer: GlobalTimer) {
+    // the lock is released before cancelling, see cancel()
+    let running: Vec<u32> = SUBKERNELS
+        .async_lock()
+        .await
+        .iter()
+        .filter(|(_, subkernel)| subkernel.state == SubkernelState::Running)
+        .map(|(id, _)| *id)
+        .collect();
+    for id in running {
+        if let Err(e) = cancel(aux_mutex, routing_table, timer, id).await {
+            warn!("error cancelling subkernel #{}: {:?}", id, e);
+        }
+    }
 }
 
 pub async fn subkernel_finished(id: u32, with_exception: bool, exception_src: u8) {
@@ -157,8 +245,7 @@ pub async fn destination_changed(
     for (id, subkernel) in locked_subkernels.iter_mut() {
         if subkernel.destination == destination {
             if up {
-                match drtio::subkernel_upload(aux_mutex, routing_table, timer, *id, destination, &subkernel.data).await
-                {
+                match upload_if_missing(aux_mutex, routing_table, timer, *id, destination, &subkernel.data).await {
                     Ok(_) => subkernel.state = SubkernelState::Uploaded,
                     Err(e) => error!("Error adding subkernel on destination {}: {}", destination, e),
                 }
@@ -172,6 +259,10 @@ pub async fn destination_changed(
             }
         }
     }
+    if !up {
+        // the slices in flight are lost with the destination
+        STREAMS.async_lock().await.remove(&destination);
+    }
 }
 
 pub async fn await_finish(
@@ -240,6 +331,26 @@ static MESSAGE_QUEUE: Mutex<Vec<Message>> = Mutex::new(Vec::new());
 // currently under construction message(s) (can be from multiple sources)
 static CURRENT_MESSAGES: Mutex<BTreeMap<u32, Message>> = Mutex::new(BTreeMap::new());
 
+// slices sent to a destination ahead of their acks, at most; every slice in flight
+// takes one of the receiver's aux buffers until it is processed there
+const STREAM_WINDOW: usize = 4;
+// messages not fully acknowledged by a destination, beyond which try-sends report a full queue
+const STREAM_QUEUE_LENGTH: usize = 8;
+// ms without any ack before the slices in flight are deemed lost
+const STREAM_TIMEOUT: u64 = 1000;
+
+// outgoing messages to one destination, all sent through it in order
+#[derive(Default)]
+struct Stream {
+    pending: VecDeque<(Packet, bool)>, // slices not sent yet, true for the last of a message
+    in_flight: VecDeque<bool>,         // same flags for the slices awaiting their ack
+    messages: usize,                   // messages not fully acknowledged
+    acks: u32,                         // slices acknowledged so far
+    progress: (u32, u64),              // acks and time in ms when the stream was last seen moving
+}
+
+static STREAMS: Mutex<BTreeMap<u8, Stream>> = Mutex::new(BTreeMap::new());
+
 pub async fn message_handle_incoming(
     id: u32,
     status: PayloadStatus,
@@ -297,30 +408,131 @@ pub async fn message_await(id: u32, timeout: i64, timer: GlobalTimer) -> Result<
     }
     let max_time = timer.get_time() + Milliseconds(timeout as u64);
     while timeout < 0 || (timeout > 0 && timer.get_time() < max_time) {
-        {
-            let mut message_queue = MESSAGE_QUEUE.async_lock().await;
-            for i in 0..message_queue.len() {
-                let msg = &message_queue[i];
-                if msg.from_id == id {
-                    let message = message_queue.remove(i);
-                    return Ok(message);
+        if let Some(message) = message_try_receive(id).await? {
+            return Ok(message);
+        }
+        task::r#yield().await;
+    }
+    Err(Error::Timeout)
+}
+
+pub async fn message_try_receive(id: u32) -> Result<Option<Message>, Error> {
+    {
+        let mut message_queue = MESSAGE_QUEUE.async_lock().await;
+        for i in 0..message_queue.len() {
+            let msg = &message_queue[i];
+            if msg.from_id == id {
+                let message = message_queue.remove(i);
+                return Ok(Some(message));
+            }
+        }
+    }
+    if let Some(subkernel) = SUBKERNELS.async_lock().await.get(&id) {
+        match subkernel.state {
+            SubkernelState::Finished {
+                status: FinishStatus::CommLost,
+            } => return Err(Error::CommLost),
+            SubkernelState::Finished {
+                status: FinishStatus::Exception(_),
+            } => return Err(Error::SubkernelException),
+            SubkernelState::Finished {
+                status: FinishStatus::Cancelled,
+            } => return Err(Error::Cancelled),
+            SubkernelState::Running | SubkernelState::Finished { .. } => (),
+            _ => return Err(Error::IncorrectState),
+        }
+    }
+    Ok(None)
+}
+
+pub async fn stream_next_slices(destination: u8) -> Vec<Packet> {
+    let mut slices = Vec::new();
+    if let Some(stream) = STREAMS.async_lock().await.get_mut(&destination) {
+        while stream.in_flight.len() < STREAM_WINDOW {
+            match stream.pending.pop_front() {
+                Some((packet, last)) => {
+                    stream.in_flight.push_back(last);
+                    slices.push(packet);
                 }
+                None => break,
             }
         }
-        if is_subkernel {
-            match SUBKERNELS.async_lock().await.get(&id).unwrap().state {
-                SubkernelState::Finished {
-                    status: FinishStatus::CommLost,
-                } => return Err(Error::CommLost),
-                SubkernelState::Finished {
-                    status: FinishStatus::Exception(_),
-                } => return Err(Error::SubkernelException),
-                _ => (),
+    }
+    slices
+}
+
+pub async fn stream_slice_acked(source: u8) -> bool {
+    // acks come back in the order the slices were sent
+    match STREAMS.async_lock().await.get_mut(&source) {
+        Some(stream) => match stream.in_flight.pop_front() {
+            Some(last) => {
+                if last {
+                    stream.messages -= 1;
+                }
+                stream.acks = stream.acks.wrapping_add(1);
+                true
             }
+            None => false,
+        },
+        None => false,
+    }
+}
+
+async fn stream_push(id: u32, destination: u8, message: &[u8], timer: GlobalTimer) {
+    let mut streams = STREAMS.async_lock().await;
+    let stream = streams.entry(destination).or_default();
+    if stream.in_flight.is_empty() {
+        stream.progress = (stream.acks, timer.get_time().0);
+    }
+    let mut i = 0;
+    while i < message.len() {
+        let mut slice: [u8; MASTER_PAYLOAD_MAX_SIZE] = [0; MASTER_PAYLOAD_MAX_SIZE];
+        let len = min(MASTER_PAYLOAD_MAX_SIZE, message.len() - i);
+        let last = i + len == message.len();
+        slice[..len].clone_from_slice(&message[i..i + len]);
+        let packet = Packet::SubkernelMessage {
+            source: 0,
+            destination: destination,
+            id: id,
+            status: PayloadStatus::from_status(i == 0, last),
+            length: len as u16,
+            data: slice,
+        };
+        stream.pending.push_back((packet, last));
+        i += len;
+    }
+    stream.messages += 1;
+}
+
+// Lets out what the window allows; returns the number of messages still
+// to be acknowledged by the destination, unless the stream stalled.
+async fn stream_service(
+    aux_mutex: &Rc<Mutex<bool>>,
+    routing_table: &RoutingTable,
+    timer: GlobalTimer,
+    destination: u8,
+) -> Result<usize, Error> {
+    if let Err(e) = drtio::subkernel_stream_service(aux_mutex, routing_table, destination).await {
+        STREAMS.async_lock().await.remove(&destination);
+        return Err(e.into());
+    }
+    let now = timer.get_time().0;
+    let mut streams = STREAMS.async_lock().await;
+    let (messages, stalled) = match streams.get_mut(&destination) {
+        Some(stream) => {
+            if stream.in_flight.is_empty() || stream.acks != stream.progress.0 {
+                stream.progress = (stream.acks, now);
+            }
+            (stream.messages, now > stream.progress.1 + STREAM_TIMEOUT)
         }
-        task::r#yield().await;
+        None => (0, false),
+    };
+    if stalled {
+        error!("destination {} stopped acknowledging messages", destination);
+        streams.remove(&destination);
+        return Err(Error::Timeout);
     }
-    Err(Error::Timeout)
+    Ok(messages)
 }
 
 pub async fn message_send<'a>(
@@ -331,5 +543,26 @@ pub async fn message_send<'a>(
     destination: u8,
     message: Vec<u8>,
 ) -> Result<(), Error> {
-    Ok(drtio::subkernel_send_message(aux_mutex, routing_table, timer, id, destination, &message).await?)
+    stream_push(id, destination, &message, timer).await;
+    // done once the destination acknowledged this message, and any streamed before it
+    while stream_service(aux_mutex, routing_table, timer, destination).await? > 0 {
+        task::r#yield().await;
+    }
+    Ok(())
+}
+
+pub async fn message_try_send(
+    aux_mutex: &Rc<Mutex<bool>>,
+    routing_table: &RoutingTable,
+    timer: GlobalTimer,
+    id: u32,
+    destination: u8,
+    message: Vec<u8>,
+) -> Result<bool, Error> {
+    if stream_service(aux_mutex, routing_table, timer, destination).await? >= STREAM_QUEUE_LENGTH {
+        return Ok(false);
+    }
+    stream_push(id, destination, &message, timer).await;
+    stream_service(aux_mutex, routing_table, timer, destination).await?;
+    Ok(true)
 }
@@ -6,7 +6,7 @@ build = "build.rs"
 
 [features]
 target_zc706 = ["libboard_zynq/target_zc706", "libsupport_zynq/target_zc706", "libconfig/target_zc706", "libboard_artiq/target_zc706"]
-target_kasli_soc = ["libboard_zynq/target_kasli_soc", "libsupport_zynq/target_kasli_soc", "libconfig/target_kasli_soc", "libboard_artiq/target_kasli_soc"]
+target_kasli_soc = ["libboard_zynq/target_kasli_soc", "libsupport_zynq/target_kasli_soc", "libconfig/target_kasli_soc", "libboard_artiq/target_kasli_soc", "ksupport/target_kasli_soc"]
 calibrate_wrpll_skew = ["libboard_artiq/calibrate_wrpll_skew"]
 default = ["target_zc706", ]
 
@@ -17,6 +17,7 @@ build_zynq = { path = "../libbuild_zynq" }
 log = { version = "0.4", default-features = false }
 core_io = { version = "0.1", features = ["collections"] }
 cslice = "0.3"
+crc = { version = "1.7", default-features = false }
 embedded-hal = "0.2"
 
 libboard_zynq = { path = "@@ZYNQ_RS@@/libboard_zynq", features = ["ipv6"]}
@@ -27,6 +28,8 @@ libregister = { path = "@@ZYNQ_RS@@/libregister" }
 libconfig = { path = "@@ZYNQ_RS@@/libconfig", features = ["fat_lfn", "ipv6"] }
 
 libboard_artiq = { path = "../libboard_artiq" }
+dma_trace = { path = "../libdma_trace" }
+drtio_hops = { path = "../libdrtio_hops" }
 unwind = { path = "../libunwind" }
 libc = { path = "../libc" }
 io = { path = "../libio", features = ["alloc"] }
@@ -1,6 +1,7 @@
 use alloc::{collections::btree_map::BTreeMap, string::String, vec::Vec};
 use core::mem;
 
+use dma_trace::{find_event_word, patch_word, revert_patches};
 use ksupport::kernel::DmaRecorder;
 use libboard_artiq::{drtio_routing::RoutingTable,
                      drtioaux_proto::{Packet, PayloadStatus, MASTER_PAYLOAD_MAX_SIZE},
@@ -32,6 +33,7 @@ pub enum Error {
     EntryNotComplete,
     MasterDmaFound,
     UploadFail,
+    EventNotFound,
 }
 
 #[derive(Debug)]
@@ -40,6 +42,8 @@ struct Entry {
     padding_len: usize,
     complete: bool,
     duration: i64, // relevant for local DMA
+    // offsets and original values of the words patched for the next playback
+    patches: Vec<(usize, u32)>,
 }
 
 impl Entry {
@@ -49,6 +53,7 @@ impl Entry {
             padding_len: 0,
             complete: true,
             duration: duration,
+            patches: Vec::new(),
         };
         entry.realign();
         entry
@@ -77,6 +82,25 @@ impl Entry {
 
         dcci_slice(&self.trace);
     }
+
+    pub fn patch(&mut self, target: u32, timestamp: u64, word: usize, value: u32) -> Result<(), Error> {
+        let trace = &mut self.trace[self.padding_len..];
+        let offset = find_event_word(trace, target, timestamp, word).ok_or(Error::EventNotFound)?;
+        let old = patch_word(trace, offset, value);
+        self.patches.push((offset, old));
+        dcci_slice(trace);
+        Ok(())
+    }
+
+    pub fn revert_patches(&mut self) {
+        if self.patches.is_empty() {
+            return;
+        }
+        let trace = &mut self.trace[self.padding_len..];
+        revert_patches(trace, &self.patches);
+        self.patches.clear();
+        dcci_slice(trace);
+    }
 }
 
 #[derive(Debug)]
@@ -91,6 +115,8 @@ enum RemoteTraceState {
 struct RemoteTraces {
     remote_traces: BTreeMap<u8, Sliceable>,
     state: RemoteTraceState,
+    // patches requested while the traces are still being sent
+    pending_patches: Vec<Packet>,
 }
 
 impl RemoteTraces {
@@ -98,6 +124,7 @@ impl RemoteTraces {
         RemoteTraces {
             remote_traces: traces,
             state: RemoteTraceState::Unsent,
+            pending_patches: Vec::new(),
         }
     }
 
@@ -152,6 +179,9 @@ impl RemoteTraces {
                 if trace.at_end() {
                     if count - 1 == 0 {
                         self.state = RemoteTraceState::Ready;
+                        for packet in self.pending_patches.drain(..) {
+                            router.route(packet, routing_table, rank, self_destination);
+                        }
                         if let Some((id, timestamp)) = kernel_manager.ddma_remote_uploaded(succeeded) {
                             self.playback(id, timestamp, router, rank, self_destination, routing_table);
                         }
@@ -224,6 +254,72 @@ impl RemoteTraces {
         }
     }
 
+    // on subkernel request, reverted by the destination after the next playback
+    pub fn patch(
+        &mut self,
+        id: u32,
+        destination: u8,
+        target: u32,
+        timestamp: u64,
+        word: u8,
+        value: u32,
+        router: &mut Router,
+        rank: u8,
+        self_destination: u8,
+        routing_table: &RoutingTable,
+    ) -> bool {
+        if !self.remote_traces.contains_key(&destination) {
+            return false;
+        }
+        let packet = Packet::DmaPatchRequest {
+            source: self_destination,
+            destination: destination,
+            id: id,
+            target: target,
+            timestamp: timestamp,
+            word: word,
+            value: value,
+        };
+        if let RemoteTraceState::Sending(_) = self.state {
+            self.pending_patches.push(packet);
+        } else {
+            router.route(packet, routing_table, rank, self_destination);
+        }
+        // the kernel waits for the response, it reaches the destination before the playback request
+        true
+    }
+
+    // on subkernel request, for patches of a trace that will not be played back
+    pub fn revert_patches(
+        &mut self,
+        id: u32,
+        destination: u8,
+        router: &mut Router,
+        rank: u8,
+        self_destination: u8,
+        routing_table: &RoutingTable,
+    ) {
+        if let RemoteTraceState::Sending(_) = self.state {
+            // not sent yet
+            self.pending_patches.retain(|packet| match packet {
+                Packet::DmaPatchRequest { destination: dest, .. } => *dest != destination,
+                _ => true,
+            });
+            return;
+        }
+        router.route(
+            Packet::DmaPatchRevertRequest {
+                source: self_destination,
+                destination: destination,
+                id: id,
+            },
+            routing_table,
+            rank,
+            self_destination,
+        );
+        // response will be ignored
+    }
+
     pub fn erase(
         &mut self,
         id: u32,
@@ -298,6 +394,7 @@ impl Manager {
                             padding_len: 0,
                             complete: false,
                             duration: 0,
+                            patches: Vec::new(),
                         },
                     );
                     self.entries.get_mut(&(source, id)).unwrap()
@@ -313,6 +410,7 @@ impl Manager {
                         padding_len: 0,
                         complete: false,
                         duration: 0,
+                        patches: Vec::new(),
                     },
                 );
                 self.entries.get_mut(&(source, id)).unwrap()
@@ -426,6 +524,86 @@ impl Manager {
         }
     }
 
+    // API for subkernel
+    pub fn patch_remote(
+        &mut self,
+        id: u32,
+        destination: u8,
+        target: u32,
+        timestamp: u64,
+        word: u8,
+        value: u32,
+        router: &mut Router,
+        rank: u8,
+        self_destination: u8,
+        routing_table: &RoutingTable,
+    ) -> bool {
+        match self.remote_entries.get_mut(&id) {
+            Some(traces) => traces.patch(
+                id,
+                destination,
+                target,
+                timestamp,
+                word,
+                value,
+                router,
+                rank,
+                self_destination,
+                routing_table,
+            ),
+            None => false,
+        }
+    }
+
+    // API for subkernel
+    pub fn revert_remote_patches(
+        &mut self,
+        id: u32,
+        destination: u8,
+        router: &mut Router,
+        rank: u8,
+        self_destination: u8,
+        routing_table: &RoutingTable,
+    ) {
+        if let Some(traces) = self.remote_entries.get_mut(&id) {
+            traces.revert_patches(id, destination, router, rank, self_destination, routing_table);
+        }
+    }
+
+    // api for DRTIO
+    pub fn patch(
+        &mut self,
+        source: u8,
+        id: u32,
+        target: u32,
+        timestamp: u64,
+        word: u8,
+        value: u32,
+    ) -> Result<(), Error> {
+        if self.state == ManagerState::Playback && self.current_source == source && self.current_id == id {
+            return Err(Error::PlaybackInProgress);
+        }
+        match self.entries.get_mut(&(source, id)) {
+            Some(entry) if entry.complete => entry.patch(target, timestamp, word as usize, value),
+            Some(_) => Err(Error::EntryNotComplete),
+            None => Err(Error::IdNotFound),
+        }
+    }
+
+    // api for DRTIO
+    pub fn revert_patches(&mut self, source: u8, id: u32) -> Result<(), Error> {
+        if self.state == ManagerState::Playback && self.current_source == source && self.current_id == id {
+            return Err(Error::PlaybackInProgress);
+        }
+        match self.entries.get_mut(&(source, id)) {
+            Some(entry) => {
+                entry.revert_patches();
+                Ok(())
+            }
+            None => Err(Error::IdNotFound),
+        }
+    }
+
     // API for subkernel
     pub fn cleanup(&mut self, router: &mut Router, rank: u8, self_destination: u8, routing_table: &RoutingTable) {
         // after subkernel ends, remove all self-generated traces
@@ -538,6 +716,10 @@ impl Manager {
             return None;
         } else {
             self.state = ManagerState::Idle;
+            // patches only apply to a single playback
+            if let Some(entry) = self.entries.get_mut(&(self.current_source, self.current_id)) {
+                entry.revert_patches();
+            }
             unsafe {
                 csr::cri_con::selected_write(0);
                 let error = csr::rtio_dma::error_read();
@@ -5,7 +5,10 @@
 #[macro_use]
 extern crate log;
 extern crate core_io;
+extern crate crc;
 extern crate cslice;
+extern crate dma_trace;
+extern crate drtio_hops;
 extern crate embedded_hal;
 
 extern crate io;
@@ -35,19 +38,21 @@ use libboard_artiq::si549;
 use libboard_artiq::{drtio_routing, drtioaux,
                      drtioaux_proto::{MASTER_PAYLOAD_MAX_SIZE, SAT_PAYLOAD_MAX_SIZE},
                      identifier_read, logger,
-                     pl::csr};
+                     pl::csr, spi};
 #[cfg(feature = "target_kasli_soc")]
 use libboard_zynq::error_led::ErrorLED;
-use libboard_zynq::{i2c::I2c, print, println, time::Milliseconds, timer::GlobalTimer};
+use libboard_zynq::{i2c::I2c, print, println, slcr, time::Milliseconds, timer::GlobalTimer};
 use libconfig::Config;
 use libcortex_a9::{l2c::enable_l2_cache, regs::MPIDR};
 use libregister::RegisterR;
 use libsupport_zynq::{exception_vectors, ram};
+use mgmt::Manager as MgmtManager;
 use routing::Router;
 use subkernel::Manager as KernelManager;
 
 mod analyzer;
 mod dma;
+mod mgmt;
 mod repeater;
 mod routing;
 mod subkernel;
@@ -115,7 +120,7 @@ macro_rules! forward {
                         $timer,
                     );
                 } else {
-                    return $repeaters[repno].aux_send($packet);
+                    return $repeaters[repno].aux_relay($packet);
                 }
             } else {
                 return Err(drtioaux::Error::RoutingError);
@@ -149,6 +154,8 @@ fn process_aux_packet(
     dma_manager: &mut DmaManager,
     analyzer: &mut Analyzer,
     kernel_manager: &mut KernelManager,
+    mgmt_manager: &mut MgmtManager,
+    cfg: &Config,
     router: &mut Router,
 ) -> Result<(), drtioaux::Error> {
     // In the code below, *_chan_sel_write takes an u8 if there are fewer than 256 channels,
@@ -268,6 +275,72 @@ fn process_aux_packet(
             drtioaux::send(0, &drtioaux::Packet::RoutingAck)
         }
 
+        drtioaux::Packet::RepeaterStatsRequest {
+            destination: _destination,
+            repno: _repno,
+        } => {
+            forward!(
+                router,
+                _routing_table,
+                _destination,
+                *rank,
+                *self_destination,
+                _repeaters,
+                &packet,
+                timer
+            );
+            #[cfg(has_drtio_routing)]
+            if (_repno as usize) < _repeaters.len() {
+                let rep = &_repeaters[_repno as usize];
+                let stats = rep.stats();
+                return drtioaux::send(
+                    0,
+                    &drtioaux::Packet::RepeaterStatsReply {
+                        succeeded: true,
+                        up: rep.is_up(),
+                        forwarded_packets: stats.forwarded_packets,
+                        forward_timeouts: stats.forward_timeouts,
+                        rtio_errors: stats.rtio_errors,
+                        tsc_sync_attempts: stats.tsc_sync_attempts,
+                    },
+                );
+            }
+            drtioaux::send(
+                0,
+                &drtioaux::Packet::RepeaterStatsReply {
+                    succeeded: false,
+                    up: false,
+                    forwarded_packets: 0,
+                    forward_timeouts: 0,
+                    rtio_errors: 0,
+                    tsc_sync_attempts: 0,
+                },
+            )
+        }
+
+        drtioaux::Packet::RepeaterSetEnabledRequest {
+            destination: _destination,
+            repno: _repno,
+            enabled: _enabled,
+        } => {
+            forward!(
+                router,
+                _routing_table,
+                _destination,
+                *rank,
+                *self_destination,
+                _repeaters,
+                &packet,
+                timer
+            );
+            #[cfg(has_drtio_routing)]
+            if (_repno as usize) < _repeaters.len() {
+                _repeaters[_repno as usize].set_enabled(_enabled);
+                return drtioaux::send(0, &drtioaux::Packet::RepeaterSetEnabledReply { succeeded: true });
+            }
+            drtioaux::send(0, &drtioaux::Packet::RepeaterSetEnabledReply { succeeded: false })
+        }
+
         #[cfg(not(has_drtio_routing))]
         drtioaux::Packet::RoutingSetPath {
             destination: _,
@@ -509,11 +582,11 @@ fn process_aux_packet(
 
         drtioaux::Packet::SpiSetConfigRequest {
             destination: _destination,
-            busno: _busno,
-            flags: _flags,
-            length: _length,
-            div: _div,
-            cs: _cs,
+            busno,
+            flags,
+            length,
+            div,
+            cs,
         } => {
             forward!(
                 router,
@@ -525,14 +598,13 @@ fn process_aux_packet(
                 &packet,
                 timer
             );
-            // todo: reimplement when/if SPI is available
-            //let succeeded = spi::set_config(busno, flags, length, div, cs).is_ok();
-            drtioaux::send(0, &drtioaux::Packet::SpiBasicReply { succeeded: false })
+            let succeeded = spi::set_config(busno, flags, length, div, cs).is_ok();
+            drtioaux::send(0, &drtioaux::Packet::SpiBasicReply { succeeded })
         }
         drtioaux::Packet::SpiWriteRequest {
             destination: _destination,
-            busno: _busno,
-            data: _data,
+            busno,
+            data,
         } => {
             forward!(
                 router,
@@ -544,13 +616,12 @@ fn process_aux_packet(
                 &packet,
                 timer
             );
-            // todo: reimplement when/if SPI is available
-            //let succeeded = spi::write(busno, data).is_ok();
-            drtioaux::send(0, &drtioaux::Packet::SpiBasicReply { succeeded: false })
+            let succeeded = spi::write(busno, data).is_ok();
+            drtioaux::send(0, &drtioaux::Packet::SpiBasicReply { succeeded })
         }
         drtioaux::Packet::SpiReadRequest {
             destination: _destination,
-            busno: _busno,
+            busno,
         } => {
             forward!(
                 router,
@@ -562,20 +633,16 @@ fn process_aux_packet(
                 &packet,
                 timer
             );
-            // todo: reimplement when/if SPI is available
-            // match spi::read(busno) {
-            //     Ok(data) => drtioaux::send(0,
-            //         &drtioaux::Packet::SpiReadReply { succeeded: true, data: data }),
-            //     Err(_) => drtioaux::send(0,
-            //         &drtioaux::Packet::SpiReadReply { succeeded: false, data: 0 })
-            // }
-            drtioaux::send(
-                0,
-                &drtioaux::Packet::SpiReadReply {
-                    succeeded: false,
-                    data: 0,
-                },
-            )
+            match spi::read(busno) {
+                Ok(data) => drtioaux::send(0, &drtioaux::Packet::SpiReadReply { succeeded: true, data }),
+                Err(_) => drtioaux::send(
+                    0,
+                    &drtioaux::Packet::SpiReadReply {
+                        succeeded: false,
+                        data: 0,
+                    },
+                ),
+            }
         }
 
         drtioaux::Packet::AnalyzerHeaderRequest {
@@ -778,6 +845,95 @@ fn process_aux_packet(
             }
             Ok(())
         }
+        drtioaux::Packet::DmaPatchRequest {
+            source,
+            destination: _destination,
+            id,
+            target,
+            timestamp,
+            word,
+            value,
+        } => {
+            forward!(
+                router,
+                _routing_table,
+                _destination,
+                *rank,
+                *self_destination,
+                _repeaters,
+                &packet,
+                timer
+            );
+            let succeeded = dma_manager.patch(source, id, target, timestamp, word, value).is_ok();
+            router.send(
+                drtioaux::Packet::DmaPatchReply {
+                    destination: source,
+                    succeeded: succeeded,
+                },
+                _routing_table,
+                *rank,
+                *self_destination,
+            )
+        }
+        drtioaux::Packet::DmaPatchReply {
+            destination: _destination,
+            succeeded,
+        } => {
+            forward!(
+                router,
+                _routing_table,
+                _destination,
+                *rank,
+                *self_destination,
+                _repeaters,
+                &packet,
+                timer
+            );
+            kernel_manager.ddma_patched(succeeded);
+            Ok(())
+        }
+        drtioaux::Packet::DmaPatchRevertRequest {
+            source,
+            destination: _destination,
+            id,
+        } => {
+            forward!(
+                router,
+                _routing_table,
+                _destination,
+                *rank,
+                *self_destination,
+                _repeaters,
+                &packet,
+                timer
+            );
+            let succeeded = dma_manager.revert_patches(source, id).is_ok();
+            router.send(
+                drtioaux::Packet::DmaPatchRevertReply {
+                    destination: source,
+                    succeeded: succeeded,
+                },
+                _routing_table,
+                *rank,
+                *self_destination,
+            )
+        }
+        drtioaux::Packet::DmaPatchRevertReply {
+            destination: _destination,
+            succeeded: _,
+        } => {
+            forward!(
+                router,
+                _routing_table,
+                _destination,
+                *rank,
+                *self_destination,
+                _repeaters,
+                &packet,
+                timer
+            );
+            Ok(())
+        }
         drtioaux::Packet::DmaPlaybackStatus {
             source: _,
             destination: _destination,
@@ -821,6 +977,27 @@ fn process_aux_packet(
             let succeeded = kernel_manager.add(id, status, &data, length as usize).is_ok();
             drtioaux::send(0, &drtioaux::Packet::SubkernelAddDataReply { succeeded: succeeded })
         }
+        drtioaux::Packet::SubkernelCacheQuery {
+            destination,
+            id,
+            hash,
+            length,
+            bind,
+        } => {
+            forward!(
+                router,
+                _routing_table,
+                destination,
+                *rank,
+                *self_destination,
+                _repeaters,
+                &packet,
+                timer
+            );
+            *self_destination = destination;
+            let present = kernel_manager.cached(id, hash, length as usize, bind);
+            drtioaux::send(0, &drtioaux::Packet::SubkernelCacheReply { present: present })
+        }
         drtioaux::Packet::SubkernelLoadRunRequest {
             source,
             destination: _destination,
@@ -858,6 +1035,61 @@ fn process_aux_packet(
                 *self_destination,
             )
         }
+        drtioaux::Packet::SubkernelStopRequest {
+            source,
+            destination: _destination,
+            id,
+        } => {
+            forward!(
+                router,
+                _routing_table,
+                _destination,
+                *rank,
+                *self_destination,
+                _repeaters,
+                &packet,
+                timer
+            );
+            let succeeded = kernel_manager.stop(id).is_ok();
+            if succeeded {
+                info!("subkernel {} stopped", id);
+                dma_manager.cleanup(router, *rank, *self_destination, _routing_table);
+            }
+            router.send(
+                drtioaux::Packet::SubkernelStopReply {
+                    destination: source,
+                    succeeded: succeeded,
+                },
+                _routing_table,
+                *rank,
+                *self_destination,
+            )
+        }
+        drtioaux::Packet::DestinationEchoRequest {
+            source,
+            destination: _destination,
+            seqno,
+        } => {
+            forward!(
+                router,
+                _routing_table,
+                _destination,
+                *rank,
+                *self_destination,
+                _repeaters,
+                &packet,
+                timer
+            );
+            router.send(
+                drtioaux::Packet::DestinationEchoReply {
+                    destination: source,
+                    seqno: seqno,
+                },
+                _routing_table,
+                *rank,
+                *self_destination,
+            )
+        }
         drtioaux::Packet::SubkernelLoadRunReply {
             destination: _destination,
             succeeded,
@@ -969,13 +1201,17 @@ fn process_aux_packet(
             );
             kernel_manager.message_handle_incoming(status, id, length as usize, &data);
             router.send(
-                drtioaux::Packet::SubkernelMessageAck { destination: source },
+                drtioaux::Packet::SubkernelMessageAck {
+                    source: *self_destination,
+                    destination: source,
+                },
                 _routing_table,
                 *rank,
                 *self_destination,
             )
         }
         drtioaux::Packet::SubkernelMessageAck {
+            source,
             destination: _destination,
         } => {
             forward!(
@@ -988,26 +1224,147 @@ fn process_aux_packet(
                 &packet,
                 timer
             );
-            if kernel_manager.message_ack_slice() {
-                let mut data_slice: [u8; MASTER_PAYLOAD_MAX_SIZE] = [0; MASTER_PAYLOAD_MAX_SIZE];
-                if let Some(meta) = kernel_manager.message_get_slice(&mut data_slice) {
-                    // route and not send immediately as ACKs are not a beginning of a transaction
-                    router.route(
-                        drtioaux::Packet::SubkernelMessage {
-                            source: *self_destination,
-                            destination: meta.destination,
-                            id: kernel_manager.get_current_id().unwrap(),
-                            status: meta.status,
-                            length: meta.len as u16,
-                            data: data_slice,
-                        },
-                        _routing_table,
-                        *rank,
-                        *self_destination,
-                    );
-                } else {
-                    error!("Error receiving message slice");
-                }
+            kernel_manager.message_ack_slice(source, router, _routing_table, *rank, *self_destination);
+            Ok(())
+        }
+
+        drtioaux::Packet::FirmwareAddDataRequest {
+            destination: _destination,
+            status,
+            length,
+            data,
+        } => {
+            forward!(
+                router,
+                _routing_table,
+                _destination,
+                *rank,
+                *self_destination,
+                _repeaters,
+                &packet,
+                timer
+            );
+            let succeeded = mgmt_manager
+                .add_firmware_data(status, &data[..length as usize])
+                .map_err(|e| warn!("error adding firmware data: {:?}", e))
+                .is_ok();
+            drtioaux::send(0, &drtioaux::Packet::FirmwareAddDataReply { succeeded: succeeded })
+        }
+        drtioaux::Packet::FirmwareCommitRequest {
+            destination: _destination,
+            length,
+            checksum,
+        } => {
+            forward!(
+                router,
+                _routing_table,
+                _destination,
+                *rank,
+                *self_destination,
+                _repeaters,
+                &packet,
+                timer
+            );
+            let succeeded = mgmt_manager
+                .commit_firmware(cfg, length, checksum)
+                .map_err(|e| error!("firmware update failed: {:?}", e))
+                .is_ok();
+            drtioaux::send(0, &drtioaux::Packet::FirmwareCommitReply { succeeded: succeeded })
+        }
+        drtioaux::Packet::RebootRequest {
+            destination: _destination,
+        } => {
+            forward!(
+                router,
+                _routing_table,
+                _destination,
+                *rank,
+                *self_destination,
+                _repeaters,
+                &packet,
+                timer
+            );
+            info!("rebooting");
+            drtioaux::send(0, &drtioaux::Packet::RebootAck)?;
+            // give the acknowledgement time to leave before going down
+            timer.delay_us(10_000);
+            slcr::reboot();
+            Ok(())
+        }
+        drtioaux::Packet::GrabberStatusRequest {
+            destination: _destination,
+            grabber: _grabber,
+        } => {
+            forward!(
+                router,
+                _routing_table,
+                _destination,
+                *rank,
+                *self_destination,
+                _repeaters,
+                &packet,
+                timer
+            );
+            #[cfg(has_grabber)]
+            {
+                let status = grabber::status(_grabber as usize).unwrap_or_default();
+                drtioaux::send(
+                    0,
+                    &drtioaux::Packet::GrabberStatusReply {
+                        count: grabber::GRABBER_COUNT as u8,
+                        state: status.state as u8,
+                        video_clock: status.video_clock,
+                        frame_width: status.frame_size.0,
+                        frame_height: status.frame_size.1,
+                        lock_loss_count: status.lock_loss_count,
+                    },
+                )
+            }
+            #[cfg(not(has_grabber))]
+            drtioaux::send(
+                0,
+                &drtioaux::Packet::GrabberStatusReply {
+                    count: 0,
+                    state: 0,
+                    video_clock: 0,
+                    frame_width: 0,
+                    frame_height: 0,
+                    lock_loss_count: 0,
+                },
+            )
+        }
+        drtioaux::Packet::WrpllCalibrateRequest {
+            destination: _destination,
+        } => {
+            forward!(
+                router,
+                _routing_table,
+                _destination,
+                *rank,
+                *self_destination,
+                _repeaters,
+                &packet,
+                timer
+            );
+            #[cfg(all(has_wrpll, feature = "calibrate_wrpll_skew"))]
+            {
+                info!("recalibrating WRPLL skew");
+                let succeeded = match si549::wrpll::recalibrate_skew(timer, cfg) {
+                    Ok(offset) => {
+                        info!("WRPLL skew recalibrated, tag offset: {}", offset);
+                        true
+                    }
+                    Err(e) => {
+                        error!("WRPLL skew recalibration failed: {}", e);
+                        false
+                    }
+                };
+                drtioaux::send(0, &drtioaux::Packet::WrpllCalibrateReply { succeeded })?;
+            }
+            #[cfg(not(all(has_wrpll, feature = "calibrate_wrpll_skew")))]
+            {
+                warn!("WRPLL skew calibration is not supported by this satellite");
+                drtioaux::send(0, &drtioaux::Packet::WrpllCalibrateReply { succeeded: false })?;
             }
             Ok(())
         }
@@ -1029,6 +1386,8 @@ fn process_aux_packets(
     dma_manager: &mut DmaManager,
     analyzer: &mut Analyzer,
     kernel_manager: &mut KernelManager,
+    mgmt_manager: &mut MgmtManager,
+    cfg: &Config,
     router: &mut Router,
 ) {
     let result = drtioaux::recv(0).and_then(|packet| {
@@ -1044,6 +1403,8 @@ fn process_aux_packets(
                 dma_manager,
                 analyzer,
                 kernel_manager,
+                mgmt_manager,
+                cfg,
                 router,
             )
         } else {
@@ -1101,6 +1462,27 @@ fn drtiosat_process_errors() {
     }
 }
 
+#[cfg(feature = "target_kasli_soc")]
+fn io_expanders_service(
+    i2c: &mut I2c,
+    io_expander0: &mut io_expander::IoExpander,
+    io_expander1: &mut io_expander::IoExpander,
+    timer: &GlobalTimer,
+) {
+    if let Err(e) = io_expander0.service(i2c) {
+        warn!("I2C I/O expander #0 service failed ({})", e);
+    }
+    if let Err(e) = io_expander1.service(i2c) {
+        warn!("I2C I/O expander #1 service failed ({})", e);
+    }
+    if let Err(e) = io_expander0.poll_inputs(i2c, timer) {
+        warn!("I2C I/O expander #0 input poll failed ({})", e);
+    }
+    if let Err(e) = io_expander1.poll_inputs(i2c, timer) {
+        warn!("I2C I/O expander #1 input poll failed ({})", e);
+    }
+}
+
 fn hardware_tick(ts: &mut u64, timer: &mut GlobalTimer) {
     let now = timer.get_time();
     let mut ts_ms = Milliseconds(*ts);
@@ -1109,6 +1491,13 @@ fn hardware_tick(ts: &mut u64, timer: &mut GlobalTimer) {
         *ts = ts_ms.0;
         #[cfg(has_grabber)]
         grabber::tick();
+        #[cfg(has_wrpll)]
+        while let Some(event) = si549::wrpll::take_event() {
+            match event {
+                si549::wrpll::Event::ReferenceLost => warn!("WRPLL reference lost, entering holdover"),
+                si549::wrpll::Event::ReferenceRecovered => info!("WRPLL reference recovered, re-acquiring lock"),
+            }
+        }
     }
 }
 
@@ -1187,6 +1576,14 @@ pub extern "C" fn main_core0() -> i32 {
 
     ram::init_alloc_core0();
 
+    let cfg = match Config::new() {
+        Ok(cfg) => cfg,
+        Err(err) => {
+            warn!("config initialization failed: {}", err);
+            Config::new_dummy()
+        }
+    };
+
     ksupport::kernel::i2c::init();
     let mut i2c = unsafe { (ksupport::kernel::i2c::I2C_BUS).as_mut().unwrap() };
 
@@ -1203,19 +1600,8 @@ pub extern "C" fn main_core0() -> i32 {
             .init(&mut i2c)
             .expect("I2C I/O expander #1 initialization failed");
 
-        // Drive CLK_SEL to true
-        #[cfg(has_si549)]
-        io_expander0.set(1, 7, true);
-
-        // Drive TX_DISABLE to false on SFP0..3
-        io_expander0.set(0, 1, false);
-        io_expander1.set(0, 1, false);
-        io_expander0.set(1, 1, false);
-        io_expander1.set(1, 1, false);
-
-        // Enable EEM power
-        #[cfg(hw_rev = "v1.2")]
-        io_expander1.set(0, 7, true);
+        // CLK_SEL, SFP TX_DISABLE and EEM power, with overrides from config
+        io_expander::config(&cfg);
 
         io_expander0.service(&mut i2c).unwrap();
         io_expander1.service(&mut i2c).unwrap();
@@ -1245,13 +1631,11 @@ pub extern "C" fn main_core0() -> i32 {
     #[cfg(has_si549)]
     si549::helper_setup(&mut timer, &SI549_SETTINGS).expect("cannot initialize helper Si549");
 
-    let cfg = match Config::new() {
-        Ok(cfg) => cfg,
-        Err(err) => {
-            warn!("config initialization failed: {}", err);
-            Config::new_dummy()
-        }
-    };
+    #[cfg(has_wrpll)]
+    {
+        si549::wrpll::config_filter(&cfg);
+        si549::wrpll::load_tag_offset(&cfg);
+    }
 
     if let Ok(spread_enable) = cfg.read_str("sed_spread_enable") {
         match spread_enable.as_ref() {
@@ -1271,8 +1655,9 @@ pub extern "C" fn main_core0() -> i32 {
     let mut repeaters = [repeater::Repeater::default(); csr::DRTIOREP.len()];
     #[cfg(not(has_drtio_routing))]
     let mut repeaters = [repeater::Repeater::default(); 0];
+    let retry_policy = drtio_routing::config_retry_policy(&cfg, 200);
     for i in 0..repeaters.len() {
-        repeaters[i] = repeater::Repeater::new(i as u8);
+        repeaters[i] = repeater::Repeater::new(i as u8, retry_policy);
     }
     let mut routing_table = drtio_routing::RoutingTable::default_empty();
     let mut rank = 1;
@@ -1280,6 +1665,8 @@ pub extern "C" fn main_core0() -> i32 {
 
     let mut hardware_tick_ts = 0;
 
+    let mut mgmt_manager = MgmtManager::new();
+
     let mut control = ksupport::kernel::Control::start();
 
     loop {
@@ -1293,12 +1680,7 @@ pub extern "C" fn main_core0() -> i32 {
             }
             #[cfg(feature = "target_kasli_soc")]
             {
-                io_expander0
-                    .service(&mut i2c)
-                    .expect("I2C I/O expander #0 service failed");
-                io_expander1
-                    .service(&mut i2c)
-                    .expect("I2C I/O expander #1 service failed");
+                io_expanders_service(&mut i2c, &mut io_expander0, &mut io_expander1, &timer);
             }
 
             hardware_tick(&mut hardware_tick_ts, &mut timer);
@@ -1312,7 +1694,13 @@ pub extern "C" fn main_core0() -> i32 {
         }
 
         #[cfg(has_wrpll)]
-        si549::wrpll::select_recovered_clock(true, &mut timer);
+        {
+            si549::wrpll::select_recovered_clock(true, &mut timer);
+            #[cfg(feature = "calibrate_wrpll_skew")]
+            if let Err(e) = si549::wrpll::save_tag_offset(&cfg) {
+                error!("{}", e);
+            }
+        }
 
         // Various managers created here, so when link is dropped, all DMA traces
         // are cleared out for a clean slate on subsequent connections,
@@ -1337,6 +1725,8 @@ pub extern "C" fn main_core0() -> i32 {
                 &mut dma_manager,
                 &mut analyzer,
                 &mut kernel_manager,
+                &mut mgmt_manager,
+                &cfg,
                 &mut router,
             );
             #[allow(unused_mut)]
@@ -1345,17 +1735,15 @@ pub extern "C" fn main_core0() -> i32 {
             }
             #[cfg(feature = "target_kasli_soc")]
             {
-                io_expander0
-                    .service(&mut i2c)
-                    .expect("I2C I/O expander #0 service failed");
-                io_expander1
-                    .service(&mut i2c)
-                    .expect("I2C I/O expander #1 service failed");
+                // a running subkernel drives the same bus and switch
+                if !kernel_manager.running() {
+                    io_expanders_service(&mut i2c, &mut io_expander0, &mut io_expander1, &timer);
+                }
             }
             hardware_tick(&mut hardware_tick_ts, &mut timer);
             if drtiosat_tsc_loaded() {
                 info!("TSC loaded from uplink");
-                for rep in repeaters.iter() {
+                for rep in repeaters.iter_mut() {
                     if let Err(e) = rep.sync_tsc(&mut timer) {
                         error!("failed to sync TSC ({:?})", e);
                     }
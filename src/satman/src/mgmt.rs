@@ -0,0 +1,55 @@
+use alloc::vec::Vec;
+use core::mem;
+
+use crc::crc32;
+use libboard_artiq::drtioaux_proto::PayloadStatus;
+use libconfig::Config;
+
+#[derive(Debug)]
+pub enum Error {
+    NoTransfer,
+    LengthMismatch,
+    ChecksumMismatch,
+    ConfigWriteFailed,
+}
+
+pub struct Manager {
+    image: Vec<u8>,
+    in_progress: bool,
+}
+
+impl Manager {
+    pub fn new() -> Manager {
+        Manager {
+            image: Vec::new(),
+            in_progress: false,
+        }
+    }
+
+    pub fn add_firmware_data(&mut self, status: PayloadStatus, data: &[u8]) -> Result<(), Error> {
+        if status.is_first() {
+            self.image.clear();
+            self.in_progress = true;
+        } else if !self.in_progress {
+            return Err(Error::NoTransfer);
+        }
+        self.image.extend_from_slice(data);
+        Ok(())
+    }
+
+    pub fn commit_firmware(&mut self, cfg: &Config, length: u32, checksum: u32) -> Result<(), Error> {
+        if !self.in_progress {
+            return Err(Error::NoTransfer);
+        }
+        self.in_progress = false;
+        let image = mem::replace(&mut self.image, Vec::new());
+        if image.len() != length as usize {
+            return Err(Error::LengthMismatch);
+        }
+        if crc32::checksum_ieee(&image) != checksum {
+            return Err(Error::ChecksumMismatch);
+        }
+        info!("writing new firmware ({} bytes)", image.len());
+        cfg.write("boot", image).map_err(|_| Error::ConfigWriteFailed)
+    }
+}
@@ -22,6 +22,7 @@ enum RepeaterState {
     WaitPingReply { ping_count: u16, timeout: Milliseconds },
     Up,
     Failed,
+    Disabled,
 }
 
 #[cfg(has_drtio_routing)]
@@ -31,29 +32,79 @@ impl Default for RepeaterState {
     }
 }
 
+#[cfg(has_drtio_routing)]
+#[derive(Clone, Copy, Default)]
+pub struct RepeaterStats {
+    pub forwarded_packets: u32,
+    pub forward_timeouts: u32,
+    pub rtio_errors: u32,
+    pub tsc_sync_attempts: u32,
+}
+
 #[cfg(has_drtio_routing)]
 #[derive(Clone, Copy, Default)]
 pub struct Repeater {
     repno: u8,
     auxno: u8,
     state: RepeaterState,
+    stats: RepeaterStats,
+    policy: drtio_routing::RetryPolicy,
+    failures: u32,
+    retry_at: u64,
 }
 
 #[cfg(has_drtio_routing)]
 impl Repeater {
-    pub fn new(repno: u8) -> Repeater {
+    pub fn new(repno: u8, policy: drtio_routing::RetryPolicy) -> Repeater {
         Repeater {
             repno: repno,
             auxno: repno + 1,
             state: RepeaterState::Down,
+            stats: RepeaterStats::default(),
+            policy: policy,
+            failures: 0,
+            retry_at: 0,
         }
     }
 
-    #[allow(dead_code)]
     pub fn is_up(&self) -> bool {
         self.state == RepeaterState::Up
     }
 
+    pub fn stats(&self) -> RepeaterStats {
+        self.stats
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if enabled {
+            if self.state == RepeaterState::Disabled || self.state == RepeaterState::Failed {
+                info!("[REP#{}] link enabled", self.repno);
+                self.failures = 0;
+                self.retry_at = 0;
+                self.state = RepeaterState::Down;
+            }
+        } else if self.state != RepeaterState::Disabled {
+            info!("[REP#{}] link administratively disabled", self.repno);
+            self.state = RepeaterState::Disabled;
+        }
+    }
+
+    fn bringup_failed(&mut self, timer: &mut GlobalTimer) {
+        self.failures += 1;
+        if self.policy.exhausted(self.failures) {
+            error!(
+                "[REP#{}] link failed {} times, giving up until re-enabled or RX is lost",
+                self.repno, self.failures
+            );
+            self.state = RepeaterState::Failed;
+        } else {
+            let backoff = self.policy.backoff(self.failures);
+            warn!("[REP#{}] retrying link bring-up in {} ms", self.repno, backoff);
+            self.retry_at = timer.get_time().0 + backoff;
+            self.state = RepeaterState::Down;
+        }
+    }
+
     pub fn service(
         &mut self,
         routing_table: &drtio_routing::RoutingTable,
@@ -66,7 +117,7 @@ impl Repeater {
 
         match self.state {
             RepeaterState::Down => {
-                if rep_link_rx_up(self.repno) {
+                if rep_link_rx_up(self.repno) && timer.get_time().0 >= self.retry_at {
                     info!("[REP#{}] link RX became up, pinging", self.repno);
                     self.state = RepeaterState::SendPing { ping_count: 0 };
                 }
@@ -94,24 +145,25 @@ impl Repeater {
                         self.state = RepeaterState::Up;
                         if let Err(e) = self.sync_tsc(timer) {
                             error!("[REP#{}] failed to sync TSC ({:?})", self.repno, e);
-                            self.state = RepeaterState::Failed;
+                            self.bringup_failed(timer);
                             return;
                         }
                         if let Err(e) = self.load_routing_table(routing_table, timer) {
                             error!("[REP#{}] failed to load routing table ({:?})", self.repno, e);
-                            self.state = RepeaterState::Failed;
+                            self.bringup_failed(timer);
                             return;
                         }
                         if let Err(e) = self.set_rank(rank + 1, timer) {
                             error!("[REP#{}] failed to set rank ({:?})", self.repno, e);
-                            self.state = RepeaterState::Failed;
+                            self.bringup_failed(timer);
                             return;
                         }
+                        self.failures = 0;
                     } else {
                         if timer.get_time() > timeout {
-                            if ping_count > 200 {
+                            if ping_count >= self.policy.ping_count {
                                 error!("[REP#{}] ping failed", self.repno);
-                                self.state = RepeaterState::Failed;
+                                self.bringup_failed(timer);
                             } else {
                                 self.state = RepeaterState::SendPing { ping_count: ping_count };
                             }
@@ -130,11 +182,15 @@ impl Repeater {
                 }
             }
             RepeaterState::Failed => {
+                // a new cable or a rebooted satellite gets a fresh set of retries
                 if !rep_link_rx_up(self.repno) {
                     info!("[REP#{}] link is down", self.repno);
+                    self.failures = 0;
+                    self.retry_at = 0;
                     self.state = RepeaterState::Down;
                 }
             }
+            RepeaterState::Disabled => (),
         }
     }
 
@@ -152,12 +208,13 @@ impl Repeater {
         }
     }
 
-    fn process_local_errors(&self) {
+    fn process_local_errors(&mut self) {
         let repno = self.repno as usize;
         let errors;
         unsafe {
             errors = (csr::DRTIOREP[repno].protocol_error_read)();
         }
+        self.stats.rtio_errors = self.stats.rtio_errors.wrapping_add(errors.count_ones());
         if errors & 1 != 0 {
             error!("[REP#{}] received packet of an unknown type", repno);
         }
@@ -209,7 +266,7 @@ impl Repeater {
     }
 
     pub fn aux_forward(
-        &self,
+        &mut self,
         request: &drtioaux::Packet,
         router: &mut Router,
         routing_table: &drtio_routing::RoutingTable,
@@ -217,9 +274,16 @@ impl Repeater {
         self_destination: u8,
         timer: &mut GlobalTimer,
     ) -> Result<(), drtioaux::Error> {
-        self.aux_send(request)?;
+        self.aux_relay(request)?;
         loop {
-            let reply = self.recv_aux_timeout(200, timer)?;
+            let reply = match self.recv_aux_timeout(request.reply_timeout(), timer) {
+                Ok(reply) => reply,
+                Err(drtioaux::Error::TimedOut) => {
+                    self.stats.forward_timeouts = self.stats.forward_timeouts.wrapping_add(1);
+                    return Err(drtioaux::Error::TimedOut);
+                }
+                Err(e) => return Err(e),
+            };
             match reply {
                 // async/locally requested packets to be consumed or routed
                 // these may come while a packet would be forwarded
@@ -242,17 +306,25 @@ impl Repeater {
         Ok(())
     }
 
-    pub fn aux_send(&self, request: &drtioaux::Packet) -> Result<(), drtioaux::Error> {
+    pub fn aux_send(&mut self, request: &drtioaux::Packet) -> Result<(), drtioaux::Error> {
         if self.state != RepeaterState::Up {
             return Err(drtioaux::Error::LinkDown);
         }
         drtioaux::send(self.auxno, request)
     }
 
-    pub fn sync_tsc(&self, timer: &mut GlobalTimer) -> Result<(), drtioaux::Error> {
+    // Passes on a packet received from the upstream link, counting it as forwarded
+    pub fn aux_relay(&mut self, request: &drtioaux::Packet) -> Result<(), drtioaux::Error> {
+        self.aux_send(request)?;
+        self.stats.forwarded_packets = self.stats.forwarded_packets.wrapping_add(1);
+        Ok(())
+    }
+
+    pub fn sync_tsc(&mut self, timer: &mut GlobalTimer) -> Result<(), drtioaux::Error> {
         if self.state != RepeaterState::Up {
             return Ok(());
         }
+        self.stats.tsc_sync_attempts = self.stats.tsc_sync_attempts.wrapping_add(1);
 
         let repno = self.repno as usize;
         unsafe {
@@ -347,7 +419,7 @@ pub struct Repeater {}
 
 #[cfg(not(has_drtio_routing))]
 impl Repeater {
-    pub fn new(_repno: u8) -> Repeater {
+    pub fn new(_repno: u8, _policy: drtio_routing::RetryPolicy) -> Repeater {
         Repeater::default()
     }
 
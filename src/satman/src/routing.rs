@@ -76,6 +76,12 @@ pub struct Router {
     downstream_queue: VecDeque<(usize, drtioaux::Packet)>,
 }
 
+// Repeater leading to the destination, if it is within this satellite's subtree.
+#[cfg(has_drtio_routing)]
+fn downstream_repno(routing_table: &drtio_routing::RoutingTable, rank: u8, destination: u8) -> Option<usize> {
+    drtio_hops::downstream_repno(&routing_table.0[destination as usize], rank, csr::DRTIOREP.len())
+}
+
 impl Router {
     pub fn new() -> Router {
         Router {
@@ -87,7 +93,9 @@ impl Router {
     }
 
     // Called by local sources (DDMA, kernel) and by repeaters on receiving async data;
-    // messages are always buffered for both upstream and downstream
+    // messages are always buffered for both upstream and downstream.
+    // Packets between satellites of the same subtree (e.g. subkernel messages) are passed
+    // down directly, only those for destinations outside of it go through the upstream.
     pub fn route(
         &mut self,
         packet: drtioaux::Packet,
@@ -99,11 +107,9 @@ impl Router {
         #[cfg(has_drtio_routing)]
         {
             if let Some(destination) = destination {
-                let hop = _routing_table.0[destination as usize][_rank as usize] as usize;
                 if destination == self_destination {
                     self.local_queue.push_back(packet);
-                } else if hop > 0 && hop < csr::DRTIOREP.len() {
-                    let repno = (hop - 1) as usize;
+                } else if let Some(repno) = downstream_repno(_routing_table, _rank, destination) {
                     self.downstream_queue.push_back((repno, packet));
                 } else {
                     self.upstream_queue.push_back(packet);
@@ -134,17 +140,15 @@ impl Router {
         {
             let destination = packet.routable_destination();
             if let Some(destination) = destination {
-                let hop = _routing_table.0[destination as usize][_rank as usize] as usize;
                 if destination == 0 {
                     // response is needed immediately if master required it
                     drtioaux::send(0, &packet)?;
-                } else if !(hop > 0 && hop < csr::DRTIOREP.len()) {
-                    // higher rank can wait
-                    self.upstream_queue.push_back(packet);
-                } else {
-                    let repno = (hop - 1) as usize;
+                } else if let Some(repno) = downstream_repno(_routing_table, _rank, destination) {
                     // transaction will occur at closest possible opportunity
                     self.downstream_queue.push_back((repno, packet));
+                } else {
+                    // higher rank can wait
+                    self.upstream_queue.push_back(packet);
                 }
                 Ok(())
             } else {
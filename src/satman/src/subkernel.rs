@@ -1,10 +1,12 @@
-use alloc::{collections::BTreeMap,
+use alloc::{collections::{BTreeMap, VecDeque},
             format,
+            rc::Rc,
             string::{String, ToString},
             vec::Vec};
 use core::{option::NoneError, slice, str};
 
 use core_io::{Error as IoError, Write};
+use crc::crc32;
 use cslice::AsCSlice;
 use dma::{Error as DmaError, Manager as DmaManager};
 use io::{Cursor, ProtoWrite};
@@ -28,7 +30,9 @@ enum KernelState {
         id: u32,
         tags: Vec<u8>,
     },
-    MsgSending,
+    MsgSending {
+        destination: u8,
+    },
     SubkernelAwaitLoad,
     SubkernelAwaitFinish {
         max_time: Option<Milliseconds>,
@@ -47,6 +51,11 @@ enum KernelState {
     DmaAwait {
         max_time: Milliseconds,
     },
+    DmaPatchAwait {
+        max_time: Milliseconds,
+        // traces still being sent to the destinations
+        uploading: bool,
+    },
     SubkernelRetrievingException {
         destination: u8,
     },
@@ -106,92 +115,36 @@ struct Message {
     data: Vec<u8>,
 }
 
-#[derive(PartialEq)]
-enum OutMessageState {
-    NoMessage,
-    MessageBeingSent,
-    MessageSent,
-    MessageAcknowledged,
+// slices sent ahead of their acks, each takes one aux buffer on the receiver
+const STREAM_WINDOW: usize = 4;
+// unacknowledged messages to one destination before try-sends report a full queue
+const STREAM_QUEUE_LENGTH: usize = 8;
+
+/* outgoing messages to one destination */
+#[derive(Default)]
+struct Stream {
+    queue: VecDeque<Sliceable>,
+    in_flight: VecDeque<bool>, // whether each slice sent is the last of its message
+    messages: usize,           // messages not fully acknowledged yet
 }
 
 /* for dealing with incoming and outgoing interkernel messages */
 struct MessageManager {
-    out_message: Option<Sliceable>,
-    out_state: OutMessageState,
     in_queue: Vec<Message>,
     in_buffer: Option<Message>,
-}
-
-// Per-run state
-struct Session {
-    id: u32,
-    kernel_state: KernelState,
-    last_exception: Option<Sliceable>,   // exceptions raised locally
-    external_exception: Option<Vec<u8>>, // exceptions from sub-subkernels
-    messages: MessageManager,
-    source: u8, // which destination requested running the kernel
-    subkernels_finished: Vec<(u32, Option<u8>)>,
-}
-
-impl Session {
-    pub fn new(id: u32) -> Session {
-        Session {
-            id: id,
-            kernel_state: KernelState::Absent,
-            last_exception: None,
-            external_exception: None,
-            messages: MessageManager::new(),
-            source: 0,
-            subkernels_finished: Vec::new(),
-        }
-    }
-
-    fn running(&self) -> bool {
-        match self.kernel_state {
-            KernelState::Absent | KernelState::Loaded => false,
-            _ => true,
-        }
-    }
-}
-
-#[derive(Debug)]
-struct KernelLibrary {
-    library: Vec<u8>,
-    complete: bool,
-}
-
-pub struct Manager<'a> {
-    kernels: BTreeMap<u32, KernelLibrary>,
-    session: Session,
-    control: &'a mut kernel::Control,
-    cache: BTreeMap<String, Vec<i32>>,
-    last_finished: Option<SubkernelFinished>,
-}
-
-pub struct SubkernelFinished {
-    pub id: u32,
-    pub with_exception: bool,
-    pub exception_source: u8,
-    pub source: u8,
+    streams: BTreeMap<u8, Stream>,
 }
 
 impl MessageManager {
     pub fn new() -> MessageManager {
         MessageManager {
-            out_message: None,
-            out_state: OutMessageState::NoMessage,
             in_queue: Vec::new(),
             in_buffer: None,
+            streams: BTreeMap::new(),
         }
     }
 
-    pub fn handle_incoming(
-        &mut self,
-        status: PayloadStatus,
-        id: u32,
-        length: usize,
-        data: &[u8; MASTER_PAYLOAD_MAX_SIZE],
-    ) {
+    pub fn handle_incoming(&mut self, status: PayloadStatus, id: u32, length: usize, data: &[u8; MASTER_PAYLOAD_MAX_SIZE]) {
         // called when receiving a message from master
         if status.is_first() {
             self.in_buffer = None;
@@ -212,76 +165,55 @@ impl MessageManager {
         }
     }
 
-    pub fn was_message_acknowledged(&mut self) -> bool {
-        match self.out_state {
-            OutMessageState::MessageAcknowledged => {
-                self.out_state = OutMessageState::NoMessage;
-                true
-            }
-            _ => false,
-        }
+    pub fn pending(&self, destination: u8) -> usize {
+        self.streams.get(&destination).map_or(0, |stream| stream.messages)
     }
 
-    pub fn get_outgoing_slice(&mut self, data_slice: &mut [u8; MASTER_PAYLOAD_MAX_SIZE]) -> Option<SliceMeta> {
-        if self.out_state != OutMessageState::MessageBeingSent {
+    pub fn queue_full(&self, destination: u8) -> bool {
+        self.pending(destination) >= STREAM_QUEUE_LENGTH
+    }
+
+    pub fn accept_outgoing(&mut self, destination: u8, message: Vec<u8>) {
+        let stream = self.streams.entry(destination).or_default();
+        stream.queue.push_back(Sliceable::new(destination, message));
+        stream.messages += 1;
+    }
+
+    pub fn get_outgoing_slice(
+        &mut self,
+        destination: u8,
+        data_slice: &mut [u8; MASTER_PAYLOAD_MAX_SIZE],
+    ) -> Option<SliceMeta> {
+        // next slice to send, if the window to the destination allows it
+        let stream = self.streams.get_mut(&destination)?;
+        if stream.in_flight.len() >= STREAM_WINDOW {
             return None;
         }
-        let meta = self.out_message.as_mut()?.get_slice_master(data_slice);
+        let meta = stream.queue.front_mut()?.get_slice_master(data_slice);
         if meta.status.is_last() {
-            // clear the message slot
-            self.out_message = None;
-            // notify kernel with a flag that message is sent
-            self.out_state = OutMessageState::MessageSent;
+            stream.queue.pop_front();
         }
+        stream.in_flight.push_back(meta.status.is_last());
         Some(meta)
     }
 
-    pub fn ack_slice(&mut self) -> bool {
-        // returns whether or not there's more to be sent
-        match self.out_state {
-            OutMessageState::MessageBeingSent => true,
-            OutMessageState::MessageSent => {
-                self.out_state = OutMessageState::MessageAcknowledged;
-                false
-            }
-            _ => {
-                warn!("received unsolicited SubkernelMessageAck");
-                false
+    pub fn ack_slice(&mut self, source: u8) -> bool {
+        // returns false if no slice to the source was waiting for an ack
+        let stream = match self.streams.get_mut(&source) {
+            Some(stream) => stream,
+            None => return false,
+        };
+        match stream.in_flight.pop_front() {
+            Some(last) => {
+                if last {
+                    stream.messages -= 1;
+                }
+                true
             }
+            None => false,
         }
     }
 
-    pub fn accept_outgoing(
-        &mut self,
-        id: u32,
-        self_destination: u8,
-        destination: u8,
-        message: Vec<u8>,
-        routing_table: &RoutingTable,
-        rank: u8,
-        router: &mut Router,
-    ) -> Result<(), Error> {
-        self.out_message = Some(Sliceable::new(destination, message));
-
-        let mut data_slice: [u8; MASTER_PAYLOAD_MAX_SIZE] = [0; MASTER_PAYLOAD_MAX_SIZE];
-        self.out_state = OutMessageState::MessageBeingSent;
-        let meta = self.get_outgoing_slice(&mut data_slice).unwrap();
-        router.route(
-            drtioaux::Packet::SubkernelMessage {
-                source: self_destination,
-                destination: destination,
-                id: id,
-                status: meta.status,
-                length: meta.len as u16,
-                data: data_slice,
-            },
-            routing_table,
-            rank,
-            self_destination,
-        );
-        Ok(())
-    }
-
     pub fn get_incoming(&mut self, id: u32) -> Option<Message> {
         for i in 0..self.in_queue.len() {
             if self.in_queue[i].id == id {
@@ -312,8 +244,9 @@ impl<'a> Manager<'_> {
                     self.kernels.insert(
                         id,
                         KernelLibrary {
-                            library: Vec::new(),
+                            library: Rc::new(Vec::new()),
                             complete: false,
+                            hash: 0,
                         },
                     );
                     self.kernels.get_mut(&id)?
@@ -325,28 +258,53 @@ impl<'a> Manager<'_> {
                 self.kernels.insert(
                     id,
                     KernelLibrary {
-                        library: Vec::new(),
+                        library: Rc::new(Vec::new()),
                         complete: false,
+                        hash: 0,
                     },
                 );
                 self.kernels.get_mut(&id)?
             }
         };
-        kernel.library.extend(&data[0..data_len]);
+        // only complete libraries are shared, so this does not copy
+        Rc::make_mut(&mut kernel.library).extend(&data[0..data_len]);
 
         kernel.complete = status.is_last();
+        if kernel.complete {
+            kernel.hash = crc32::checksum_ieee(&kernel.library);
+        }
         Ok(())
     }
 
-    pub fn running(&self) -> bool {
-        self.session.running()
+    pub fn cached(&mut self, id: u32, hash: u32, length: usize, bind: bool) -> bool {
+        let matches = |kernel: &KernelLibrary| kernel.complete && kernel.hash == hash && kernel.library.len() == length;
+        if self.kernels.get(&id).map_or(false, matches) {
+            return true;
+        }
+        let source_id = match self.kernels.iter().find(|(_, kernel)| matches(*kernel)) {
+            Some((source_id, _)) => *source_id,
+            None => return false,
+        };
+        if bind {
+            // a stale library may still be loaded under that id
+            if self.session.id == id && self.session.kernel_state == KernelState::Loaded {
+                self.session.kernel_state = KernelState::Absent;
+            }
+            let library = Rc::clone(&self.kernels.get(&source_id).unwrap().library);
+            self.kernels.insert(
+                id,
+                KernelLibrary {
+                    library: library,
+                    complete: true,
+                    hash: hash,
+                },
+            );
+        }
+        true
     }
 
-    pub fn get_current_id(&self) -> Option<u32> {
-        match self.running() {
-            true => Some(self.session.id),
-            false => None,
-        }
+    pub fn running(&self) -> bool {
+        self.session.running()
     }
 
     pub fn run(&mut self, source: u8, id: u32, timestamp: u64) -> Result<(), Error> {
@@ -364,6 +322,16 @@ impl<'a> Manager<'_> {
         Ok(())
     }
 
+    pub fn stop(&mut self, id: u32) -> Result<(), Error> {
+        if !self.running() || self.session.id != id {
+            return Err(Error::KernelNotFound);
+        }
+        // the master marks the subkernel as cancelled, no SubkernelFinished is sent
+        self.control.restart();
+        self.kernel_stop();
+        Ok(())
+    }
+
     pub fn message_handle_incoming(
         &mut self,
         status: PayloadStatus,
@@ -377,19 +345,46 @@ impl<'a> Manager<'_> {
         self.session.messages.handle_incoming(status, id, length, slice);
     }
 
-    pub fn message_get_slice(&mut self, slice: &mut [u8; MASTER_PAYLOAD_MAX_SIZE]) -> Option<SliceMeta> {
-        if !self.running() {
-            return None;
+    pub fn message_ack_slice(
+        &mut self,
+        source: u8,
+        router: &mut Router,
+        routing_table: &RoutingTable,
+        rank: u8,
+        self_destination: u8,
+    ) {
+        if !self.running() || !self.session.messages.ack_slice(source) {
+            warn!("received unsolicited SubkernelMessageAck from {}", source);
+            return;
         }
-        self.session.messages.get_outgoing_slice(slice)
+        self.message_send_slices(source, router, routing_table, rank, self_destination);
     }
 
-    pub fn message_ack_slice(&mut self) -> bool {
-        if !self.running() {
-            warn!("received unsolicited SubkernelMessageAck");
-            return false;
+    fn message_send_slices(
+        &mut self,
+        destination: u8,
+        router: &mut Router,
+        routing_table: &RoutingTable,
+        rank: u8,
+        self_destination: u8,
+    ) {
+        let mut data_slice: [u8; MASTER_PAYLOAD_MAX_SIZE] = [0; MASTER_PAYLOAD_MAX_SIZE];
+        while let Some(meta) = self.session.messages.get_outgoing_slice(destination, &mut data_slice) {
+            // route and not send immediately as ACKs are not a beginning of a transaction
+            router.route(
+                drtioaux::Packet::SubkernelMessage {
+                    source: self_destination,
+                    destination: destination,
+                    id: self.session.id,
+                    status: meta.status,
+                    length: meta.len as u16,
+                    data: data_slice,
+                },
+                routing_table,
+                rank,
+                self_destination,
+            );
         }
-        self.session.messages.ack_slice()
     }
 
     pub fn load(&mut self, id: u32) -> Result<(), Error> {
@@ -404,7 +399,7 @@ impl<'a> Manager<'_> {
 
         self.control
             .tx
-            .send(kernel::Message::LoadRequest(self.kernels.get(&id)?.library.clone()));
+            .send(kernel::Message::LoadRequest(self.kernels.get(&id)?.library.to_vec()));
         let reply = self.control.rx.recv();
         match reply {
             kernel::Message::LoadCompleted => Ok(()),
@@ -487,6 +482,17 @@ impl<'a> Manager<'_> {
         }
     }
 
+    pub fn ddma_patched(&mut self, succeeded: bool) {
+        if let KernelState::DmaPatchAwait { uploading, .. } = self.session.kernel_state {
+            self.control.tx.send(kernel::Message::DmaPatchRemoteReply(succeeded));
+            self.session.kernel_state = if uploading {
+                KernelState::DmaUploading
+            } else {
+                KernelState::Running
+            };
+        }
+    }
+
     pub fn ddma_remote_uploaded(&mut self, succeeded: bool) -> Option<(u32, u64)> {
         // returns a tuple of id, timestamp in case a playback needs to be started immediately
         if !succeeded {
@@ -510,6 +516,13 @@ impl<'a> Manager<'_> {
                 self.session.kernel_state = KernelState::Running;
                 None
             }
+            KernelState::DmaPatchAwait { max_time, .. } => {
+                self.session.kernel_state = KernelState::DmaPatchAwait {
+                    max_time: max_time,
+                    uploading: false,
+                };
+                None
+            }
             _ => None,
         };
         res
@@ -736,6 +749,7 @@ impl<'a> Manager<'_> {
             kernel::Message::DmaPutRequest(recorder) => {
                 // ddma is always used on satellites
                 if let Ok(id) = dma_manager.put_record(recorder, self_destination) {
+                    self.control.tx.send(kernel::Message::DmaPutReply(true));
                     dma_manager.upload_traces(id, router, rank, self_destination, routing_table)?;
                     self.session.kernel_state = KernelState::DmaUploading;
                 } else {
@@ -766,6 +780,46 @@ impl<'a> Manager<'_> {
                     };
                 }
             }
+            kernel::Message::DmaPatchRemoteRequest {
+                id,
+                destination,
+                target,
+                timestamp,
+                word,
+                value,
+            } => {
+                let succeeded = dma_manager.patch_remote(
+                    id as u32,
+                    destination,
+                    target,
+                    timestamp,
+                    word,
+                    value,
+                    router,
+                    rank,
+                    self_destination,
+                    routing_table,
+                );
+                if succeeded {
+                    // reply once the destination has patched the trace
+                    self.session.kernel_state = KernelState::DmaPatchAwait {
+                        max_time: timer.get_time() + Milliseconds(10000),
+                        uploading: self.session.kernel_state == KernelState::DmaUploading,
+                    };
+                } else {
+                    self.control.tx.send(kernel::Message::DmaPatchRemoteReply(false));
+                }
+            }
+            kernel::Message::DmaRevertRemoteRequest { id, destination } => {
+                dma_manager.revert_remote_patches(
+                    id as u32,
+                    destination,
+                    router,
+                    rank,
+                    self_destination,
+                    routing_table,
+                );
+            }
             kernel::Message::DmaAwaitRemoteRequest(_id) => {
                 let max_time = timer.get_time() + Milliseconds(10000);
                 self.session.kernel_state = match self.session.kernel_state {
@@ -785,16 +839,36 @@ impl<'a> Manager<'_> {
                 data,
             } => {
                 let msg_dest = msg_dest.or(Some(self.session.source)).unwrap();
-                self.session.messages.accept_outgoing(
-                    self.session.id,
-                    self_destination,
-                    msg_dest,
-                    data,
-                    routing_table,
-                    rank,
-                    router,
-                )?;
-                self.session.kernel_state = KernelState::MsgSending;
+                self.session.messages.accept_outgoing(msg_dest, data);
+                self.message_send_slices(msg_dest, router, routing_table, rank, self_destination);
+                // replied once every message to the destination is acknowledged
+                self.session.kernel_state = KernelState::MsgSending { destination: msg_dest };
+            }
+            kernel::Message::SubkernelMsgTrySend {
+                id: _id,
+                destination: msg_dest,
+                data,
+            } => {
+                let msg_dest = msg_dest.or(Some(self.session.source)).unwrap();
+                if self.session.messages.queue_full(msg_dest) {
+                    self.control.tx.send(kernel::Message::SubkernelMsgQueueFull);
+                } else {
+                    self.session.messages.accept_outgoing(msg_dest, data);
+                    self.message_send_slices(msg_dest, router, routing_table, rank, self_destination);
+                    self.control.tx.send(kernel::Message::SubkernelMsgSent);
+                }
+            }
+            kernel::Message::SubkernelMsgTryRecvRequest { id, tags } => {
+                let id = if id == -1 { self.session.id } else { id as u32 };
+                match self.session.messages.get_incoming(id) {
+                    Some(message) => {
+                        self.control
+                            .tx
+                            .send(kernel::Message::SubkernelMsgRecvReply { count: message.count });
+                        self.pass_message_to_kernel(&message, tags, timer)?;
+                    }
+                    None => self.control.tx.send(kernel::Message::SubkernelMsgRecvEmpty),
+                }
             }
             kernel::Message::SubkernelMsgRecvRequest { id, timeout, tags } => {
                 let id = if id == -1 { self.session.id } else { id as u32 };
@@ -889,8 +963,8 @@ impl<'a> Manager<'_> {
                     Err(Error::AwaitingMessage)
                 }
             }
-            KernelState::MsgSending => {
-                if self.session.messages.was_message_acknowledged() {
+            KernelState::MsgSending { destination } => {
+                if self.session.messages.pending(*destination) == 0 {
                     self.session.kernel_state = KernelState::Running;
                     self.control.tx.send(kernel::Message::SubkernelMsgSent);
                     Ok(())
@@ -925,6 +999,17 @@ impl<'a> Manager<'_> {
                 }
                 Ok(())
             }
+            KernelState::DmaPatchAwait { max_time, uploading } => {
+                if timer.get_time() > *max_time {
+                    self.control.tx.send(kernel::Message::DmaPatchRemoteReply(false));
+                    self.session.kernel_state = if *uploading {
+                        KernelState::DmaUploading
+                    } else {
+                        KernelState::Running
+                    };
+                }
+                Ok(())
+            }
             _ => Ok(()),
         }
     }